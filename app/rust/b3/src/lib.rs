@@ -19,13 +19,13 @@
 //! The `B3Propagator` facilitates `SpanContext` propagation using
 //! B3 Headers. This propagator supports both version of B3 headers,
 //!  1. Single Header:
-//!    b3: {trace_id}-{span_id}-{sampling_state}-{parent_span_id}
+//!     b3: {trace_id}-{span_id}-{sampling_state}-{parent_span_id}
 //!  2. Multiple Headers:
-//!    X-B3-TraceId: {trace_id}
-//!    X-B3-ParentSpanId: {parent_span_id}
-//!    X-B3-SpanId: {span_id}
-//!    X-B3-Sampled: {sampling_state}
-//!    X-B3-Flags: {debug_flag}
+//!     X-B3-TraceId: {trace_id}
+//!     X-B3-ParentSpanId: {parent_span_id}
+//!     X-B3-SpanId: {span_id}
+//!     X-B3-Sampled: {sampling_state}
+//!     X-B3-Flags: {debug_flag}
 //!
 //! If `inject_encoding` is set to `B3Encoding::SingleHeader` then `b3` header is used to inject
 //! and extract. Otherwise, separate headers are used to inject and extract.
@@ -323,6 +323,72 @@ impl TextMapPropagator for Propagator {
     }
 }
 
+// Tonic grpc etadata adapters from opentelemetry grpc tracing examples at
+// https://github.com/open-telemetry/opentelemetry-rust/tree/main/examples/tracing-grpc
+pub struct ExMetadataMap<'a>(pub &'a tonic::metadata::MetadataMap);
+pub struct InMetadataMap<'a>(pub &'a mut tonic::metadata::MetadataMap);
+
+impl<'a> Extractor for ExMetadataMap<'a> {
+    /// Get a value for a key from the MetadataMap.  If the value can't be converted to &str, returns None
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|metadata| metadata.to_str().ok())
+    }
+
+    /// Collect all the keys from the MetadataMap.
+    fn keys(&self) -> Vec<&str> {
+        self.0
+            .keys()
+            .map(|key| match key {
+                tonic::metadata::KeyRef::Ascii(v) => v.as_str(),
+                tonic::metadata::KeyRef::Binary(v) => v.as_str(),
+            })
+            .collect::<Vec<_>>()
+    }
+}
+
+impl<'a> Injector for InMetadataMap<'a> {
+    /// Set a key and value in the MetadataMap.  Does nothing if the key or value are not valid inputs
+    fn set(&mut self, key: &str, value: String) {
+        if let Ok(key) = tonic::metadata::MetadataKey::from_bytes(key.as_bytes()) {
+            if let Ok(val) = tonic::metadata::MetadataValue::from_str(&value) {
+                self.0.insert(key, val);
+            }
+        }
+    }
+}
+
+// Modified from the opentelemetry code for http HeaderMaps to work with rocket HeaderMaps
+pub struct HeaderExtractor<'a>(pub &'a HeaderMap<'a>);
+
+impl<'a> Extractor for HeaderExtractor<'a> {
+    /// Get a value for a key from the HeaderMap.  If the value is not valid ASCII, returns None.
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get_one(key)
+    }
+
+    /// Collect all the keys from the HeaderMap.
+    fn keys(&self) -> Vec<&str> {
+        // TODO: fix move/borrow issue
+        // self.0
+        //     .iter()
+        //     .map(|header| header.name().as_str())
+        //     .collect::<Vec<_>>();
+        vec!["b3"]
+    }
+}
+
+// Rocket Header handling for trace propagation
+pub struct RocketHttpHeaderMap<'a>(pub &'a HeaderMap<'a>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for RocketHttpHeaderMap<'r> {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, ()> {
+        Outcome::Success(RocketHttpHeaderMap(request.headers()))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -705,69 +771,3 @@ mod tests {
         );
     }
 }
-
-// Tonic grpc etadata adapters from opentelemetry grpc tracing examples at
-// https://github.com/open-telemetry/opentelemetry-rust/tree/main/examples/tracing-grpc
-pub struct ExMetadataMap<'a>(pub &'a tonic::metadata::MetadataMap);
-pub struct InMetadataMap<'a>(pub &'a mut tonic::metadata::MetadataMap);
-
-impl<'a> Extractor for ExMetadataMap<'a> {
-    /// Get a value for a key from the MetadataMap.  If the value can't be converted to &str, returns None
-    fn get(&self, key: &str) -> Option<&str> {
-        self.0.get(key).and_then(|metadata| metadata.to_str().ok())
-    }
-
-    /// Collect all the keys from the MetadataMap.
-    fn keys(&self) -> Vec<&str> {
-        self.0
-            .keys()
-            .map(|key| match key {
-                tonic::metadata::KeyRef::Ascii(v) => v.as_str(),
-                tonic::metadata::KeyRef::Binary(v) => v.as_str(),
-            })
-            .collect::<Vec<_>>()
-    }
-}
-
-impl<'a> Injector for InMetadataMap<'a> {
-    /// Set a key and value in the MetadataMap.  Does nothing if the key or value are not valid inputs
-    fn set(&mut self, key: &str, value: String) {
-        if let Ok(key) = tonic::metadata::MetadataKey::from_bytes(key.as_bytes()) {
-            if let Ok(val) = tonic::metadata::MetadataValue::from_str(&value) {
-                self.0.insert(key, val);
-            }
-        }
-    }
-}
-
-// Modified from the opentelemetry code for http HeaderMaps to work with rocket HeaderMaps
-pub struct HeaderExtractor<'a>(pub &'a HeaderMap<'a>);
-
-impl<'a> Extractor for HeaderExtractor<'a> {
-    /// Get a value for a key from the HeaderMap.  If the value is not valid ASCII, returns None.
-    fn get(&self, key: &str) -> Option<&str> {
-        self.0.get_one(key)
-    }
-
-    /// Collect all the keys from the HeaderMap.
-    fn keys(&self) -> Vec<&str> {
-        // TODO: fix move/borrow issue
-        // self.0
-        //     .iter()
-        //     .map(|header| header.name().as_str())
-        //     .collect::<Vec<_>>();
-        vec!["b3"]
-    }
-}
-
-// Rocket Header handling for trace propagation
-pub struct RocketHttpHeaderMap<'a>(pub &'a HeaderMap<'a>);
-
-#[rocket::async_trait]
-impl<'r> FromRequest<'r> for RocketHttpHeaderMap<'r> {
-    type Error = ();
-
-    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, ()> {
-        Outcome::Success(RocketHttpHeaderMap(request.headers()))
-    }
-}
@@ -1,4 +1,11 @@
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    tonic_build::compile_protos("../dill/proto/dill.proto")?;
+    tonic_build::configure().compile(
+        &[
+            "proto/dill.proto",
+            "proto/google/rpc/status.proto",
+            "proto/google/rpc/error_details.proto",
+        ],
+        &["proto"],
+    )?;
     Ok(())
 }
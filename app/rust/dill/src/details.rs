@@ -0,0 +1,259 @@
+//
+// Helpers for the google.rpc error model. A grpc Status can carry a
+// google.rpc.Status in its details, which in turn holds a list of typed
+// detail messages: ErrorInfo for the cause of an error, BadRequest for the
+// request fields that were rejected, and RetryInfo for when to try again.
+//
+
+use crate::google::rpc::{self, bad_request::FieldViolation, BadRequest, ErrorInfo, RetryInfo};
+use prost::Message;
+use prost_types::Any;
+use std::{error::Error, io, time::Duration};
+use tonic::{Code, Status};
+
+const TYPE_URL_PREFIX: &str = "type.googleapis.com/";
+const ERROR_INFO: &str = "google.rpc.ErrorInfo";
+const BAD_REQUEST: &str = "google.rpc.BadRequest";
+const RETRY_INFO: &str = "google.rpc.RetryInfo";
+
+// How tonic::transport::Error displays, the message of a Status made from one
+const TRANSPORT_ERROR: &str = "transport error";
+
+/// A detail message from the google.rpc error model
+#[derive(Clone, Debug, PartialEq)]
+pub enum ErrorDetail {
+    ErrorInfo(ErrorInfo),
+    BadRequest(BadRequest),
+    RetryInfo(RetryInfo),
+}
+
+/// Creates a Status carrying the error detail messages
+///
+/// # Arguments
+///
+/// * `code` - grpc status code
+/// * `message` - Developer-facing error message
+/// * `details` - Detail messages describing the error
+pub fn with_details(code: Code, message: impl Into<String>, details: Vec<ErrorDetail>) -> Status {
+    let message = message.into();
+    let status = rpc::Status {
+        code: code as i32,
+        message: message.clone(),
+        details: details.iter().map(ErrorDetail::to_any).collect(),
+    };
+    Status::with_details(code, message, status.encode_to_vec().into())
+}
+
+/// Returns the error detail messages carried by a Status. Details of other
+/// types, or that fail to decode, are skipped.
+pub fn error_details(status: &Status) -> Vec<ErrorDetail> {
+    if status.details().is_empty() {
+        return Vec::new();
+    }
+    match rpc::Status::decode(status.details()) {
+        Ok(rpc_status) => rpc_status
+            .details
+            .iter()
+            .filter_map(ErrorDetail::from_any)
+            .collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// Converts a Status to the google.rpc.Status it carries, for returning as
+/// part of a message. A Status without one is converted from its code and
+/// message.
+pub fn to_rpc_status(status: &Status) -> rpc::Status {
+    match rpc::Status::decode(status.details()) {
+        Ok(rpc_status) if !status.details().is_empty() => rpc_status,
+        _ => rpc::Status {
+            code: status.code() as i32,
+            message: status.message().to_string(),
+            details: Vec::new(),
+        },
+    }
+}
+
+/// Converts a google.rpc.Status returned as part of a message back to a
+/// Status carrying its details
+pub fn from_rpc_status(rpc_status: &rpc::Status) -> Status {
+    Status::with_details(
+        Code::from_i32(rpc_status.code),
+        rpc_status.message.clone(),
+        rpc_status.encode_to_vec().into(),
+    )
+}
+
+/// Whether a Status is tonic's report of a failure to reach the service,
+/// such as a refused connection, rather than a status the service returned.
+/// A status from the service may carry details; one made by tonic from a
+/// transport or io error doesn't. tonic 0.5 keeps the error as the Status's
+/// source only when it can map it to a code, so a Status made from a
+/// tonic::transport::Error it couldn't map is known by that error's message.
+pub fn is_transport_error(status: &Status) -> bool {
+    if !status.details().is_empty() {
+        return false;
+    }
+    let mut source = status.source();
+    while let Some(e) = source {
+        if e.is::<tonic::transport::Error>() || e.is::<io::Error>() {
+            return true;
+        }
+        source = e.source();
+    }
+    status.code() == Code::Unknown && status.message() == TRANSPORT_ERROR
+}
+
+/// ErrorInfo naming the cause of an error
+///
+/// # Arguments
+///
+/// * `reason` - UPPER_SNAKE_CASE cause, unique within the domain
+/// * `domain` - Service the reason belongs to, e.g. `dill.SignWords`
+/// * `metadata` - Additional key/value details
+pub fn error_info(reason: &str, domain: &str, metadata: &[(&str, &str)]) -> ErrorDetail {
+    ErrorDetail::ErrorInfo(ErrorInfo {
+        reason: reason.to_string(),
+        domain: domain.to_string(),
+        metadata: metadata
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect(),
+    })
+}
+
+/// BadRequest listing rejected request fields as (field, description) pairs
+pub fn bad_request(violations: &[(&str, &str)]) -> ErrorDetail {
+    ErrorDetail::BadRequest(BadRequest {
+        field_violations: violations
+            .iter()
+            .map(|(field, description)| FieldViolation {
+                field: field.to_string(),
+                description: description.to_string(),
+            })
+            .collect(),
+    })
+}
+
+/// RetryInfo asking the caller to wait before retrying
+pub fn retry_after(delay: Duration) -> ErrorDetail {
+    ErrorDetail::RetryInfo(RetryInfo {
+        retry_delay: Some(prost_types::Duration {
+            seconds: delay.as_secs() as i64,
+            nanos: delay.subsec_nanos() as i32,
+        }),
+    })
+}
+
+impl ErrorDetail {
+    fn to_any(&self) -> Any {
+        let (name, value) = match self {
+            ErrorDetail::ErrorInfo(m) => (ERROR_INFO, m.encode_to_vec()),
+            ErrorDetail::BadRequest(m) => (BAD_REQUEST, m.encode_to_vec()),
+            ErrorDetail::RetryInfo(m) => (RETRY_INFO, m.encode_to_vec()),
+        };
+        Any {
+            type_url: format!("{}{}", TYPE_URL_PREFIX, name),
+            value,
+        }
+    }
+
+    fn from_any(any: &Any) -> Option<ErrorDetail> {
+        let name = any.type_url.rsplit('/').next()?;
+        let value = any.value.as_slice();
+        match name {
+            ERROR_INFO => ErrorInfo::decode(value).ok().map(ErrorDetail::ErrorInfo),
+            BAD_REQUEST => BadRequest::decode(value).ok().map(ErrorDetail::BadRequest),
+            RETRY_INFO => RetryInfo::decode(value).ok().map(ErrorDetail::RetryInfo),
+            _ => None,
+        }
+    }
+}
+
+impl RetryInfo {
+    /// The retry delay as a std Duration, if one was given
+    pub fn delay(&self) -> Option<Duration> {
+        let d = self.retry_delay.as_ref()?;
+        if d.seconds < 0 || d.nanos < 0 {
+            return None;
+        }
+        Some(Duration::new(d.seconds as u64, d.nanos as u32))
+    }
+}
+
+// Unit tests
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn details_round_trip() {
+        let details = vec![
+            error_info("SIGNING_FAILED", "dill.SignWords", &[("kid", "k1")]),
+            bad_request(&[("words", "must not be empty")]),
+            retry_after(Duration::from_millis(1500)),
+        ];
+        let status = with_details(Code::InvalidArgument, "bad words", details.clone());
+        assert_eq!(status.code(), Code::InvalidArgument);
+        assert_eq!(status.message(), "bad words");
+        assert_eq!(error_details(&status), details);
+    }
+
+    #[test]
+    fn retry_delay() {
+        match retry_after(Duration::from_millis(1500)) {
+            ErrorDetail::RetryInfo(info) => {
+                assert_eq!(info.delay(), Some(Duration::from_millis(1500)))
+            }
+            d => panic!("expected RetryInfo, got {:?}", d),
+        }
+    }
+
+    #[test]
+    fn rpc_status_round_trip() {
+        let details = vec![error_info("TOO_MANY_WORDS", "dill.SignWords", &[])];
+        let status = with_details(Code::InvalidArgument, "too many", details.clone());
+        let rpc_status = to_rpc_status(&status);
+        assert_eq!(rpc_status.code, Code::InvalidArgument as i32);
+        let status = from_rpc_status(&rpc_status);
+        assert_eq!(status.code(), Code::InvalidArgument);
+        assert_eq!(status.message(), "too many");
+        assert_eq!(error_details(&status), details);
+
+        let plain = to_rpc_status(&Status::unavailable("down"));
+        assert_eq!(plain.code, Code::Unavailable as i32);
+        assert!(plain.details.is_empty());
+    }
+
+    #[tokio::test]
+    async fn transport_error_from_refused_connection() {
+        // Nothing listens on port 1
+        let channel = tonic::transport::Endpoint::from_static("http://127.0.0.1:1")
+            .connect_lazy()
+            .unwrap();
+        let mut client = crate::dill::sign_words_client::SignWordsClient::new(channel);
+        let e = client
+            .sign_words(crate::dill::SignRequest::default())
+            .await
+            .unwrap_err();
+        assert!(is_transport_error(&e));
+
+        assert!(!is_transport_error(&Status::unknown("something else")));
+        assert!(!is_transport_error(&Status::unavailable("down")));
+        let returned = with_details(
+            Code::Unknown,
+            TRANSPORT_ERROR,
+            vec![error_info("SIGNING_FAILED", "dill.SignWords", &[])],
+        );
+        assert!(!is_transport_error(&returned));
+    }
+
+    #[test]
+    fn no_details() {
+        assert!(error_details(&Status::internal("plain")).is_empty());
+        let garbage = Status::with_details(Code::Internal, "garbage", vec![0xff, 0xff].into());
+        assert!(error_details(&garbage).is_empty());
+    }
+}
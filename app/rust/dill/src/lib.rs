@@ -1,3 +1,13 @@
+// SignResult's oneof holds a whole WordsResponse beside a Status
+#[allow(clippy::large_enum_variant)]
 pub mod dill {
     tonic::include_proto!("dill");
 }
+
+pub mod google {
+    pub mod rpc {
+        tonic::include_proto!("google.rpc");
+    }
+}
+
+pub mod details;
@@ -0,0 +1,382 @@
+//
+// ApiError is the error type returned by pickle's api handlers. Failures
+// calling the grpc services are mapped to the closest HTTP status and
+// rendered as an RFC 7807 `application/problem+json` document that carries
+// the trace id of the request, so a client report can be matched to a trace.
+//
+// Services can describe an error with google.rpc detail messages. Their
+// ErrorInfo and BadRequest details are included in the problem document, and
+// RetryInfo sets the Retry-After header.
+//
+
+use crate::upstream::Upstream;
+use dill::details::{error_details, ErrorDetail};
+use okapi::openapi3::{MediaType, RefOr, Response as OpenApiResponse, Responses};
+use opentelemetry::trace::SpanContext;
+use rocket::{
+    http::{ContentType, Header, Status},
+    request::Request,
+    response::{self, Responder},
+    serde::{json::Json, Serialize},
+};
+use rocket_okapi::{gen::OpenApiGenerator, response::OpenApiResponderInner, JsonSchema};
+use std::collections::BTreeMap;
+use tonic::Code;
+
+// Seconds a client should wait before retrying a 429 or 503, unless the
+// service says otherwise
+const RETRY_AFTER: u64 = 1;
+
+#[derive(Debug)]
+pub struct ApiError {
+    status: Status,
+    detail: String,
+    trace_id: Option<String>,
+    error_info: Option<ErrorInfo>,
+    invalid_params: Vec<InvalidParam>,
+    retry_after: Option<u64>,
+}
+
+/// RFC 7807 problem details
+#[derive(Debug, JsonSchema, Serialize)]
+#[serde(crate = "rocket::serde", rename_all = "camelCase")]
+pub struct Problem {
+    /// Problem type uri; `about:blank` when the HTTP status says it all
+    #[serde(rename = "type")]
+    problem_type: String,
+
+    /// Short summary of the problem, the reason phrase of the status
+    title: String,
+
+    /// HTTP status code
+    status: u16,
+
+    /// Explanation specific to this occurrence of the problem
+    detail: String,
+
+    /// Path of the request that failed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    instance: Option<String>,
+
+    /// Id of the distributed trace recorded for the request
+    #[serde(skip_serializing_if = "Option::is_none")]
+    trace_id: Option<String>,
+
+    /// Machine readable cause of the error, as reported by the service
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error_info: Option<ErrorInfo>,
+
+    /// Request parameters the service rejected
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    invalid_params: Vec<InvalidParam>,
+}
+
+/// Cause of an error, from a google.rpc.ErrorInfo
+#[derive(Clone, Debug, JsonSchema, PartialEq, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct ErrorInfo {
+    /// UPPER_SNAKE_CASE cause of the error, unique within the domain
+    reason: String,
+
+    /// Service the reason belongs to
+    domain: String,
+
+    /// Additional details about the error
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    metadata: BTreeMap<String, String>,
+}
+
+/// A rejected request parameter, from a google.rpc.BadRequest field violation
+#[derive(Clone, Debug, JsonSchema, PartialEq, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct InvalidParam {
+    /// Path of the rejected field
+    name: String,
+
+    /// Why the value was rejected
+    reason: String,
+}
+
+impl ApiError {
+    /// Creates an error with the given HTTP status
+    ///
+    /// # Arguments
+    ///
+    /// * `status` - HTTP status of the response
+    /// * `detail` - Explanation included in the problem document
+    /// * `cx` - Span context of the request, for its trace id
+    pub fn new(status: Status, detail: String, cx: &SpanContext) -> ApiError {
+        ApiError {
+            status,
+            detail,
+            trace_id: match cx.is_valid() {
+                true => Some(cx.trace_id().to_hex()),
+                false => None,
+            },
+            error_info: None,
+            invalid_params: Vec::new(),
+            retry_after: None,
+        }
+    }
+
+    /// Error for a request pickle rejects itself, listing the invalid parameters
+    ///
+    /// # Arguments
+    ///
+    /// * `params` - Rejected parameters as (name, reason) pairs
+    /// * `cx` - Span context of the request, for its trace id
+    pub fn invalid_params(params: &[(&str, &str)], cx: &SpanContext) -> ApiError {
+        let mut e = ApiError::new(
+            Status::BadRequest,
+            String::from("invalid request parameters"),
+            cx,
+        );
+        e.invalid_params = params
+            .iter()
+            .map(|(name, reason)| InvalidParam {
+                name: name.to_string(),
+                reason: reason.to_string(),
+            })
+            .collect();
+        e
+    }
+
+    /// Error for an upstream service that isn't currently reachable
+    pub fn unavailable(upstream: &Upstream, cx: &SpanContext) -> ApiError {
+        ApiError::new(
+            Status::ServiceUnavailable,
+            format!("{} service unavailable", upstream.name()),
+            cx,
+        )
+    }
+
+    /// Error for a call to an upstream service that didn't complete in time
+    pub fn timeout(upstream: &Upstream, cx: &SpanContext) -> ApiError {
+        ApiError::new(
+            Status::GatewayTimeout,
+            format!("{} service did not respond in time", upstream.name()),
+            cx,
+        )
+    }
+
+    /// Maps a failed call to an upstream service to an HTTP error. Calls the
+    /// service couldn't receive are reported as unavailable, whatever their code.
+    /// A status with details came from the service itself, so is passed on.
+    pub fn from_status(upstream: &Upstream, status: &tonic::Status, cx: &SpanContext) -> ApiError {
+        if status.details().is_empty() && upstream.check_failure(status) {
+            return ApiError::unavailable(upstream, cx);
+        }
+        ApiError::new(
+            http_status(status.code()),
+            format!("{} service: {}", upstream.name(), status.message()),
+            cx,
+        )
+        .with_details(error_details(status))
+    }
+
+    /// Error for one request of a batch, from the status the service
+    /// returned for it
+    pub fn from_item_status(status: &tonic::Status, cx: &SpanContext) -> ApiError {
+        ApiError::new(http_status(status.code()), status.message().to_string(), cx)
+            .with_details(error_details(status))
+    }
+
+    /// The problem document describing the error
+    ///
+    /// # Arguments
+    ///
+    /// * `instance` - Path of the request that failed, if it has its own
+    pub fn into_problem(self, instance: Option<String>) -> Problem {
+        Problem {
+            problem_type: String::from("about:blank"),
+            title: self.status.reason().unwrap_or("Error").to_string(),
+            status: self.status.code,
+            detail: self.detail,
+            instance,
+            trace_id: self.trace_id,
+            error_info: self.error_info,
+            invalid_params: self.invalid_params,
+        }
+    }
+
+    // Adds the google.rpc details of a failed call. Only the first ErrorInfo
+    // and RetryInfo are used.
+    fn with_details(mut self, details: Vec<ErrorDetail>) -> ApiError {
+        for detail in details {
+            match detail {
+                ErrorDetail::ErrorInfo(info) if self.error_info.is_none() => {
+                    self.error_info = Some(ErrorInfo {
+                        reason: info.reason,
+                        domain: info.domain,
+                        metadata: info.metadata.into_iter().collect(),
+                    });
+                }
+                ErrorDetail::BadRequest(bad_request) => {
+                    self.invalid_params
+                        .extend(
+                            bad_request
+                                .field_violations
+                                .into_iter()
+                                .map(|v| InvalidParam {
+                                    name: v.field,
+                                    reason: v.description,
+                                }),
+                        );
+                }
+                ErrorDetail::RetryInfo(info) if self.retry_after.is_none() => {
+                    // Retry-After is in whole seconds, so round up
+                    self.retry_after = info
+                        .delay()
+                        .map(|d| d.as_secs() + u64::from(d.subsec_nanos() > 0));
+                }
+                _ => {}
+            }
+        }
+        self
+    }
+}
+
+/// Returns the HTTP status that best matches a grpc status code
+pub fn http_status(code: Code) -> Status {
+    match code {
+        Code::InvalidArgument | Code::OutOfRange | Code::FailedPrecondition => Status::BadRequest,
+        Code::ResourceExhausted => Status::TooManyRequests,
+        Code::Unavailable => Status::ServiceUnavailable,
+        // A service that runs out of the deadline pickle sets on a call cancels
+        // it, racing pickle's own timeout
+        Code::DeadlineExceeded | Code::Cancelled => Status::GatewayTimeout,
+        _ => Status::InternalServerError,
+    }
+}
+
+impl<'r> Responder<'r, 'static> for ApiError {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+        let (status, retry_after) = (self.status, self.retry_after);
+        let problem = self.into_problem(Some(request.uri().path().to_string()));
+        let mut response = Json(problem).respond_to(request)?;
+        response.set_status(status);
+        response.set_header(ContentType::new("application", "problem+json"));
+        let retry_after = match retry_after {
+            Some(seconds) => Some(seconds),
+            None if status == Status::ServiceUnavailable || status == Status::TooManyRequests => {
+                Some(RETRY_AFTER)
+            }
+            None => None,
+        };
+        if let Some(seconds) = retry_after {
+            response.set_header(Header::new("Retry-After", seconds.to_string()));
+        }
+        Ok(response)
+    }
+}
+
+impl OpenApiResponderInner for ApiError {
+    fn responses(gen: &mut OpenApiGenerator) -> rocket_okapi::Result<Responses> {
+        let schema = gen.json_schema::<Problem>();
+        let mut responses = Responses::default();
+        for (code, description) in &[
+            (
+                "400",
+                "The request was rejected by an upstream service as invalid",
+            ),
+            ("429", "An upstream service is overloaded; retry later"),
+            ("500", "An upstream service failed"),
+            ("503", "An upstream service is unavailable; retry later"),
+            ("504", "An upstream service did not respond in time"),
+        ] {
+            let mut response = OpenApiResponse {
+                description: description.to_string(),
+                ..Default::default()
+            };
+            response.content.insert(
+                String::from("application/problem+json"),
+                MediaType {
+                    schema: Some(schema.clone()),
+                    ..Default::default()
+                },
+            );
+            responses
+                .responses
+                .insert(code.to_string(), RefOr::Object(response));
+        }
+        Ok(responses)
+    }
+}
+
+// Renders any other error, like a malformed request body, as a problem document
+#[catch(default)]
+pub fn default_catcher(status: Status, _request: &Request) -> ApiError {
+    ApiError::new(
+        status,
+        status.reason().unwrap_or("Error").to_string(),
+        &SpanContext::empty_context(),
+    )
+}
+
+// Unit tests
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use dill::details;
+    use std::time::Duration;
+
+    #[test]
+    fn http_status_for_codes() {
+        assert_eq!(http_status(Code::InvalidArgument), Status::BadRequest);
+        assert_eq!(http_status(Code::OutOfRange), Status::BadRequest);
+        assert_eq!(
+            http_status(Code::ResourceExhausted),
+            Status::TooManyRequests
+        );
+        assert_eq!(http_status(Code::Unavailable), Status::ServiceUnavailable);
+        assert_eq!(http_status(Code::DeadlineExceeded), Status::GatewayTimeout);
+        assert_eq!(http_status(Code::Cancelled), Status::GatewayTimeout);
+        assert_eq!(http_status(Code::Internal), Status::InternalServerError);
+        assert_eq!(http_status(Code::Unknown), Status::InternalServerError);
+    }
+
+    #[test]
+    fn trace_id_only_for_valid_context() {
+        let e = ApiError::new(
+            Status::BadRequest,
+            String::new(),
+            &SpanContext::empty_context(),
+        );
+        assert_eq!(e.trace_id, None);
+    }
+
+    #[test]
+    fn details_from_status() {
+        let e = ApiError::new(
+            Status::BadRequest,
+            String::new(),
+            &SpanContext::empty_context(),
+        )
+        .with_details(vec![
+            details::error_info("EMPTY_WORD", "dill.SignWords", &[("index", "1")]),
+            details::error_info("IGNORED", "dill.SignWords", &[]),
+            details::bad_request(&[("words[1]", "must not be empty")]),
+            details::retry_after(Duration::from_millis(1500)),
+        ]);
+        assert_eq!(
+            e.error_info,
+            Some(ErrorInfo {
+                reason: String::from("EMPTY_WORD"),
+                domain: String::from("dill.SignWords"),
+                metadata: vec![(String::from("index"), String::from("1"))]
+                    .into_iter()
+                    .collect(),
+            })
+        );
+        assert_eq!(
+            e.invalid_params,
+            vec![InvalidParam {
+                name: String::from("words[1]"),
+                reason: String::from("must not be empty"),
+            }]
+        );
+        assert_eq!(e.retry_after, Some(2));
+    }
+}
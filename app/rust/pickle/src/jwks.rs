@@ -0,0 +1,242 @@
+//
+// Builds the JWKS document (RFC 7517) of the signer's public keys, served at
+// `/.well-known/jwks.json`, so consumers of signed words can verify them with
+// standard libraries. The key set is cached for a short while to spare the
+// signer, and served with Cache-Control and ETag headers so clients can cache
+// it too. If the signer can't be reached, the last key set fetched is served
+// for a while longer, uncached, so a rotation is never missed for long.
+//
+
+use crate::{
+    error::ApiError,
+    upstream::{Upstream, CALL_TIMEOUT},
+};
+use b3::InMetadataMap;
+use dill::dill::{sign_words_client::SignWordsClient, PublicKey, PublicKeysRequest};
+use log::{error, warn};
+use opentelemetry::{
+    global::{self, BoxedSpan},
+    trace::{Span, TraceContextExt},
+    Context,
+};
+use ring::digest::{digest, SHA256};
+use rocket::{
+    http::{ContentType, Header, Status},
+    request::Request,
+    response::{self, Responder, Response},
+    serde::Serialize,
+};
+use std::{
+    io::Cursor,
+    time::{Duration, Instant},
+};
+use tokio::{sync::Mutex, time::timeout};
+
+// How long pickle reuses a key set fetched from the signer, and clients may
+// cache it
+const REFRESH_AFTER: Duration = Duration::from_secs(30);
+
+// How long past its refresh pickle serves a key set the signer couldn't
+// refresh
+const MAX_STALE: Duration = Duration::from_secs(300);
+
+/// The key set last fetched from the signer, managed as Rocket state
+#[derive(Default)]
+pub struct JwksCache(Mutex<Option<Jwks>>);
+
+/// A serialized key set and its entity tag
+#[derive(Clone, Debug)]
+pub struct Jwks {
+    body: String,
+    etag: String,
+    fetched: Instant,
+    // Whether the key set is served past its refresh, so shouldn't be cached
+    stale: bool,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+struct JwkSet {
+    keys: Vec<Jwk>,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+struct Jwk {
+    kty: String,
+    kid: String,
+    alg: String,
+    #[serde(rename = "use")]
+    key_use: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    crv: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    n: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    e: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    x: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    y: Option<String>,
+}
+
+impl Jwks {
+    fn new(keys: Vec<PublicKey>) -> Jwks {
+        let set = JwkSet {
+            keys: keys.into_iter().map(Jwk::from).collect(),
+        };
+        // Serializing plain strings can't fail
+        let body = serde_json::to_string(&set).unwrap_or_default();
+        // A SHA-256 tag is the same for the same keys on every replica and
+        // release
+        let hash = digest(&SHA256, body.as_bytes());
+        let hex = hash
+            .as_ref()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<String>();
+        Jwks {
+            etag: format!("\"{}\"", hex),
+            body,
+            fetched: Instant::now(),
+            stale: false,
+        }
+    }
+
+    // The key set if it may still be served after a failed refresh
+    fn serve_stale(&self) -> Option<Jwks> {
+        if self.fetched.elapsed() >= REFRESH_AFTER + MAX_STALE {
+            return None;
+        }
+        Some(Jwks {
+            stale: true,
+            ..self.clone()
+        })
+    }
+}
+
+impl Jwk {
+    fn from(key: PublicKey) -> Jwk {
+        Jwk {
+            kty: key.kty,
+            kid: key.kid,
+            alg: key.alg,
+            key_use: "sig",
+            crv: key.crv,
+            n: key.n,
+            e: key.e,
+            x: key.x,
+            y: key.y,
+        }
+    }
+}
+
+impl JwksCache {
+    /// Returns the cached key set, fetching it from the signer when it's
+    /// missing or due for a refresh
+    ///
+    /// # Arguments
+    ///
+    /// * `upstream` - The signer service
+    /// * `span` - Span of the request, which the call to the signer joins
+    pub async fn get(&self, upstream: &Upstream, span: &mut BoxedSpan) -> Result<Jwks, ApiError> {
+        // Hold the lock while fetching, so concurrent requests share one fetch
+        let mut cached = self.0.lock().await;
+        if let Some(jwks) = cached.as_ref() {
+            if jwks.fetched.elapsed() < REFRESH_AFTER {
+                return Ok(jwks.clone());
+            }
+        }
+
+        match (
+            fetch(upstream, span).await,
+            cached.as_ref().and_then(Jwks::serve_stale),
+        ) {
+            (Ok(jwks), _) => {
+                *cached = Some(jwks.clone());
+                Ok(jwks)
+            }
+            (Err(_), Some(stale)) => {
+                warn!("Serving cached key set");
+                Ok(stale)
+            }
+            (Err(e), None) => Err(e),
+        }
+    }
+}
+
+async fn fetch(upstream: &Upstream, span: &mut BoxedSpan) -> Result<Jwks, ApiError> {
+    if !upstream.is_ready() {
+        return Err(ApiError::unavailable(upstream, span.span_context()));
+    }
+
+    let mut client = SignWordsClient::new(upstream.channel());
+    let mut request = tonic::Request::new(PublicKeysRequest {});
+
+    let grpc_cx = &Context::new().with_remote_span_context(span.span_context().clone());
+    global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(grpc_cx, &mut InMetadataMap(request.metadata_mut()));
+    });
+
+    request.set_timeout(CALL_TIMEOUT);
+    match timeout(CALL_TIMEOUT, client.get_public_keys(request)).await {
+        Ok(Ok(response)) => Ok(Jwks::new(response.into_inner().keys)),
+        Ok(Err(e)) => {
+            error!("Failed to call GetPublicKeys service: {}", e);
+            span.record_exception(&e);
+            Err(ApiError::from_status(upstream, &e, span.span_context()))
+        }
+        Err(e) => {
+            error!("Timed out calling GetPublicKeys service");
+            span.record_exception(&e);
+            Err(ApiError::timeout(upstream, span.span_context()))
+        }
+    }
+}
+
+impl<'r> Responder<'r, 'static> for Jwks {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+        let mut response = Response::build();
+        let cache_control = match self.stale {
+            true => String::from("no-cache"),
+            false => format!("public, max-age={}", REFRESH_AFTER.as_secs()),
+        };
+        response
+            .header(Header::new("Cache-Control", cache_control))
+            .header(Header::new("ETag", self.etag.clone()));
+        let not_modified = request
+            .headers()
+            .get("If-None-Match")
+            .flat_map(|tags| tags.split(','))
+            .any(|tag| tag.trim() == self.etag || tag.trim() == "*");
+        match not_modified {
+            true => response.status(Status::NotModified),
+            false => response
+                .header(ContentType::JSON)
+                .sized_body(self.body.len(), Cursor::new(self.body)),
+        };
+        response.ok()
+    }
+}
+
+// Unit tests
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn stale_key_set_is_bounded() {
+        let jwks = Jwks::new(Vec::new());
+        assert_eq!(jwks.etag.len(), 66);
+        assert_eq!(jwks.etag, Jwks::new(Vec::new()).etag);
+        assert!(jwks.serve_stale().unwrap().stale);
+
+        let old = Jwks {
+            fetched: Instant::now() - REFRESH_AFTER - MAX_STALE,
+            ..jwks
+        };
+        assert!(old.serve_stale().is_none());
+    }
+}
@@ -6,43 +6,83 @@
 #[macro_use]
 extern crate rocket;
 
+mod error;
+mod jwks;
+mod upstream;
+
 use b3::{HeaderExtractor, InMetadataMap, RocketHttpHeaderMap};
-use dill::dill::{
-    pick_words_client::PickWordsClient, sign_words_client::SignWordsClient, SignRequest,
-    WordsRequest, WordsResponse,
+use dill::{
+    details::from_rpc_status,
+    dill::{
+        pick_words_client::PickWordsClient, sign_result, sign_words_client::SignWordsClient,
+        Capitalization, SignBatchRequest, SignRequest, SignResult, SignatureFormat, VerifyRequest,
+        VerifyResponse, WordsRequest, WordsResponse,
+    },
 };
+use error::{ApiError, Problem};
+use jwks::{Jwks, JwksCache};
 use log::error;
-use once_cell::sync::OnceCell;
+use okapi::openapi3::Responses;
 use opentelemetry::{
     global,
-    trace::{noop::NoopTracerProvider, Span, TraceContextExt, Tracer},
+    trace::{noop::NoopTracerProvider, Span, SpanContext, TraceContextExt, Tracer},
     Context,
 };
 use rocket::{
+    fairing::AdHoc,
     get,
-    response::content::Html,
-    serde::{json::Json, Deserialize, Serialize},
+    http::Status,
+    request::Request,
+    response::{self, content::Html, status::Custom, Responder},
+    serde::{de::Error as _, json::Json, Deserialize, Deserializer, Serialize},
+    Build, Rocket, State,
 };
 use rocket_okapi::{
-    openapi, routes_with_openapi,
+    gen::OpenApiGenerator,
+    openapi,
+    response::OpenApiResponderInner,
+    routes_with_openapi,
     swagger_ui::{make_swagger_ui, SwaggerUIConfig},
     JsonSchema,
 };
-use std::{panic, time::Duration};
-use tonic::transport::Channel;
+use std::{cmp::Ordering, time::Duration};
+use tokio::time::timeout;
+use upstream::{Addrs, Upstream, BATCH_CALL_TIMEOUT, CALL_TIMEOUT};
+
+// Words in a passphrase when the request doesn't give a count
+const DEFAULT_PASSPHRASE_COUNT: u8 = 6;
 
 // App-specific config provided using Rocket config
-#[derive(Debug)]
+#[derive(Debug, Deserialize)]
+#[serde(crate = "rocket::serde", rename_all = "kebab-case")]
 struct Config {
-    words_svc_addr: String,
-    sign_svc_addr: String,
+    // one address or a list of addresses for each upstream service
+    words_svc_addr: Addrs,
+    sign_svc_addr: Addrs,
+    // seconds between re-resolving upstream DNS names
+    #[serde(default = "default_dns_refresh", deserialize_with = "dns_refresh")]
+    upstream_dns_refresh: u64,
     tracing_service_name: String,
     trace_collector_endpoint: String,
 }
-static CONFIG: OnceCell<Config> = OnceCell::new();
 
-static WORDS_CHANNEL: OnceCell<Channel> = OnceCell::new();
-static SIGN_CHANNEL: OnceCell<Channel> = OnceCell::new();
+fn default_dns_refresh() -> u64 {
+    30
+}
+
+// At least 1 second, as re-resolving without a pause would spin
+fn dns_refresh<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+    match u64::deserialize(deserializer)? {
+        0 => Err(D::Error::custom("must be at least 1 second")),
+        seconds => Ok(seconds),
+    }
+}
+
+// grpc services used by the api, managed as Rocket state
+struct Upstreams {
+    words: Upstream,
+    sign: Upstream,
+}
 
 // json return value
 #[derive(Debug, Deserialize, JsonSchema, Serialize)]
@@ -54,6 +94,141 @@ struct Words {
 
     #[serde(skip_serializing_if = "Option::is_none")]
     signature: Option<String>,
+
+    /// JOSE name of the signature algorithm: PS256, ES256 or EdDSA
+    #[serde(skip_serializing_if = "Option::is_none")]
+    algorithm: Option<String>,
+
+    /// Id of the key that made the signature
+    #[serde(skip_serializing_if = "Option::is_none")]
+    kid: Option<String>,
+
+    /// Version of the signed message layout
+    #[serde(skip_serializing_if = "Option::is_none")]
+    version: Option<u32>,
+
+    /// The signed words as a compact JWS, when requested with format=jws
+    #[serde(skip_serializing_if = "Option::is_none")]
+    jws: Option<String>,
+
+    /// The signed words as a base64 COSE_Sign1 message, when requested with
+    /// format=cose
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cose: Option<String>,
+
+    /// When the signature expires, in milliseconds since the unix epoch
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expires_at: Option<u64>,
+
+    /// A base64 DER RFC 3161 timestamp token for the words, when requested
+    /// with tst=true
+    #[serde(skip_serializing_if = "Option::is_none")]
+    timestamp_token: Option<String>,
+
+    /// Seed the words were generated from; passing it back as seed= with
+    /// the same count, dictionary and locale gives the same words
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<u64>,
+
+    /// Language tag of the word lists the words were picked from
+    #[serde(skip_serializing_if = "Option::is_none")]
+    locale: Option<String>,
+
+    /// The words joined by the separator, when requested with passphrase=true
+    #[serde(skip_serializing_if = "Option::is_none")]
+    passphrase: Option<String>,
+
+    /// Bits of entropy in the choice of the words of a passphrase
+    #[serde(skip_serializing_if = "Option::is_none")]
+    entropy_bits: Option<f64>,
+}
+
+// query parameters of /words
+#[derive(Debug, FromForm, JsonSchema)]
+struct WordsQuery {
+    /// Number of words, 3 by default
+    count: Option<u8>,
+
+    /// Whether to sign the words
+    sign: Option<bool>,
+
+    /// Signature format, raw, jws or cose
+    format: Option<String>,
+
+    /// Seconds the signature stays valid
+    ttl: Option<u32>,
+
+    /// Whether to also return an RFC 3161 timestamp token
+    tst: Option<bool>,
+
+    /// Seed for the word generator, to regenerate earlier words
+    seed: Option<u64>,
+
+    /// Dictionary to pick the words from, the default one when not given
+    dictionary: Option<String>,
+
+    /// BCP 47 language tag of the words; the Accept-Language header is used
+    /// when not given
+    locale: Option<String>,
+
+    /// Whether to pick the words as a passphrase, 6 of them by default
+    passphrase: Option<bool>,
+
+    /// Separator between the words of a passphrase, - by default
+    separator: Option<String>,
+
+    /// Capitalization of the words of a passphrase: lower (the default),
+    /// upper or title
+    capitalization: Option<String>,
+
+    /// Whether each word may appear only once
+    unique: Option<bool>,
+
+    /// Fewest characters a word may have
+    min_length: Option<u32>,
+
+    /// Most characters a word may have
+    max_length: Option<u32>,
+
+    /// Kinds of words, in order, as parts joined by -, each adj, noun, verb
+    /// or num, such as adj-adj-noun-verb
+    pattern: Option<String>,
+
+    /// Whether all the words start with the same letter
+    alliterate: Option<bool>,
+
+    /// Whether all the words end the same from their last vowel
+    rhyme: Option<bool>,
+}
+
+// json result of signing one list of words in a batch
+#[derive(Debug, JsonSchema, Serialize)]
+struct BatchResult {
+    /// The signed words
+    #[serde(skip_serializing_if = "Option::is_none")]
+    signed: Option<Words>,
+
+    /// Why the words were not signed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<Problem>,
+}
+
+// json result of verifying signed words
+#[derive(Debug, Deserialize, JsonSchema, PartialEq, Serialize)]
+struct Verification {
+    valid: bool,
+
+    /// Why the signature is not valid
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<String>,
+
+    /// Id of the key that made a valid signature
+    #[serde(skip_serializing_if = "Option::is_none")]
+    kid: Option<String>,
+
+    /// Version of the message layout a valid signature was made over
+    #[serde(skip_serializing_if = "Option::is_none")]
+    version: Option<u32>,
 }
 
 #[openapi]
@@ -69,172 +244,503 @@ fn index() -> Html<&'static str> {
     )
 }
 
+// Maps the format query parameter to the signature format to request:
+// "raw" (the default) for a separate signature and timestamp, "jws" or "cose"
+#[allow(clippy::result_large_err)]
+fn signature_format(format: Option<&str>, cx: &SpanContext) -> Result<SignatureFormat, ApiError> {
+    match format {
+        None | Some("raw") => Ok(SignatureFormat::Raw),
+        Some("jws") => Ok(SignatureFormat::Jws),
+        Some("cose") => Ok(SignatureFormat::Cose),
+        Some(_) => Err(ApiError::invalid_params(
+            &[("format", "must be raw, jws or cose")],
+            cx,
+        )),
+    }
+}
+
+// Maps the capitalization query parameter to the passphrase capitalization
+// to request
+#[allow(clippy::result_large_err)]
+fn capitalization(
+    capitalization: Option<&str>,
+    cx: &SpanContext,
+) -> Result<Capitalization, ApiError> {
+    match capitalization {
+        None | Some("lower") => Ok(Capitalization::Lower),
+        Some("upper") => Ok(Capitalization::Upper),
+        Some("title") => Ok(Capitalization::Title),
+        Some(_) => Err(ApiError::invalid_params(
+            &[("capitalization", "must be lower, upper or title")],
+            cx,
+        )),
+    }
+}
+
+// Parses an Accept-Language header into its language tags, most preferred
+// first, dropping the wildcard and tags with a weight of zero
+fn accept_language(header: Option<&str>) -> Vec<String> {
+    let mut ranges = header
+        .unwrap_or_default()
+        .split(',')
+        .filter_map(|range| {
+            let mut params = range.split(';');
+            let tag = params.next()?.trim();
+            let weight = match params.find_map(|p| p.trim().strip_prefix("q=")) {
+                Some(q) => q.trim().parse::<f32>().ok()?,
+                None => 1.0,
+            };
+            (!tag.is_empty() && tag != "*" && weight > 0.0).then(|| (tag.to_string(), weight))
+        })
+        .collect::<Vec<(String, f32)>>();
+    // A stable sort keeps tags of the same weight in header order
+    ranges.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(Ordering::Equal));
+    ranges.into_iter().map(|(tag, _)| tag).collect()
+}
+
+// A response that depends on the Accept-Language header, so caches keep one
+// per language
+struct Localized<R>(R);
+
+impl<'r, R: Responder<'r, 'static>> Responder<'r, 'static> for Localized<R> {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+        let mut response = self.0.respond_to(request)?;
+        response.set_raw_header("Vary", "Accept-Language");
+        Ok(response)
+    }
+}
+
+impl<R: OpenApiResponderInner> OpenApiResponderInner for Localized<R> {
+    fn responses(gen: &mut OpenApiGenerator) -> rocket_okapi::Result<Responses> {
+        R::responses(gen)
+    }
+}
+
 #[openapi]
-#[get("/words?<count>&<sign>")]
+#[get("/words?<query..>")]
 async fn words(
     header_map: RocketHttpHeaderMap<'_>,
-    count: Option<u8>,
-    sign: Option<bool>,
-) -> Option<Json<Words>> {
+    upstreams: &State<Upstreams>,
+    query: WordsQuery,
+) -> Localized<Result<Json<Words>, ApiError>> {
+    Localized(pick_words(header_map, upstreams, query).await)
+}
+
+// Gets words from the words service, in the locale Accept-Language prefers
+// when the query doesn't name one
+async fn pick_words(
+    header_map: RocketHttpHeaderMap<'_>,
+    upstreams: &State<Upstreams>,
+    query: WordsQuery,
+) -> Result<Json<Words>, ApiError> {
     let cx = global::get_text_map_propagator(|propagator| {
         propagator.extract(&HeaderExtractor(header_map.0))
     });
     let mut span = global::tracer("pickle web").start_with_context("words", cx.clone());
 
-    let cnt = match count {
-        Some(cnt) => cnt,
-        None => 3,
+    let passphrase = query.passphrase.unwrap_or(false);
+    let cnt = match (passphrase, &query.pattern) {
+        (true, _) => query.count.unwrap_or(DEFAULT_PASSPHRASE_COUNT),
+        // A pattern sets the number of words
+        (false, Some(_)) => query.count.unwrap_or(0),
+        (false, None) => query.count.unwrap_or(3),
     };
-
-    let signed = match sign {
-        Some(signed) => signed,
-        None => false,
+    let signed = query.sign.unwrap_or(false);
+    let format = match signature_format(query.format.as_deref(), span.span_context()) {
+        Ok(format) => format,
+        Err(e) => {
+            span.end();
+            return Err(e);
+        }
+    };
+    let capitalization = match capitalization(query.capitalization.as_deref(), span.span_context())
+    {
+        Ok(capitalization) => capitalization,
+        Err(e) => {
+            span.end();
+            return Err(e);
+        }
     };
 
-    let mut client = PickWordsClient::new(WORDS_CHANNEL.get().unwrap().clone());
+    let upstream = &upstreams.words;
+    if !upstream.is_ready() {
+        let e = ApiError::unavailable(upstream, span.span_context());
+        span.end();
+        return Err(e);
+    }
+
+    let mut client = PickWordsClient::new(upstream.channel());
     let mut request = tonic::Request::new(WordsRequest {
         count: u32::from(cnt),
-        signed: signed,
+        signed,
+        format: format as i32,
+        ttl_seconds: query.ttl,
+        timestamp_token: query.tst.unwrap_or(false),
+        seed: query.seed,
+        dictionary: query.dictionary.unwrap_or_default(),
+        locale: query.locale.unwrap_or_default(),
+        accept_locales: accept_language(header_map.0.get_one("Accept-Language")),
+        passphrase,
+        separator: query.separator,
+        capitalization: capitalization as i32,
+        unique: query.unique.unwrap_or(false),
+        min_length: query.min_length,
+        max_length: query.max_length,
+        pattern: query.pattern.unwrap_or_default(),
+        alliterate: query.alliterate.unwrap_or(false),
+        rhyme: query.rhyme.unwrap_or(false),
     });
 
     let grpc_cx = &Context::new().with_remote_span_context(span.span_context().clone());
     global::get_text_map_propagator(|propagator| {
-        propagator.inject_context(&grpc_cx, &mut InMetadataMap(request.metadata_mut()));
+        propagator.inject_context(grpc_cx, &mut InMetadataMap(request.metadata_mut()));
     });
 
-    let response = match client.get_words(request).await {
-        Ok(response) => response,
-        Err(e) => {
+    request.set_timeout(CALL_TIMEOUT);
+    let response = match timeout(CALL_TIMEOUT, client.get_words(request)).await {
+        Ok(Ok(response)) => response,
+        Ok(Err(e)) => {
             error!("Failed to call GetWords service: {}", e);
             span.record_exception(&e);
-            return None;
+            let e = ApiError::from_status(upstream, &e, span.span_context());
+            span.end();
+            return Err(e);
+        }
+        Err(e) => {
+            error!("Timed out calling GetWords service");
+            span.record_exception(&e);
+            let e = ApiError::timeout(upstream, span.span_context());
+            span.end();
+            return Err(e);
         }
     };
 
     span.end();
 
-    Some(Json(Words::from(response.into_inner())))
+    Ok(Json(Words::from(response.into_inner())))
 }
 
 #[openapi]
-#[post("/sign", data = "<words>")]
+#[post("/sign?<format>&<ttl>&<tst>", data = "<words>")]
 async fn sign_words(
     header_map: RocketHttpHeaderMap<'_>,
+    upstreams: &State<Upstreams>,
     words: Json<Words>,
-) -> Option<Json<Words>> {
+    format: Option<String>,
+    ttl: Option<u32>,
+    tst: Option<bool>,
+) -> Result<Json<Words>, ApiError> {
     let cx = global::get_text_map_propagator(|propagator| {
         propagator.extract(&HeaderExtractor(header_map.0))
     });
     let mut span = global::tracer("pickle web").start_with_context("sign_words", cx.clone());
 
     let v = &words.words;
+    let format = match signature_format(format.as_deref(), span.span_context()) {
+        Ok(format) => format,
+        Err(e) => {
+            span.end();
+            return Err(e);
+        }
+    };
 
-    let mut client = SignWordsClient::new(SIGN_CHANNEL.get().unwrap().clone());
-    let mut request = tonic::Request::new(SignRequest { words: v.to_vec() });
+    let upstream = &upstreams.sign;
+    if !upstream.is_ready() {
+        let e = ApiError::unavailable(upstream, span.span_context());
+        span.end();
+        return Err(e);
+    }
+
+    let mut client = SignWordsClient::new(upstream.channel());
+    let mut request = tonic::Request::new(SignRequest {
+        words: v.to_vec(),
+        format: format as i32,
+        ttl_seconds: ttl,
+        timestamp_token: tst.unwrap_or(false),
+    });
 
     let grpc_cx = &Context::new().with_remote_span_context(span.span_context().clone());
     global::get_text_map_propagator(|propagator| {
-        propagator.inject_context(&grpc_cx, &mut InMetadataMap(request.metadata_mut()));
+        propagator.inject_context(grpc_cx, &mut InMetadataMap(request.metadata_mut()));
     });
 
-    let response = match client.sign_words(request).await {
-        Ok(response) => response,
+    request.set_timeout(CALL_TIMEOUT);
+    let response = match timeout(CALL_TIMEOUT, client.sign_words(request)).await {
+        Ok(Ok(response)) => response,
+        Ok(Err(e)) => {
+            error!("Failed to call SignWords service: {}", e);
+            span.record_exception(&e);
+            let e = ApiError::from_status(upstream, &e, span.span_context());
+            span.end();
+            return Err(e);
+        }
         Err(e) => {
-            error!("Failed to call GetWords service: {}", e);
+            error!("Timed out calling SignWords service");
             span.record_exception(&e);
-            return None;
+            let e = ApiError::timeout(upstream, span.span_context());
+            span.end();
+            return Err(e);
         }
     };
 
     span.end();
 
-    Some(Json(Words::from(response.into_inner())))
+    Ok(Json(Words::from(response.into_inner())))
 }
 
-fn get_docs() -> SwaggerUIConfig {
-    SwaggerUIConfig {
-        url: "../api/v1.0/openapi.json".to_owned(),
-        ..Default::default()
-    }
-}
+#[openapi]
+#[post("/sign/batch?<format>&<ttl>&<tst>", data = "<batch>")]
+async fn sign_batch(
+    header_map: RocketHttpHeaderMap<'_>,
+    upstreams: &State<Upstreams>,
+    batch: Json<Vec<Words>>,
+    format: Option<String>,
+    ttl: Option<u32>,
+    tst: Option<bool>,
+) -> Result<Json<Vec<BatchResult>>, ApiError> {
+    let cx = global::get_text_map_propagator(|propagator| {
+        propagator.extract(&HeaderExtractor(header_map.0))
+    });
+    let mut span = global::tracer("pickle web").start_with_context("sign_batch", cx.clone());
 
-#[launch]
-async fn rocket() -> _ {
-    let rocket = rocket::build()
-        .mount("/", routes_with_openapi![index])
-        .mount("/api/v1.0", routes_with_openapi![sign_words, words])
-        .mount("/swagger", make_swagger_ui(&get_docs()));
-    let figment = rocket.figment();
-    let config = Config {
-        words_svc_addr: figment
-            .find_value("words-svc-addr")
-            .unwrap()
-            .into_string()
-            .unwrap(),
-        sign_svc_addr: figment
-            .find_value("sign-svc-addr")
-            .unwrap()
-            .into_string()
-            .unwrap(),
-        tracing_service_name: figment
-            .find_value("tracing-service-name")
-            .unwrap()
-            .into_string()
-            .unwrap(),
-        trace_collector_endpoint: figment
-            .find_value("trace-collector-endpoint")
-            .unwrap()
-            .into_string()
-            .unwrap(),
+    let format = match signature_format(format.as_deref(), span.span_context()) {
+        Ok(format) => format,
+        Err(e) => {
+            span.end();
+            return Err(e);
+        }
     };
-    CONFIG.set(config).unwrap();
 
-    global::set_text_map_propagator(b3::Propagator::new());
-    match opentelemetry_jaeger::new_pipeline()
-        .with_service_name(&CONFIG.get().unwrap().tracing_service_name)
-        .with_collector_endpoint(&CONFIG.get().unwrap().trace_collector_endpoint)
-        .build_batch(opentelemetry::runtime::Tokio)
-    {
-        Ok(provider) => {
-            global::set_tracer_provider(provider);
-            info!(
-                "Tracing to collector {}",
-                &CONFIG.get().unwrap().trace_collector_endpoint
-            );
+    let upstream = &upstreams.sign;
+    if !upstream.is_ready() {
+        let e = ApiError::unavailable(upstream, span.span_context());
+        span.end();
+        return Err(e);
+    }
+
+    let mut client = SignWordsClient::new(upstream.channel());
+    let requests = batch
+        .into_inner()
+        .into_iter()
+        .map(|words| SignRequest {
+            words: words.words,
+            format: format as i32,
+            ttl_seconds: ttl,
+            timestamp_token: tst.unwrap_or(false),
+        })
+        .collect();
+    let mut request = tonic::Request::new(SignBatchRequest { requests });
+
+    let grpc_cx = &Context::new().with_remote_span_context(span.span_context().clone());
+    global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(grpc_cx, &mut InMetadataMap(request.metadata_mut()));
+    });
+
+    request.set_timeout(BATCH_CALL_TIMEOUT);
+    let response = match timeout(BATCH_CALL_TIMEOUT, client.sign_batch(request)).await {
+        Ok(Ok(response)) => response,
+        Ok(Err(e)) => {
+            error!("Failed to call SignBatch service: {}", e);
+            span.record_exception(&e);
+            let e = ApiError::from_status(upstream, &e, span.span_context());
+            span.end();
+            return Err(e);
         }
         Err(e) => {
-            warn!("Failed to setup tracer: {}", e);
-            global::set_tracer_provider(NoopTracerProvider::new());
+            error!("Timed out calling SignBatch service");
+            span.record_exception(&e);
+            let e = ApiError::timeout(upstream, span.span_context());
+            span.end();
+            return Err(e);
         }
     };
 
-    let sign_addr = &CONFIG.get().unwrap().sign_svc_addr;
-    let sign_channel = match Channel::from_static(sign_addr)
-        .timeout(Duration::from_millis(500))
-        .connect()
-        .await
-    {
-        Ok(channel) => channel,
-        Err(e) => {
-            panic!("Failed to create Signs channel: {}", e);
+    let results = response
+        .into_inner()
+        .results
+        .into_iter()
+        .map(|result| BatchResult::from(result, span.span_context()))
+        .collect();
+    span.end();
+
+    Ok(Json(results))
+}
+
+#[openapi]
+#[post("/verify", data = "<words>")]
+async fn verify_words(
+    header_map: RocketHttpHeaderMap<'_>,
+    upstreams: &State<Upstreams>,
+    words: Json<Words>,
+) -> Result<Json<Verification>, ApiError> {
+    let cx = global::get_text_map_propagator(|propagator| {
+        propagator.extract(&HeaderExtractor(header_map.0))
+    });
+    let mut span = global::tracer("pickle web").start_with_context("verify_words", cx.clone());
+
+    let words = words.into_inner();
+    let (timestamp, signature) = match (words.timestamp, words.signature) {
+        (Some(timestamp), Some(signature)) => (timestamp, signature),
+        (timestamp, signature) => {
+            let mut missing = Vec::new();
+            if timestamp.is_none() {
+                missing.push(("timestamp", "is required"));
+            }
+            if signature.is_none() {
+                missing.push(("signature", "is required"));
+            }
+            let e = ApiError::invalid_params(&missing, span.span_context());
+            span.end();
+            return Err(e);
         }
     };
-    SIGN_CHANNEL.set(sign_channel).unwrap();
 
-    let words_addr = &CONFIG.get().unwrap().words_svc_addr;
-    let words_channel = match Channel::from_static(words_addr)
-        .timeout(Duration::from_millis(500))
-        .connect()
-        .await
-    {
-        Ok(channel) => channel,
+    let upstream = &upstreams.sign;
+    if !upstream.is_ready() {
+        let e = ApiError::unavailable(upstream, span.span_context());
+        span.end();
+        return Err(e);
+    }
+
+    let mut client = SignWordsClient::new(upstream.channel());
+    let mut request = tonic::Request::new(VerifyRequest {
+        words: words.words,
+        timestamp,
+        signature,
+        kid: words.kid,
+        version: words.version,
+        expires_at: words.expires_at,
+    });
+
+    let grpc_cx = &Context::new().with_remote_span_context(span.span_context().clone());
+    global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(grpc_cx, &mut InMetadataMap(request.metadata_mut()));
+    });
+
+    request.set_timeout(CALL_TIMEOUT);
+    let response = match timeout(CALL_TIMEOUT, client.verify_words(request)).await {
+        Ok(Ok(response)) => response,
+        Ok(Err(e)) => {
+            error!("Failed to call VerifyWords service: {}", e);
+            span.record_exception(&e);
+            let e = ApiError::from_status(upstream, &e, span.span_context());
+            span.end();
+            return Err(e);
+        }
         Err(e) => {
-            panic!("Failed to create Words channel: {}", e);
+            error!("Timed out calling VerifyWords service");
+            span.record_exception(&e);
+            let e = ApiError::timeout(upstream, span.span_context());
+            span.end();
+            return Err(e);
         }
     };
-    WORDS_CHANNEL.set(words_channel).unwrap();
 
+    span.end();
+
+    Ok(Json(Verification::from(response.into_inner())))
+}
+
+// Public keys of the signer, as a JWKS document
+#[get("/jwks.json")]
+async fn public_keys(
+    header_map: RocketHttpHeaderMap<'_>,
+    upstreams: &State<Upstreams>,
+    cache: &State<JwksCache>,
+) -> Result<Jwks, ApiError> {
+    let cx = global::get_text_map_propagator(|propagator| {
+        propagator.extract(&HeaderExtractor(header_map.0))
+    });
+    let mut span = global::tracer("pickle web").start_with_context("public_keys", cx.clone());
+
+    let jwks = cache.get(&upstreams.sign, &mut span).await;
+    span.end();
+    jwks
+}
+
+// Readiness probe: succeeds once every upstream service is reachable
+#[get("/ready")]
+fn ready(upstreams: &State<Upstreams>) -> Custom<&'static str> {
+    match upstreams.words.is_ready() && upstreams.sign.is_ready() {
+        true => Custom(Status::Ok, "ready"),
+        false => Custom(Status::ServiceUnavailable, "not ready"),
+    }
+}
+
+fn get_docs() -> SwaggerUIConfig {
+    SwaggerUIConfig {
+        url: "../api/v1.0/openapi.json".to_owned(),
+        ..Default::default()
+    }
+}
+
+/// Mounts the api and connects the upstream services named in the figment.
+/// Upstream channels are created lazily when the instance ignites, so the
+/// services don't need to be up yet.
+fn app(rocket: Rocket<Build>) -> Rocket<Build> {
     rocket
+        .mount("/", routes_with_openapi![index])
+        .mount(
+            "/api/v1.0",
+            routes_with_openapi![sign_batch, sign_words, verify_words, words],
+        )
+        .mount("/.well-known", routes![public_keys])
+        .mount("/healthz", routes![ready])
+        .mount("/swagger", make_swagger_ui(&get_docs()))
+        .register("/", catchers![error::default_catcher])
+        .manage(JwksCache::default())
+        .attach(AdHoc::try_on_ignite("Upstream services", |rocket| async {
+            let config = match rocket.figment().extract::<Config>() {
+                Ok(config) => config,
+                Err(e) => {
+                    error!("Invalid configuration: {}", e);
+                    return Err(rocket);
+                }
+            };
+            let dns_refresh = Duration::from_secs(config.upstream_dns_refresh);
+            let upstreams =
+                Upstream::new("words", &config.words_svc_addr, dns_refresh).and_then(|words| {
+                    Ok(Upstreams {
+                        words,
+                        sign: Upstream::new("signer", &config.sign_svc_addr, dns_refresh)?,
+                    })
+                });
+            match upstreams {
+                Ok(upstreams) => Ok(rocket.manage(config).manage(upstreams)),
+                Err(e) => {
+                    error!("Invalid upstream service address: {}", e);
+                    Err(rocket)
+                }
+            }
+        }))
+}
+
+#[launch]
+async fn rocket() -> _ {
+    app(rocket::build()).attach(AdHoc::on_ignite("Tracing", |rocket| async {
+        let config = match rocket.state::<Config>() {
+            Some(config) => config,
+            None => return rocket,
+        };
+
+        global::set_text_map_propagator(b3::Propagator::new());
+        match opentelemetry_jaeger::new_pipeline()
+            .with_service_name(&config.tracing_service_name)
+            .with_collector_endpoint(&config.trace_collector_endpoint)
+            .build_batch(opentelemetry::runtime::Tokio)
+        {
+            Ok(provider) => {
+                global::set_tracer_provider(provider);
+                info!("Tracing to collector {}", &config.trace_collector_endpoint);
+            }
+            Err(e) => {
+                warn!("Failed to setup tracer: {}", e);
+                global::set_tracer_provider(NoopTracerProvider::new());
+            }
+        };
+
+        rocket
+    }))
 }
 
 // Convenience functions for working with Words and WordsResponses
@@ -243,24 +749,75 @@ impl Words {
     fn from(proto: WordsResponse) -> Words {
         Words {
             words: proto.words,
-            timestamp: match proto.timestamp {
-                Some(time) => Some(time),
-                _ => None,
+            timestamp: proto.timestamp,
+            signature: proto.signature,
+            algorithm: proto.algorithm,
+            kid: proto.kid,
+            version: proto.version,
+            jws: proto.jws,
+            cose: proto.cose.map(base64::encode),
+            expires_at: proto.expires_at,
+            timestamp_token: proto.timestamp_token.map(base64::encode),
+            seed: proto.seed,
+            locale: proto.locale,
+            passphrase: proto.passphrase,
+            entropy_bits: proto.entropy_bits,
+        }
+    }
+}
+
+impl BatchResult {
+    fn from(proto: SignResult, cx: &SpanContext) -> BatchResult {
+        match proto.result {
+            Some(sign_result::Result::Response(response)) => BatchResult {
+                signed: Some(Words::from(response)),
+                error: None,
+            },
+            Some(sign_result::Result::Error(status)) => BatchResult {
+                signed: None,
+                error: Some(
+                    ApiError::from_item_status(&from_rpc_status(&status), cx).into_problem(None),
+                ),
             },
-            signature: match proto.signature {
-                Some(signature) => Some(signature),
-                _ => None,
+            None => BatchResult {
+                signed: None,
+                error: Some(
+                    ApiError::from_item_status(&tonic::Status::unknown("no result"), cx)
+                        .into_problem(None),
+                ),
             },
         }
     }
 }
 
+impl Verification {
+    fn from(proto: VerifyResponse) -> Verification {
+        Verification {
+            valid: proto.valid,
+            reason: proto.reason,
+            kid: proto.kid,
+            version: proto.version,
+        }
+    }
+}
+
 impl PartialEq for Words {
     fn eq(&self, other: &Self) -> bool {
         (self.words.len() == other.words.len())
             && self.words.iter().zip(&other.words).all(|(a, b)| a == b)
-            && self.timestamp == self.timestamp
-            && self.signature == self.signature
+            && self.timestamp == other.timestamp
+            && self.signature == other.signature
+            && self.algorithm == other.algorithm
+            && self.kid == other.kid
+            && self.version == other.version
+            && self.jws == other.jws
+            && self.cose == other.cose
+            && self.expires_at == other.expires_at
+            && self.timestamp_token == other.timestamp_token
+            && self.seed == other.seed
+            && self.locale == other.locale
+            && self.passphrase == other.passphrase
+            && self.entropy_bits == other.entropy_bits
     }
 }
 
@@ -270,6 +827,772 @@ impl PartialEq for Words {
 mod tests {
 
     use super::*;
+    use dill::{
+        details::{self, ErrorDetail},
+        dill::{
+            pick_words_server::{PickWords, PickWordsServer},
+            sign_words_server::{SignWords, SignWordsServer},
+            PublicKey, PublicKeysRequest, PublicKeysResponse, SignBatchResponse, TimestampRequest,
+            TimestampResponse,
+        },
+    };
+    use rocket::{
+        http::{ContentType, Header},
+        local::asynchronous::{Client, LocalResponse},
+        tokio,
+        tokio::time::sleep,
+    };
+    use std::{
+        net::{SocketAddr, TcpListener},
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+    use tonic::{transport::Server, Code};
+
+    // The last request a mock service was sent
+    type Forwarded = Arc<Mutex<Option<WordsRequest>>>;
+
+    // PickWords service with canned behavior
+    enum MockPickWords {
+        // returns the word as many times as requested
+        Word(&'static str),
+        // returns the word like Word, keeping the request
+        Recorded(&'static str, Forwarded),
+        // fails every call with the code
+        Fail(Code),
+        // fails every call with the code and google.rpc details
+        Detailed(Code, Vec<ErrorDetail>),
+        // never answers within pickle's deadline
+        Stall,
+    }
+
+    #[tonic::async_trait]
+    impl PickWords for MockPickWords {
+        async fn get_words(
+            &self,
+            request: tonic::Request<WordsRequest>,
+        ) -> Result<tonic::Response<WordsResponse>, tonic::Status> {
+            let request = request.into_inner();
+            let count = match request.pattern.as_str() {
+                "" => request.count as usize,
+                pattern => pattern.split('-').count(),
+            };
+            if let MockPickWords::Recorded(_, forwarded) = self {
+                *forwarded.lock().unwrap() = Some(request.clone());
+            }
+            match self {
+                MockPickWords::Word(word) | MockPickWords::Recorded(word, _) => {
+                    let words = vec![String::from(*word); count];
+                    let separator = request.separator.as_deref().unwrap_or("-");
+                    Ok(tonic::Response::new(WordsResponse {
+                        passphrase: request.passphrase.then(|| words.join(separator)),
+                        words,
+                        seed: request.seed,
+                        locale: request.accept_locales.first().cloned(),
+                        ..Default::default()
+                    }))
+                }
+                MockPickWords::Fail(code) => Err(tonic::Status::new(*code, "mock failure")),
+                MockPickWords::Detailed(code, details) => Err(details::with_details(
+                    *code,
+                    "mock failure",
+                    details.clone(),
+                )),
+                MockPickWords::Stall => {
+                    sleep(CALL_TIMEOUT * 4).await;
+                    Err(tonic::Status::internal("too late"))
+                }
+            }
+        }
+    }
+
+    // SignWords service whose only valid signature is "good"
+    struct MockSignWords;
+
+    #[tonic::async_trait]
+    impl SignWords for MockSignWords {
+        async fn sign_words(
+            &self,
+            request: tonic::Request<SignRequest>,
+        ) -> Result<tonic::Response<WordsResponse>, tonic::Status> {
+            let request = request.into_inner();
+            match request.format() {
+                SignatureFormat::Raw => Ok(tonic::Response::new(WordsResponse {
+                    words: request.words,
+                    timestamp: Some(1),
+                    signature: Some(String::from("good")),
+                    algorithm: Some(String::from("EdDSA")),
+                    kid: Some(String::from("k1")),
+                    version: Some(2),
+                    expires_at: request.ttl_seconds.map(|ttl| 1 + u64::from(ttl) * 1000),
+                    timestamp_token: match request.timestamp_token {
+                        true => Some(vec![0x30, 0]),
+                        false => None,
+                    },
+                    ..Default::default()
+                })),
+                SignatureFormat::Jws => Ok(tonic::Response::new(WordsResponse {
+                    words: request.words,
+                    algorithm: Some(String::from("EdDSA")),
+                    kid: Some(String::from("k1")),
+                    jws: Some(String::from("e30.e30.Z29vZA")),
+                    ..Default::default()
+                })),
+                SignatureFormat::Cose => Ok(tonic::Response::new(WordsResponse {
+                    words: request.words,
+                    algorithm: Some(String::from("EdDSA")),
+                    kid: Some(String::from("k1")),
+                    cose: Some(vec![0xd2, 0x84]),
+                    ..Default::default()
+                })),
+            }
+        }
+
+        // Signs each request as sign_words does, failing those with no words
+        async fn sign_batch(
+            &self,
+            request: tonic::Request<SignBatchRequest>,
+        ) -> Result<tonic::Response<SignBatchResponse>, tonic::Status> {
+            let mut results = Vec::new();
+            for request in request.into_inner().requests {
+                let result = match request.words.is_empty() {
+                    true => {
+                        sign_result::Result::Error(details::to_rpc_status(&details::with_details(
+                            Code::InvalidArgument,
+                            "no words",
+                            vec![details::bad_request(&[("words", "must not be empty")])],
+                        )))
+                    }
+                    false => sign_result::Result::Response(
+                        self.sign_words(tonic::Request::new(request))
+                            .await?
+                            .into_inner(),
+                    ),
+                };
+                results.push(SignResult {
+                    result: Some(result),
+                });
+            }
+            Ok(tonic::Response::new(SignBatchResponse { results }))
+        }
+
+        type SignStreamStream = rocket::futures::stream::Empty<Result<SignResult, tonic::Status>>;
+
+        async fn sign_stream(
+            &self,
+            _request: tonic::Request<tonic::Streaming<SignRequest>>,
+        ) -> Result<tonic::Response<Self::SignStreamStream>, tonic::Status> {
+            Err(tonic::Status::unimplemented("not mocked"))
+        }
+
+        async fn verify_words(
+            &self,
+            request: tonic::Request<VerifyRequest>,
+        ) -> Result<tonic::Response<VerifyResponse>, tonic::Status> {
+            let request = request.into_inner();
+            Ok(tonic::Response::new(match request.signature.as_str() {
+                "good" => VerifyResponse {
+                    valid: true,
+                    reason: None,
+                    kid: Some(String::from("k1")),
+                    version: Some(2),
+                },
+                _ => VerifyResponse {
+                    valid: false,
+                    reason: Some(String::from("signature does not match")),
+                    kid: None,
+                    version: None,
+                },
+            }))
+        }
+
+        async fn get_public_keys(
+            &self,
+            _request: tonic::Request<PublicKeysRequest>,
+        ) -> Result<tonic::Response<PublicKeysResponse>, tonic::Status> {
+            Ok(tonic::Response::new(PublicKeysResponse {
+                keys: vec![PublicKey {
+                    kid: String::from("k1"),
+                    alg: String::from("EdDSA"),
+                    kty: String::from("OKP"),
+                    crv: Some(String::from("Ed25519")),
+                    x: Some(String::from("11qYAYKxCrfVS_7TyWQHOg7hcvPapiMlrwIaaPcHURo")),
+                    ..Default::default()
+                }],
+            }))
+        }
+
+        async fn timestamp(
+            &self,
+            _request: tonic::Request<TimestampRequest>,
+        ) -> Result<tonic::Response<TimestampResponse>, tonic::Status> {
+            Err(tonic::Status::unimplemented("not mocked"))
+        }
+    }
+
+    fn serve_words(addr: SocketAddr, mock: MockPickWords) {
+        tokio::spawn(
+            Server::builder()
+                .add_service(PickWordsServer::new(mock))
+                .serve(addr),
+        );
+    }
+
+    // Starts pickle with a words service, returning the first response to
+    // `uri` once the words service is reachable
+    async fn get_when_ready<'c>(client: &'c Client, uri: &'static str) -> LocalResponse<'c> {
+        let mut response = client.get(uri).dispatch().await;
+        for _ in 0..100 {
+            if response.status() != Status::ServiceUnavailable {
+                break;
+            }
+            sleep(Duration::from_millis(100)).await;
+            response = client.get(uri).dispatch().await;
+        }
+        response
+    }
+
+    async fn client_with_words(mock: MockPickWords) -> Client {
+        let words_addr = unused_addr();
+        serve_words(words_addr, mock);
+        let figment = rocket::Config::figment()
+            .merge(("words-svc-addr", format!("http://{}", words_addr)))
+            .merge(("sign-svc-addr", format!("http://{}", unused_addr())));
+        Client::tracked(app(rocket::custom(figment))).await.unwrap()
+    }
+
+    // Starts pickle with a words service answering with the word, returning
+    // the request pickle last sent it
+    async fn client_recording_words(word: &'static str) -> (Client, Forwarded) {
+        let forwarded = Forwarded::default();
+        let client = client_with_words(MockPickWords::Recorded(word, forwarded.clone())).await;
+        (client, forwarded)
+    }
+
+    // The request pickle last sent the words service
+    fn forwarded(forwarded: &Forwarded) -> WordsRequest {
+        forwarded.lock().unwrap().clone().unwrap()
+    }
+
+    async fn client_with_signer() -> Client {
+        let sign_addr = unused_addr();
+        tokio::spawn(
+            Server::builder()
+                .add_service(SignWordsServer::new(MockSignWords))
+                .serve(sign_addr),
+        );
+        let figment = rocket::Config::figment()
+            .merge(("words-svc-addr", format!("http://{}", unused_addr())))
+            .merge(("sign-svc-addr", format!("http://{}", sign_addr)));
+        Client::tracked(app(rocket::custom(figment))).await.unwrap()
+    }
+
+    // Posts the json body to `uri`, returning the first response once the
+    // service it calls is reachable
+    async fn post_when_ready<'c>(
+        client: &'c Client,
+        uri: &'static str,
+        body: &'static str,
+    ) -> LocalResponse<'c> {
+        let post = || {
+            client
+                .post(uri)
+                .header(ContentType::JSON)
+                .body(body)
+                .dispatch()
+        };
+        let mut response = post().await;
+        for _ in 0..100 {
+            if response.status() != Status::ServiceUnavailable {
+                break;
+            }
+            sleep(Duration::from_millis(100)).await;
+            response = post().await;
+        }
+        response
+    }
+
+    fn unused_addr() -> SocketAddr {
+        TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+    }
+
+    #[rocket::async_test]
+    async fn words_before_upstream_starts() {
+        let words_addr = unused_addr();
+        let figment = rocket::Config::figment()
+            .merge(("words-svc-addr", format!("http://{}", words_addr)))
+            .merge(("sign-svc-addr", format!("http://{}", unused_addr())));
+        let client = Client::tracked(app(rocket::custom(figment))).await.unwrap();
+
+        let response = client.get("/api/v1.0/words").dispatch().await;
+        assert_eq!(response.status(), Status::ServiceUnavailable);
+        assert_eq!(
+            response.content_type(),
+            Some(ContentType::new("application", "problem+json"))
+        );
+
+        serve_words(words_addr, MockPickWords::Word("pickle"));
+
+        let response = get_when_ready(&client, "/api/v1.0/words?count=2").await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(
+            response.into_string().await.unwrap(),
+            r#"{"words":["pickle","pickle"]}"#
+        );
+
+        // signer never starts, so pickle as a whole is still not ready
+        let response = client.get("/healthz/ready").dispatch().await;
+        assert_eq!(response.status(), Status::ServiceUnavailable);
+    }
+
+    #[rocket::async_test]
+    async fn words_balanced_across_addresses() {
+        let (dill_addr, brine_addr) = (unused_addr(), unused_addr());
+        serve_words(dill_addr, MockPickWords::Word("dill"));
+        serve_words(brine_addr, MockPickWords::Word("brine"));
+        let figment = rocket::Config::figment()
+            .merge((
+                "words-svc-addr",
+                vec![
+                    format!("http://{}", dill_addr),
+                    format!("http://{}", brine_addr),
+                ],
+            ))
+            .merge(("sign-svc-addr", format!("http://{}", unused_addr())));
+        let client = Client::tracked(app(rocket::custom(figment))).await.unwrap();
+
+        let mut seen = Vec::new();
+        for _ in 0..200 {
+            let response = client.get("/api/v1.0/words?count=1").dispatch().await;
+            if response.status() == Status::Ok {
+                seen.push(response.into_string().await.unwrap());
+            } else {
+                sleep(Duration::from_millis(50)).await;
+            }
+            if seen.len() >= 20 {
+                break;
+            }
+        }
+        assert!(seen.contains(&String::from(r#"{"words":["dill"]}"#)));
+        assert!(seen.contains(&String::from(r#"{"words":["brine"]}"#)));
+    }
+
+    #[rocket::async_test]
+    async fn words_with_dns_address() {
+        let words_addr = unused_addr();
+        serve_words(words_addr, MockPickWords::Word("pickle"));
+        let figment = rocket::Config::figment()
+            .merge((
+                "words-svc-addr",
+                format!("http://localhost:{}", words_addr.port()),
+            ))
+            .merge(("sign-svc-addr", format!("http://{}", unused_addr())));
+        let client = Client::tracked(app(rocket::custom(figment))).await.unwrap();
+
+        let response = get_when_ready(&client, "/api/v1.0/words?count=1").await;
+        assert_eq!(response.status(), Status::Ok);
+    }
+
+    #[rocket::async_test]
+    async fn zero_dns_refresh_is_rejected() {
+        let figment = rocket::Config::figment()
+            .merge(("words-svc-addr", format!("http://{}", unused_addr())))
+            .merge(("sign-svc-addr", format!("http://{}", unused_addr())))
+            .merge(("upstream-dns-refresh", 0));
+        let e = Client::tracked(app(rocket::custom(figment)))
+            .await
+            .unwrap_err();
+        // Inspecting the error keeps it from panicking when dropped
+        assert!(matches!(
+            e.kind(),
+            rocket::error::ErrorKind::FailedFairings(_)
+        ));
+    }
+
+    #[rocket::async_test]
+    async fn words_with_seed() {
+        let (client, sent) = client_recording_words("pickle").await;
+
+        let response = get_when_ready(&client, "/api/v1.0/words?count=1&seed=42").await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(
+            response.into_string().await.unwrap(),
+            r#"{"words":["pickle"],"seed":42}"#
+        );
+        assert_eq!(forwarded(&sent).seed, Some(42));
+
+        // Without a seed words picks one
+        let response = client.get("/api/v1.0/words?count=1").dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(forwarded(&sent).seed, None);
+    }
+
+    #[rocket::async_test]
+    async fn words_with_accept_language() {
+        let (client, sent) = client_recording_words("cornichon").await;
+
+        // The header is only sent once words is reachable
+        get_when_ready(&client, "/api/v1.0/words?count=1").await;
+        let response = client
+            .get("/api/v1.0/words?count=1")
+            .header(Header::new("Accept-Language", "en;q=0.5, fr-CA, fr;q=0.8"))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.headers().get_one("Vary"), Some("Accept-Language"));
+        assert_eq!(
+            response.into_string().await.unwrap(),
+            r#"{"words":["cornichon"],"locale":"fr-CA"}"#
+        );
+        let request = forwarded(&sent);
+        assert_eq!(request.accept_locales, ["fr-CA", "fr", "en"]);
+        assert_eq!(request.locale, "");
+
+        // An explicit locale is forwarded beside the header's
+        let response = client
+            .get("/api/v1.0/words?count=1&locale=de")
+            .header(Header::new("Accept-Language", "es"))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        let request = forwarded(&sent);
+        assert_eq!(request.locale, "de");
+        assert_eq!(request.accept_locales, ["es"]);
+
+        // Failures vary too, as a cache may keep them
+        let response = client.get("/api/v1.0/words?format=xml").dispatch().await;
+        assert_eq!(response.status(), Status::BadRequest);
+        assert_eq!(response.headers().get_one("Vary"), Some("Accept-Language"));
+    }
+
+    #[rocket::async_test]
+    async fn words_as_passphrase() {
+        let client = client_with_words(MockPickWords::Word("dill")).await;
+
+        let response = get_when_ready(&client, "/api/v1.0/words?passphrase=true&separator=.").await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(
+            response.into_string().await.unwrap(),
+            r#"{"words":["dill","dill","dill","dill","dill","dill"],"passphrase":"dill.dill.dill.dill.dill.dill"}"#
+        );
+
+        let response = client
+            .get("/api/v1.0/words?passphrase=true&capitalization=camel")
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::BadRequest);
+    }
+
+    #[rocket::async_test]
+    async fn words_with_limits() {
+        let (client, sent) = client_recording_words("dill").await;
+
+        let response = get_when_ready(
+            &client,
+            "/api/v1.0/words?count=2&unique=true&min_length=4&max_length=8",
+        )
+        .await;
+        assert_eq!(response.status(), Status::Ok);
+        let request = forwarded(&sent);
+        assert_eq!(request.count, 2);
+        assert!(request.unique);
+        assert_eq!(request.min_length, Some(4));
+        assert_eq!(request.max_length, Some(8));
+
+        // Without limits words may repeat and have any length
+        let response = client.get("/api/v1.0/words?count=2").dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        let request = forwarded(&sent);
+        assert!(!request.unique);
+        assert_eq!(request.min_length, None);
+        assert_eq!(request.max_length, None);
+    }
+
+    #[rocket::async_test]
+    async fn words_from_pattern() {
+        let (client, sent) = client_recording_words("dill").await;
+
+        let response =
+            get_when_ready(&client, "/api/v1.0/words?pattern=adj-noun&alliterate=true").await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(
+            response.into_string().await.unwrap(),
+            r#"{"words":["dill","dill"]}"#
+        );
+        // The pattern sets the number of words
+        let request = forwarded(&sent);
+        assert_eq!(request.count, 0);
+        assert_eq!(request.pattern, "adj-noun");
+        assert!(request.alliterate);
+        assert!(!request.rhyme);
+
+        let response = client
+            .get("/api/v1.0/words?pattern=noun-verb&rhyme=true")
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        let request = forwarded(&sent);
+        assert_eq!(request.pattern, "noun-verb");
+        assert!(!request.alliterate);
+        assert!(request.rhyme);
+
+        // Without a pattern, 3 words by default
+        let response = client.get("/api/v1.0/words").dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        let request = forwarded(&sent);
+        assert_eq!(request.count, 3);
+        assert_eq!(request.pattern, "");
+    }
+
+    #[test]
+    fn parse_accept_language() {
+        assert_eq!(
+            accept_language(Some("en;q=0.5, fr-CA, *;q=0.1, de;q=0, fr;q=0.8, es")),
+            vec!["fr-CA", "es", "fr", "en"]
+        );
+        assert_eq!(accept_language(Some("da, en-gb;q=bad")), vec!["da"]);
+        assert!(accept_language(None).is_empty());
+    }
+
+    #[rocket::async_test]
+    async fn words_invalid_argument_is_bad_request() {
+        let client = client_with_words(MockPickWords::Fail(Code::InvalidArgument)).await;
+
+        let response = get_when_ready(&client, "/api/v1.0/words").await;
+        assert_eq!(response.status(), Status::BadRequest);
+        assert_eq!(
+            response.content_type(),
+            Some(ContentType::new("application", "problem+json"))
+        );
+        let problem: rocket::serde::json::Value =
+            rocket::serde::json::from_str(&response.into_string().await.unwrap()).unwrap();
+        assert_eq!(problem["status"], 400);
+        assert_eq!(problem["title"], "Bad Request");
+        assert_eq!(problem["detail"], "words service: mock failure");
+        assert_eq!(problem["instance"], "/api/v1.0/words");
+    }
+
+    #[rocket::async_test]
+    async fn words_error_details_in_problem() {
+        let client = client_with_words(MockPickWords::Detailed(
+            Code::InvalidArgument,
+            vec![
+                details::error_info("COUNT_TOO_LARGE", "dill.PickWords", &[]),
+                details::bad_request(&[("count", "must be at most 255")]),
+            ],
+        ))
+        .await;
+
+        let response = get_when_ready(&client, "/api/v1.0/words").await;
+        assert_eq!(response.status(), Status::BadRequest);
+        let problem: rocket::serde::json::Value =
+            rocket::serde::json::from_str(&response.into_string().await.unwrap()).unwrap();
+        assert_eq!(problem["errorInfo"]["reason"], "COUNT_TOO_LARGE");
+        assert_eq!(problem["errorInfo"]["domain"], "dill.PickWords");
+        assert_eq!(problem["invalidParams"][0]["name"], "count");
+        assert_eq!(problem["invalidParams"][0]["reason"], "must be at most 255");
+    }
+
+    #[rocket::async_test]
+    async fn words_retry_info_sets_retry_after() {
+        let client = client_with_words(MockPickWords::Detailed(
+            Code::ResourceExhausted,
+            vec![details::retry_after(Duration::from_secs(3))],
+        ))
+        .await;
+
+        let response = get_when_ready(&client, "/api/v1.0/words").await;
+        assert_eq!(response.status(), Status::TooManyRequests);
+        assert_eq!(response.headers().get_one("Retry-After"), Some("3"));
+    }
+
+    #[rocket::async_test]
+    async fn sign_words_with_ttl() {
+        let client = client_with_signer().await;
+
+        let response =
+            post_when_ready(&client, "/api/v1.0/sign?ttl=30", r#"{"words":["dill"]}"#).await;
+        assert_eq!(response.status(), Status::Ok);
+        let words: Words =
+            rocket::serde::json::from_str(&response.into_string().await.unwrap()).unwrap();
+        assert_eq!(words.expires_at, Some(30_001));
+    }
+
+    #[rocket::async_test]
+    async fn sign_words_with_timestamp_token() {
+        let client = client_with_signer().await;
+
+        let response =
+            post_when_ready(&client, "/api/v1.0/sign?tst=true", r#"{"words":["dill"]}"#).await;
+        assert_eq!(response.status(), Status::Ok);
+        let words: Words =
+            rocket::serde::json::from_str(&response.into_string().await.unwrap()).unwrap();
+        assert_eq!(words.timestamp_token, Some(String::from("MAA=")));
+    }
+
+    #[rocket::async_test]
+    async fn sign_words_as_jws() {
+        let client = client_with_signer().await;
+
+        let response = post_when_ready(
+            &client,
+            "/api/v1.0/sign?format=jws",
+            r#"{"words":["dill"]}"#,
+        )
+        .await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(
+            response.into_string().await.unwrap(),
+            r#"{"words":["dill"],"algorithm":"EdDSA","kid":"k1","jws":"e30.e30.Z29vZA"}"#
+        );
+
+        let response = post_when_ready(
+            &client,
+            "/api/v1.0/sign?format=cose",
+            r#"{"words":["dill"]}"#,
+        )
+        .await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(
+            response.into_string().await.unwrap(),
+            r#"{"words":["dill"],"algorithm":"EdDSA","kid":"k1","cose":"0oQ="}"#
+        );
+
+        let response = post_when_ready(
+            &client,
+            "/api/v1.0/sign?format=xml",
+            r#"{"words":["dill"]}"#,
+        )
+        .await;
+        assert_eq!(response.status(), Status::BadRequest);
+        let problem: rocket::serde::json::Value =
+            rocket::serde::json::from_str(&response.into_string().await.unwrap()).unwrap();
+        assert_eq!(problem["invalidParams"][0]["name"], "format");
+    }
+
+    #[rocket::async_test]
+    async fn sign_batch_of_words() {
+        let client = client_with_signer().await;
+
+        let response = post_when_ready(
+            &client,
+            "/api/v1.0/sign/batch?ttl=30",
+            r#"[{"words":["dill"]},{"words":[]},{"words":["brine","pickle"]}]"#,
+        )
+        .await;
+        assert_eq!(response.status(), Status::Ok);
+        let results: rocket::serde::json::Value =
+            rocket::serde::json::from_str(&response.into_string().await.unwrap()).unwrap();
+        assert_eq!(results[0]["signed"]["words"][0], "dill");
+        assert_eq!(results[0]["signed"]["expires_at"], 30_001);
+        assert_eq!(results[1]["error"]["status"], 400);
+        assert_eq!(results[1]["error"]["invalidParams"][0]["name"], "words");
+        assert_eq!(results[2]["signed"]["words"][1], "pickle");
+        assert!(results[2]["error"].is_null());
+    }
+
+    #[rocket::async_test]
+    async fn verify_signed_words() {
+        let client = client_with_signer().await;
+
+        let response = post_when_ready(
+            &client,
+            "/api/v1.0/verify",
+            r#"{"words":["dill"],"timestamp":1,"signature":"good"}"#,
+        )
+        .await;
+        assert_eq!(response.status(), Status::Ok);
+        let verification: Verification =
+            rocket::serde::json::from_str(&response.into_string().await.unwrap()).unwrap();
+        assert_eq!(
+            verification,
+            Verification {
+                valid: true,
+                reason: None,
+                kid: Some(String::from("k1")),
+                version: Some(2),
+            }
+        );
+
+        let response = post_when_ready(
+            &client,
+            "/api/v1.0/verify",
+            r#"{"words":["dill"],"timestamp":1,"signature":"bad","kid":"k1"}"#,
+        )
+        .await;
+        assert_eq!(response.status(), Status::Ok);
+        let verification: Verification =
+            rocket::serde::json::from_str(&response.into_string().await.unwrap()).unwrap();
+        assert!(!verification.valid);
+        assert_eq!(
+            verification.reason,
+            Some(String::from("signature does not match"))
+        );
+    }
+
+    #[rocket::async_test]
+    async fn verify_without_signature_is_bad_request() {
+        let client = client_with_signer().await;
+
+        let response = post_when_ready(&client, "/api/v1.0/verify", r#"{"words":["dill"]}"#).await;
+        assert_eq!(response.status(), Status::BadRequest);
+        let problem: rocket::serde::json::Value =
+            rocket::serde::json::from_str(&response.into_string().await.unwrap()).unwrap();
+        assert_eq!(problem["invalidParams"][0]["name"], "timestamp");
+        assert_eq!(problem["invalidParams"][1]["name"], "signature");
+    }
+
+    #[rocket::async_test]
+    async fn words_internal_is_server_error() {
+        let client = client_with_words(MockPickWords::Fail(Code::Internal)).await;
+
+        let response = get_when_ready(&client, "/api/v1.0/words").await;
+        assert_eq!(response.status(), Status::InternalServerError);
+    }
+
+    #[rocket::async_test]
+    async fn words_stalled_is_gateway_timeout() {
+        let client = client_with_words(MockPickWords::Stall).await;
+
+        let response = get_when_ready(&client, "/api/v1.0/words").await;
+        assert_eq!(response.status(), Status::GatewayTimeout);
+    }
+
+    #[rocket::async_test]
+    async fn malformed_body_is_problem() {
+        let client = client_with_words(MockPickWords::Word("pickle")).await;
+
+        let response = client
+            .post("/api/v1.0/sign")
+            .header(ContentType::JSON)
+            .body("{\"words\": 7}")
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+        assert_eq!(
+            response.content_type(),
+            Some(ContentType::new("application", "problem+json"))
+        );
+    }
+
+    #[rocket::async_test]
+    async fn openapi_documents_problems() {
+        let client = client_with_words(MockPickWords::Word("pickle")).await;
+
+        let response = client.get("/api/v1.0/openapi.json").dispatch().await;
+        let openapi: rocket::serde::json::Value =
+            rocket::serde::json::from_str(&response.into_string().await.unwrap()).unwrap();
+        let responses = &openapi["paths"]["/words"]["get"]["responses"];
+        for code in &["200", "400", "429", "500", "503", "504"] {
+            assert!(responses.get(code).is_some(), "missing {} response", code);
+        }
+        assert!(responses["503"]["content"]
+            .get("application/problem+json")
+            .is_some());
+    }
 
     #[test]
     fn words_from_wordsresponse() {
@@ -292,7 +1615,47 @@ mod tests {
                 ],
                 timestamp: None,
                 signature: None,
+                algorithm: None,
+                kid: None,
+                version: None,
+                jws: None,
+                cose: None,
+                expires_at: None,
+                timestamp_token: None,
+                seed: None,
+                locale: None,
+                passphrase: None,
+                entropy_bits: None,
             }
         )
     }
+
+    #[rocket::async_test]
+    async fn jwks_with_caching_headers() {
+        let client = client_with_signer().await;
+
+        let response = get_when_ready(&client, "/.well-known/jwks.json").await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.content_type(), Some(ContentType::JSON));
+        assert_eq!(
+            response.headers().get_one("Cache-Control"),
+            Some("public, max-age=30")
+        );
+        let etag = response.headers().get_one("ETag").unwrap().to_string();
+        let jwks: rocket::serde::json::Value =
+            rocket::serde::json::from_str(&response.into_string().await.unwrap()).unwrap();
+        assert_eq!(jwks["keys"][0]["kid"], "k1");
+        assert_eq!(jwks["keys"][0]["kty"], "OKP");
+        assert_eq!(jwks["keys"][0]["crv"], "Ed25519");
+        assert_eq!(jwks["keys"][0]["use"], "sig");
+        assert!(jwks["keys"][0].get("n").is_none());
+
+        let response = client
+            .get("/.well-known/jwks.json")
+            .header(Header::new("If-None-Match", etag.clone()))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::NotModified);
+        assert_eq!(response.headers().get_one("ETag"), Some(etag.as_str()));
+    }
 }
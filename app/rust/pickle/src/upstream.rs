@@ -0,0 +1,384 @@
+//
+// Upstream wraps the grpc channel used to reach one of pickle's backing
+// services. Channels connect lazily, so pickle can start before the services
+// it depends on. A background task probes each service with exponential
+// backoff and tracks whether it is currently reachable.
+//
+// A service may be configured with several addresses. Requests are balanced
+// across all of them, and addresses given as DNS names are periodically
+// re-resolved so endpoints can come and go without restarting pickle.
+//
+
+use dill::details;
+use log::{info, warn};
+use rocket::serde::{Deserialize, Deserializer};
+use std::{
+    collections::{BTreeMap, HashMap},
+    error::Error as StdError,
+    net::IpAddr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+use tokio::{
+    net::lookup_host,
+    sync::{mpsc::Sender, Notify},
+    time::sleep,
+};
+use tonic::{
+    transport::{Channel, Endpoint, Uri},
+    Code, Status,
+};
+use tower::discover::Change;
+
+/// Deadline for calls to an upstream service
+pub const CALL_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Deadline for calls that sign a batch of requests
+pub const BATCH_CALL_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Timeout for establishing a connection to an endpoint
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(1);
+
+// Bounds for the delay between connection attempts
+const INITIAL_BACKOFF: Duration = Duration::from_millis(100);
+const MAX_BACKOFF: Duration = Duration::from_secs(10);
+
+// Capacity of the queue of endpoint changes sent to a balanced channel
+const DISCOVERY_CAPACITY: usize = 64;
+
+/// One or more addresses for an upstream service. Deserializes from either a
+/// single uri string or a list of them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Addrs(pub Vec<String>);
+
+impl<'de> Deserialize<'de> for Addrs {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(crate = "rocket::serde", untagged)]
+        enum OneOrMany {
+            One(String),
+            Many(Vec<String>),
+        }
+
+        Ok(match OneOrMany::deserialize(deserializer)? {
+            OneOrMany::One(addr) => Addrs(vec![addr]),
+            OneOrMany::Many(addrs) => Addrs(addrs),
+        })
+    }
+}
+
+// A configured address: either a fixed ip endpoint, or a DNS name that is
+// resolved to the set of endpoints it currently points at.
+#[derive(Debug)]
+enum Target {
+    Ip(Box<Endpoint>),
+    Dns {
+        scheme: String,
+        host: String,
+        port: u16,
+    },
+}
+
+pub struct Upstream {
+    name: &'static str,
+    channel: Channel,
+    health: Arc<Health>,
+}
+
+struct Health {
+    ready: AtomicBool,
+    lost: Notify,
+    endpoints: Mutex<Vec<Endpoint>>,
+}
+
+impl Upstream {
+    /// Creates a lazily connected channel balanced across the service's
+    /// addresses and starts probing it in the background. Must be called from
+    /// within a tokio runtime.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Service name used in logs and error responses
+    /// * `addrs` - Service uris, e.g. `http://words-svc:9090`
+    /// * `dns_refresh` - Interval between re-resolving DNS names in `addrs`
+    pub fn new(
+        name: &'static str,
+        addrs: &Addrs,
+        dns_refresh: Duration,
+    ) -> Result<Upstream, Box<dyn StdError + Send + Sync>> {
+        if addrs.0.is_empty() {
+            return Err(format!("no addresses configured for {} service", name).into());
+        }
+        let targets = addrs
+            .0
+            .iter()
+            .map(|addr| Target::parse(addr))
+            .collect::<Result<Vec<Target>, _>>()?;
+
+        let health = Arc::new(Health {
+            ready: AtomicBool::new(false),
+            lost: Notify::new(),
+            endpoints: Mutex::new(Vec::new()),
+        });
+
+        let channel = match targets.iter().all(|t| matches!(t, Target::Ip(_))) {
+            true => {
+                let endpoints = targets
+                    .into_iter()
+                    .filter_map(|t| match t {
+                        Target::Ip(endpoint) => Some(*endpoint),
+                        _ => None,
+                    })
+                    .collect::<Vec<Endpoint>>();
+                *health.endpoints.lock().unwrap() = endpoints.clone();
+                Channel::balance_list(endpoints.into_iter())
+            }
+            false => {
+                let (channel, changes) = Channel::balance_channel(DISCOVERY_CAPACITY);
+                tokio::spawn(discover(
+                    name,
+                    targets,
+                    changes,
+                    health.clone(),
+                    dns_refresh,
+                ));
+                channel
+            }
+        };
+        tokio::spawn(monitor(name, health.clone()));
+
+        Ok(Upstream {
+            name,
+            channel,
+            health,
+        })
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn channel(&self) -> Channel {
+        self.channel.clone()
+    }
+
+    /// Whether the most recent probe of the service succeeded
+    pub fn is_ready(&self) -> bool {
+        self.health.ready.load(Ordering::Relaxed)
+    }
+
+    /// Records the outcome of a failed call. Returns true if the failure means
+    /// the service could not be reached, in which case it is marked unready
+    /// and the background task starts probing it again.
+    pub fn check_failure(&self, status: &Status) -> bool {
+        if !is_unavailable(status) {
+            return false;
+        }
+        if self.health.ready.swap(false, Ordering::Relaxed) {
+            warn!("{} service lost: {}", self.name, status);
+            self.health.lost.notify_one();
+        }
+        true
+    }
+}
+
+impl Target {
+    fn parse(addr: &str) -> Result<Target, Box<dyn StdError + Send + Sync>> {
+        let uri = addr.parse::<Uri>()?;
+        let scheme = uri.scheme_str().unwrap_or("http").to_string();
+        let host = match uri.host() {
+            Some(host) => host.trim_start_matches('[').trim_end_matches(']'),
+            None => return Err(format!("missing host in {}", addr).into()),
+        };
+        if host.parse::<IpAddr>().is_ok() {
+            return Ok(Target::Ip(Box::new(endpoint(addr.to_string())?)));
+        }
+        let port = match (uri.port_u16(), scheme.as_str()) {
+            (Some(port), _) => port,
+            (None, "https") => 443,
+            (None, _) => 80,
+        };
+        Ok(Target::Dns {
+            scheme,
+            host: host.to_string(),
+            port,
+        })
+    }
+
+    // Returns the endpoints for the target, keyed by uri
+    async fn resolve(&self) -> Result<HashMap<String, Endpoint>, Box<dyn StdError + Send + Sync>> {
+        let mut endpoints = HashMap::new();
+        match self {
+            Target::Ip(endpoint) => {
+                endpoints.insert(endpoint.uri().to_string(), *endpoint.clone());
+            }
+            Target::Dns { scheme, host, port } => {
+                for addr in lookup_host((host.as_str(), *port)).await? {
+                    let uri = format!("{}://{}", scheme, addr);
+                    endpoints.insert(uri.clone(), endpoint(uri)?);
+                }
+            }
+        }
+        Ok(endpoints)
+    }
+}
+
+fn endpoint(uri: String) -> Result<Endpoint, tonic::transport::Error> {
+    Ok(Endpoint::new(uri)?.connect_timeout(CONNECT_TIMEOUT))
+}
+
+// tonic reports connection failures on a lazy channel as transport errors
+// rather than as Unavailable.
+fn is_unavailable(status: &Status) -> bool {
+    status.code() == Code::Unavailable || details::is_transport_error(status)
+}
+
+// Resolves the targets, sending endpoint changes to the balanced channel,
+// then waits for the refresh interval and resolves them again. A target that
+// fails to resolve keeps its previous endpoints, and the last endpoint of a
+// service is never removed.
+async fn discover(
+    name: &'static str,
+    targets: Vec<Target>,
+    changes: Sender<Change<String, Endpoint>>,
+    health: Arc<Health>,
+    refresh: Duration,
+) {
+    let mut known: Vec<HashMap<String, Endpoint>> = vec![HashMap::new(); targets.len()];
+    loop {
+        let mut resolved = Vec::with_capacity(targets.len());
+        for (target, previous) in targets.iter().zip(&known) {
+            match target.resolve().await {
+                Ok(endpoints) => resolved.push(endpoints),
+                Err(e) => {
+                    warn!("Failed to resolve {} service {:?}: {}", name, target, e);
+                    resolved.push(previous.clone());
+                }
+            }
+        }
+
+        let current = flatten(&known);
+        let next = flatten(&resolved);
+        if next.is_empty() {
+            warn!("No endpoints found for {} service", name);
+        } else {
+            if next.keys().ne(current.keys()) {
+                for uri in current.keys().filter(|uri| !next.contains_key(*uri)) {
+                    info!("Removing {} service endpoint {}", name, uri);
+                    if changes.send(Change::Remove(uri.clone())).await.is_err() {
+                        return;
+                    }
+                }
+                for (uri, endpoint) in next.iter().filter(|(uri, _)| !current.contains_key(*uri)) {
+                    info!("Adding {} service endpoint {}", name, uri);
+                    let change = Change::Insert(uri.clone(), endpoint.clone());
+                    if changes.send(change).await.is_err() {
+                        return;
+                    }
+                }
+                *health.endpoints.lock().unwrap() = next.values().cloned().collect();
+            }
+            known = resolved;
+        }
+
+        sleep(refresh).await;
+    }
+}
+
+fn flatten(endpoints: &[HashMap<String, Endpoint>]) -> BTreeMap<String, Endpoint> {
+    endpoints
+        .iter()
+        .flat_map(|e| {
+            e.iter()
+                .map(|(uri, endpoint)| (uri.clone(), endpoint.clone()))
+        })
+        .collect()
+}
+
+// Probes the service until one of its endpoints accepts a connection, backing
+// off exponentially between attempts, then waits until a caller reports the
+// service lost.
+async fn monitor(name: &'static str, health: Arc<Health>) {
+    loop {
+        let mut backoff = INITIAL_BACKOFF;
+        while !probe(name, &health).await {
+            sleep(backoff).await;
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+        }
+        health.ready.store(true, Ordering::Relaxed);
+        health.lost.notified().await;
+    }
+}
+
+async fn probe(name: &'static str, health: &Health) -> bool {
+    let endpoints = health.endpoints.lock().unwrap().clone();
+    if endpoints.is_empty() {
+        warn!("{} service has no endpoints yet", name);
+    }
+    for endpoint in &endpoints {
+        match endpoint.connect().await {
+            Ok(_) => {
+                info!("{} service ready at {}", name, endpoint.uri());
+                return true;
+            }
+            Err(e) => warn!("{} service unavailable at {}: {}", name, endpoint.uri(), e),
+        }
+    }
+    false
+}
+
+// Unit tests
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use rocket::figment::{providers::Serialized, Figment};
+
+    #[test]
+    fn unavailable_statuses() {
+        assert!(is_unavailable(&Status::unavailable("down")));
+        assert!(is_unavailable(&Status::unknown("transport error")));
+        assert!(!is_unavailable(&Status::unknown("something else")));
+        assert!(!is_unavailable(&Status::invalid_argument("bad")));
+    }
+
+    #[test]
+    fn addrs_from_string_or_list() {
+        let figment = Figment::from(Serialized::default("one", "http://words-svc:9090")).merge(
+            Serialized::default("many", vec!["http://a:1", "http://b:2"]),
+        );
+        assert_eq!(
+            figment.extract_inner::<Addrs>("one").unwrap(),
+            Addrs(vec![String::from("http://words-svc:9090")])
+        );
+        assert_eq!(
+            figment.extract_inner::<Addrs>("many").unwrap(),
+            Addrs(vec![String::from("http://a:1"), String::from("http://b:2")])
+        );
+    }
+
+    #[test]
+    fn parse_targets() {
+        match Target::parse("http://10.0.0.7:9090").unwrap() {
+            Target::Ip(endpoint) => assert_eq!(endpoint.uri().port_u16(), Some(9090)),
+            t => panic!("expected ip target, got {:?}", t),
+        }
+        assert!(matches!(
+            Target::parse("http://[::1]:9090").unwrap(),
+            Target::Ip(_)
+        ));
+        match Target::parse("https://signing-svc").unwrap() {
+            Target::Dns { scheme, host, port } => {
+                assert_eq!(scheme, "https");
+                assert_eq!(host, "signing-svc");
+                assert_eq!(port, 443);
+            }
+            t => panic!("expected dns target, got {:?}", t),
+        }
+        assert!(Target::parse("/no/host").is_err());
+    }
+}
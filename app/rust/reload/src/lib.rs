@@ -0,0 +1,185 @@
+//
+// Services reload configuration loaded from files, like signer's keyring and
+// words' dictionaries, without a restart. A reload happens when the service
+// receives SIGHUP, or when a poll finds that a file under the configuration's
+// paths was added, removed or changed. A reload that fails leaves the current
+// configuration in place.
+//
+// The configuration is shared as an Arc swapped under a lock, so requests
+// keep the configuration they started with while a reload replaces it.
+//
+
+use log::{error, info};
+use std::{
+    fmt::Display,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime},
+};
+use tokio::{
+    signal::unix::{signal, SignalKind},
+    time::interval,
+};
+
+/// The current configuration, replaced as a whole when it's reloaded
+pub struct Shared<T>(RwLock<Arc<T>>);
+
+impl<T> Shared<T> {
+    pub fn new(config: T) -> Shared<T> {
+        Shared(RwLock::new(Arc::new(config)))
+    }
+
+    /// Returns the current configuration
+    pub fn current(&self) -> Arc<T> {
+        match self.0.read() {
+            Ok(config) => config.clone(),
+            // The lock is only held to swap an Arc, so can't be left inconsistent
+            Err(poisoned) => poisoned.into_inner().clone(),
+        }
+    }
+
+    fn replace(&self, config: T) {
+        let config = Arc::new(config);
+        match self.0.write() {
+            Ok(mut current) => *current = config,
+            Err(poisoned) => *poisoned.into_inner() = config,
+        }
+    }
+
+    /// Reloads the configuration, keeping the current one if the new one
+    /// fails to load
+    pub fn reload<E>(&self, load: impl FnOnce() -> Result<T, E>) -> Result<(), E> {
+        self.replace(load()?);
+        Ok(())
+    }
+}
+
+/// Parses the seconds between checks for changed files, which must be at
+/// least 1 as tokio's interval rejects a zero period
+pub fn parse_poll_interval(seconds: &str) -> Result<u64, String> {
+    match seconds.parse::<u64>() {
+        Ok(0) => Err(String::from("must be at least 1 second")),
+        Ok(seconds) => Ok(seconds),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Reloads the configuration on SIGHUP, or when a file under its paths
+/// changes
+///
+/// # Arguments
+///
+/// * `what` - What the configuration is, for logging
+/// * `paths` - Files and directories the configuration is loaded from
+/// * `shared` - Configuration to replace on reload
+/// * `poll` - Interval between checks for changed files
+/// * `load` - Loads the configuration, which may block
+pub async fn watch<T, E, L>(
+    what: &'static str,
+    paths: Vec<PathBuf>,
+    shared: Arc<Shared<T>>,
+    poll: Duration,
+    load: L,
+) where
+    T: Send + Sync + 'static,
+    E: Display + Send + 'static,
+    L: Fn() -> Result<T, E> + Clone + Send + 'static,
+{
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(hangup) => Some(hangup),
+        Err(e) => {
+            error!(
+                "Unable to listen for SIGHUP, {} reload on change only: {}",
+                what, e
+            );
+            None
+        }
+    };
+    let mut ticks = interval(poll);
+    let mut last = snapshot(&paths).ok();
+    loop {
+        let signalled = tokio::select! {
+            Some(_) = async { hangup.as_mut()?.recv().await } => true,
+            _ = ticks.tick() => false,
+        };
+        let current = snapshot(&paths).ok();
+        if !signalled && current == last {
+            continue;
+        }
+        last = current;
+        info!("Reloading {}", what);
+        // Loading may read many files, open a token or a network client
+        let (reload_shared, reload_load) = (shared.clone(), load.clone());
+        let reloaded = tokio::task::spawn_blocking(move || reload_shared.reload(reload_load)).await;
+        match reloaded {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => error!("Failed to reload {0}, keeping current {0}: {1}", what, e),
+            Err(e) => error!("Failed to reload {0}, keeping current {0}: {1}", what, e),
+        }
+    }
+}
+
+// Names, modification times and sizes of the files under the paths
+fn snapshot(paths: &[PathBuf]) -> io::Result<Vec<(PathBuf, SystemTime, u64)>> {
+    let mut files = Vec::new();
+    for path in paths {
+        files_under(path, &mut files)?;
+    }
+    files.sort();
+    files
+        .into_iter()
+        .map(|file| {
+            let metadata = fs::metadata(&file)?;
+            Ok((file, metadata.modified()?, metadata.len()))
+        })
+        .collect()
+}
+
+// Adds the path to the files, or the files under it if it's a directory
+fn files_under(path: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    if !fs::metadata(path)?.is_dir() {
+        files.push(path.to_path_buf());
+        return Ok(());
+    }
+    for entry in fs::read_dir(path)? {
+        files_under(&entry?.path(), files)?;
+    }
+    Ok(())
+}
+
+// Unit tests
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn poll_interval_is_positive() {
+        assert_eq!(parse_poll_interval("10"), Ok(10));
+        assert!(parse_poll_interval("0").is_err());
+        assert!(parse_poll_interval("-1").is_err());
+    }
+
+    #[test]
+    fn reload_keeps_config_on_failure() {
+        let shared = Shared::new(1);
+        shared.reload(|| Ok::<_, String>(2)).unwrap();
+        assert_eq!(*shared.current(), 2);
+        assert!(shared.reload(|| Err(String::from("bad"))).is_err());
+        assert_eq!(*shared.current(), 2);
+    }
+
+    #[test]
+    fn snapshot_sees_nested_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths = [dir.path().to_path_buf()];
+        fs::create_dir(dir.path().join("nested")).unwrap();
+        fs::write(dir.path().join("a"), "a").unwrap();
+        assert_eq!(snapshot(&paths).unwrap().len(), 1);
+        fs::write(dir.path().join("nested").join("b"), "b").unwrap();
+        assert_eq!(snapshot(&paths).unwrap().len(), 2);
+        assert!(snapshot(&[dir.path().join("missing")]).is_err());
+    }
+}
@@ -0,0 +1,619 @@
+//
+// The audit log is an append-only record of every signature signer makes, so
+// misuse of a signing key leaves a trace. Each entry holds a hash of the
+// signed message, the time it was signed, the kid of the key and the trace id
+// of the request. The entries are the leaves of a Merkle tree built as RFC
+// 9162 describes, so an auditor holding a signed tree head can be proven that
+// an entry is in the log, and that a later tree head extends an earlier one
+// rather than rewriting it.
+//
+// The log is a file of JSON lines. An entry is appended and synced to disk
+// before the signature it records is returned, and the whole file is read
+// back when signer starts; a last line cut short by a crash or a failed write
+// is truncated, as its signature was never returned. The root of every complete subtree is kept in
+// memory as entries are appended, so tree heads and proofs only hash the
+// O(log n) subtrees that aren't complete.
+//
+
+use crate::message::{self, Version};
+use base64::{decode, encode};
+use log::warn;
+use ring::digest::{digest, Context, SHA256};
+use rocket::serde::{Deserialize, Serialize};
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    path::Path,
+};
+
+// Start the data hashed for each entry and signed for each tree head, so
+// neither can be mistaken for the other or for a signed message
+const ENTRY_DOMAIN_TAG: &[u8] = b"dill.AuditLog.entry.v1\0";
+const TREE_HEAD_DOMAIN_TAG: &[u8] = b"dill.AuditLog.v1\0";
+
+// Prefixes separating leaf hashes from interior node hashes
+const LEAF_PREFIX: u8 = 0;
+const NODE_PREFIX: u8 = 1;
+
+pub type Hash = [u8; 32];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    /// SHA-256 of the version 3 message for the signed words
+    pub words_hash: Vec<u8>,
+    /// Milliseconds since the unix epoch the words were signed at
+    pub timestamp: u64,
+    /// Id of the signing key
+    pub kid: String,
+    /// Hex trace id of the signing request, empty when it wasn't traced
+    pub trace_id: String,
+}
+
+// An entry as a line of the log file
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct Line {
+    words_hash: String,
+    timestamp: u64,
+    kid: String,
+    trace_id: String,
+}
+
+impl Entry {
+    /// Creates the entry recording a signature over the words
+    ///
+    /// # Arguments
+    ///
+    /// * `words` - Words that were signed
+    /// * `timestamp` - Milliseconds since the unix epoch
+    /// * `expires_at` - Milliseconds since the unix epoch the signature expires at
+    /// * `kid` - Id of the signing key
+    /// * `trace_id` - Hex trace id of the signing request
+    pub fn new(
+        words: &[String],
+        timestamp: u64,
+        expires_at: u64,
+        kid: &str,
+        trace_id: &str,
+    ) -> Entry {
+        let message = message::encode(Version::Expiring, words, timestamp, Some(expires_at));
+        Entry {
+            words_hash: digest(&SHA256, &message).as_ref().to_vec(),
+            timestamp,
+            kid: kid.to_string(),
+            trace_id: trace_id.to_string(),
+        }
+    }
+
+    /// The entry's leaf hash in the Merkle tree
+    pub fn leaf_hash(&self) -> Hash {
+        let mut context = Context::new(&SHA256);
+        context.update(&[LEAF_PREFIX]);
+        context.update(ENTRY_DOMAIN_TAG);
+        context.update(&self.words_hash);
+        context.update(&self.timestamp.to_be_bytes());
+        for field in &[&self.kid, &self.trace_id] {
+            context.update(&(field.len() as u32).to_be_bytes());
+            context.update(field.as_bytes());
+        }
+        to_hash(context.finish().as_ref())
+    }
+
+    fn to_line(&self) -> Line {
+        Line {
+            words_hash: encode(&self.words_hash),
+            timestamp: self.timestamp,
+            kid: self.kid.clone(),
+            trace_id: self.trace_id.clone(),
+        }
+    }
+
+    fn from_line(line: Line) -> Result<Entry, String> {
+        Ok(Entry {
+            words_hash: decode(&line.words_hash).map_err(|e| e.to_string())?,
+            timestamp: line.timestamp,
+            kid: line.kid,
+            trace_id: line.trace_id,
+        })
+    }
+}
+
+pub struct AuditLog {
+    // None for a log kept only in memory
+    file: Option<File>,
+    // Bytes of the file holding complete entries
+    length: u64,
+    entries: Vec<Entry>,
+    // levels[h][i] is the root of the complete subtree of 2^h leaves from
+    // leaf i * 2^h, so levels[0] holds the leaf hashes
+    levels: Vec<Vec<Hash>>,
+}
+
+impl AuditLog {
+    /// Opens the log file, creating it if it doesn't exist, and reads the
+    /// entries already in it
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - Path of the log file
+    pub fn open(path: &Path) -> io::Result<AuditLog> {
+        let mut log = AuditLog::in_memory();
+        let mut length = 0;
+        if path.exists() {
+            let mut reader = BufReader::new(File::open(path)?);
+            let mut number = 0;
+            let mut line = Vec::new();
+            loop {
+                line.clear();
+                let read = reader.read_until(b'\n', &mut line)?;
+                if read == 0 {
+                    break;
+                }
+                if line.last() != Some(&b'\n') {
+                    warn!(
+                        "Truncating the incomplete last line {} of audit log {}",
+                        number + 1,
+                        path.display()
+                    );
+                    OpenOptions::new().write(true).open(path)?.set_len(length)?;
+                    break;
+                }
+                number += 1;
+                length += read as u64;
+                let invalid = |reason: String| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("line {}: {}", number, reason),
+                    )
+                };
+                let line: Line =
+                    serde_json::from_slice(&line).map_err(|e| invalid(e.to_string()))?;
+                log.push(Entry::from_line(line).map_err(invalid)?);
+            }
+        }
+        log.file = Some(OpenOptions::new().create(true).append(true).open(path)?);
+        log.length = length;
+        Ok(log)
+    }
+
+    /// Creates an empty log that isn't written to a file
+    pub fn in_memory() -> AuditLog {
+        AuditLog {
+            file: None,
+            length: 0,
+            entries: Vec::new(),
+            levels: Vec::new(),
+        }
+    }
+
+    /// Appends the entry, returning its index once it is synced to disk. An
+    /// entry that fails to be written is cut off, so the file stays a list
+    /// of complete entries.
+    pub fn append(&mut self, entry: Entry) -> io::Result<u64> {
+        if let Some(file) = self.file.as_mut() {
+            // An earlier append may have failed to cut off its line
+            if file.metadata()?.len() != self.length {
+                file.set_len(self.length)?;
+            }
+            let mut line = serde_json::to_vec(&entry.to_line())?;
+            line.push(b'\n');
+            if let Err(e) = file.write_all(&line).and_then(|()| file.sync_data()) {
+                // If this fails too, the next append tries again
+                let _ = file.set_len(self.length);
+                return Err(e);
+            }
+            self.length += line.len() as u64;
+        }
+        self.push(entry);
+        Ok(self.size() - 1)
+    }
+
+    // Adds the entry's leaf, and the roots of the subtrees it completes
+    fn push(&mut self, entry: Entry) {
+        let mut hash = entry.leaf_hash();
+        self.entries.push(entry);
+        for height in 0.. {
+            if self.levels.len() == height {
+                self.levels.push(Vec::new());
+            }
+            let level = &mut self.levels[height];
+            level.push(hash);
+            if level.len() % 2 == 1 {
+                break;
+            }
+            hash = node(&level[level.len() - 2], &level[level.len() - 1]);
+        }
+    }
+
+    /// The number of entries in the log
+    pub fn size(&self) -> u64 {
+        self.entries.len() as u64
+    }
+
+    /// The entry at the index, if the log has one
+    pub fn entry(&self, index: u64) -> Option<&Entry> {
+        self.entries.get(index as usize)
+    }
+
+    /// The roots of the complete subtrees, largest first, making up the tree
+    /// of the first `size` entries, which `root` hashes to the tree's root
+    pub fn subtrees(&self, size: u64) -> Result<Vec<Hash>, String> {
+        let size = self.check_size(size)?;
+        let mut start = 0;
+        let mut subtrees = Vec::new();
+        for height in (0..self.levels.len()).rev() {
+            if size & 1 << height != 0 {
+                subtrees.push(self.levels[height][start >> height]);
+                start += 1 << height;
+            }
+        }
+        Ok(subtrees)
+    }
+
+    /// The audit path proving the entry at the index is in the tree of the
+    /// first `size` entries
+    ///
+    /// # Arguments
+    ///
+    /// * `index` - Index of the entry
+    /// * `size` - Size of the tree
+    pub fn inclusion_proof(&self, index: u64, size: u64) -> Result<Vec<Hash>, String> {
+        let size = self.check_size(size)?;
+        if index >= size as u64 {
+            return Err(format!("must be less than the tree size {}", size));
+        }
+        Ok(self.path(index as usize, 0, size))
+    }
+
+    /// The proof that the tree of the first `second` entries extends the
+    /// tree of the first `first` entries
+    ///
+    /// # Arguments
+    ///
+    /// * `first` - Size of the earlier tree
+    /// * `second` - Size of the later tree
+    pub fn consistency_proof(&self, first: u64, second: u64) -> Result<Vec<Hash>, String> {
+        let second = self.check_size(second)?;
+        if first == 0 || first > second as u64 {
+            return Err(format!("must be between 1 and {}", second));
+        }
+        Ok(self.subproof(first as usize, 0, second, true))
+    }
+
+    fn check_size(&self, size: u64) -> Result<usize, String> {
+        match size {
+            size if size > self.size() => Err(format!(
+                "tree size {} is larger than the log's {}",
+                size,
+                self.size()
+            )),
+            size => Ok(size as usize),
+        }
+    }
+
+    // MTH from RFC 9162 2.1.1 of the n leaves from start. Every subtree the
+    // RFC's recursion reaches starts at a multiple of its size rounded up to
+    // a power of two, so a complete one is a cached root.
+    fn mth(&self, start: usize, n: usize) -> Hash {
+        if n == 0 {
+            return to_hash(digest(&SHA256, b"").as_ref());
+        }
+        if n.is_power_of_two() {
+            let height = n.trailing_zeros() as usize;
+            return self.levels[height][start >> height];
+        }
+        let k = split(n);
+        node(&self.mth(start, k), &self.mth(start + k, n - k))
+    }
+
+    // PATH from RFC 9162 2.1.3.1 for leaf m of the n leaves from start
+    fn path(&self, m: usize, start: usize, n: usize) -> Vec<Hash> {
+        if n <= 1 {
+            return Vec::new();
+        }
+        let k = split(n);
+        let (mut proof, sibling) = match m < k {
+            true => (self.path(m, start, k), self.mth(start + k, n - k)),
+            false => (self.path(m - k, start + k, n - k), self.mth(start, k)),
+        };
+        proof.push(sibling);
+        proof
+    }
+
+    // SUBPROOF from RFC 9162 2.1.4.1 for the first m of the n leaves from start
+    fn subproof(&self, m: usize, start: usize, n: usize, complete: bool) -> Vec<Hash> {
+        if m == n {
+            return match complete {
+                true => Vec::new(),
+                false => vec![self.mth(start, n)],
+            };
+        }
+        let k = split(n);
+        let (mut proof, sibling) = match m <= k {
+            true => (
+                self.subproof(m, start, k, complete),
+                self.mth(start + k, n - k),
+            ),
+            false => (
+                self.subproof(m - k, start + k, n - k, false),
+                self.mth(start, k),
+            ),
+        };
+        proof.push(sibling);
+        proof
+    }
+}
+
+/// The root hash of a tree from the roots of its complete subtrees, largest
+/// first, as `AuditLog::subtrees` returns them
+pub fn root(subtrees: &[Hash]) -> Hash {
+    match subtrees.split_last() {
+        None => to_hash(digest(&SHA256, b"").as_ref()),
+        Some((last, rest)) => rest
+            .iter()
+            .rev()
+            .fold(*last, |right, left| node(left, &right)),
+    }
+}
+
+/// Encodes the tree head signed for the tree size, time and root hash
+///
+/// # Arguments
+///
+/// * `size` - Number of entries in the tree
+/// * `timestamp` - Milliseconds since the unix epoch
+/// * `root` - Root hash of the tree
+pub fn tree_head_message(size: u64, timestamp: u64, root: &Hash) -> Vec<u8> {
+    let mut message = TREE_HEAD_DOMAIN_TAG.to_vec();
+    message.extend_from_slice(&size.to_be_bytes());
+    message.extend_from_slice(&timestamp.to_be_bytes());
+    message.extend_from_slice(root);
+    message
+}
+
+fn to_hash(bytes: &[u8]) -> Hash {
+    let mut hash = [0; 32];
+    hash.copy_from_slice(bytes);
+    hash
+}
+
+fn node(left: &Hash, right: &Hash) -> Hash {
+    let mut context = Context::new(&SHA256);
+    context.update(&[NODE_PREFIX]);
+    context.update(left);
+    context.update(right);
+    to_hash(context.finish().as_ref())
+}
+
+// The largest power of two less than n, for n > 1
+fn split(n: usize) -> usize {
+    let mut k = 1;
+    while k << 1 < n {
+        k <<= 1;
+    }
+    k
+}
+
+// Unit tests
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn entry(i: u64) -> Entry {
+        Entry::new(&[format!("word{}", i)], i, i + 1, "k1", "")
+    }
+
+    fn log(size: u64) -> AuditLog {
+        let mut log = AuditLog::in_memory();
+        for i in 0..size {
+            log.append(entry(i)).unwrap();
+        }
+        log
+    }
+
+    // Inclusion proof verification from RFC 9162 2.1.3.2
+    fn verify_inclusion(index: u64, size: u64, leaf: Hash, proof: &[Hash], root: &Hash) -> bool {
+        if index >= size {
+            return false;
+        }
+        let (mut f, mut s, mut r) = (index, size - 1, leaf);
+        for p in proof {
+            if s == 0 {
+                return false;
+            }
+            if f & 1 == 1 || f == s {
+                r = node(p, &r);
+                while f & 1 == 0 && f != 0 {
+                    f >>= 1;
+                    s >>= 1;
+                }
+            } else {
+                r = node(&r, p);
+            }
+            f >>= 1;
+            s >>= 1;
+        }
+        s == 0 && r == *root
+    }
+
+    // Consistency proof verification from RFC 9162 2.1.4.2
+    fn verify_consistency(
+        first: u64,
+        second: u64,
+        proof: &[Hash],
+        first_root: &Hash,
+        second_root: &Hash,
+    ) -> bool {
+        if first == second {
+            return proof.is_empty() && first_root == second_root;
+        }
+        let mut proof = proof.to_vec();
+        if first.is_power_of_two() {
+            proof.insert(0, *first_root);
+        }
+        let (mut f, mut s) = (first - 1, second - 1);
+        while f & 1 == 1 {
+            f >>= 1;
+            s >>= 1;
+        }
+        let (mut fr, mut sr) = (proof[0], proof[0]);
+        for c in &proof[1..] {
+            if s == 0 {
+                return false;
+            }
+            if f & 1 == 1 || f == s {
+                fr = node(c, &fr);
+                sr = node(c, &sr);
+                while f & 1 == 0 && f != 0 {
+                    f >>= 1;
+                    s >>= 1;
+                }
+            } else {
+                sr = node(&sr, c);
+            }
+            f >>= 1;
+            s >>= 1;
+        }
+        fr == *first_root && sr == *second_root && s == 0
+    }
+
+    #[test]
+    fn inclusion_proofs_verify() {
+        let log = log(13);
+        for size in 1..=13 {
+            let root = root(&log.subtrees(size).unwrap());
+            for index in 0..size {
+                let proof = log.inclusion_proof(index, size).unwrap();
+                let leaf = log.entry(index).unwrap().leaf_hash();
+                assert!(verify_inclusion(index, size, leaf, &proof, &root));
+                assert!(!verify_inclusion(
+                    index,
+                    size,
+                    entry(99).leaf_hash(),
+                    &proof,
+                    &root
+                ));
+            }
+        }
+        assert!(log.inclusion_proof(3, 3).is_err());
+        assert!(log.inclusion_proof(0, 14).is_err());
+    }
+
+    #[test]
+    fn consistency_proofs_verify() {
+        let log = log(13);
+        for second in 1..=13 {
+            let second_root = root(&log.subtrees(second).unwrap());
+            for first in 1..=second {
+                let proof = log.consistency_proof(first, second).unwrap();
+                let first_root = root(&log.subtrees(first).unwrap());
+                assert!(verify_consistency(
+                    first,
+                    second,
+                    &proof,
+                    &first_root,
+                    &second_root
+                ));
+            }
+        }
+        // A rewritten entry breaks consistency with the earlier tree
+        let mut rewritten = AuditLog::in_memory();
+        for i in 0..13 {
+            rewritten
+                .append(entry(if i == 2 { 99 } else { i }))
+                .unwrap();
+        }
+        let proof = rewritten.consistency_proof(5, 13).unwrap();
+        assert!(!verify_consistency(
+            5,
+            13,
+            &proof,
+            &root(&log.subtrees(5).unwrap()),
+            &root(&rewritten.subtrees(13).unwrap())
+        ));
+        assert!(log.consistency_proof(0, 3).is_err());
+        assert!(log.consistency_proof(4, 3).is_err());
+    }
+
+    #[test]
+    fn known_roots() {
+        // The empty tree's root is the hash of nothing
+        assert_eq!(
+            encode(root(&log(0).subtrees(0).unwrap())),
+            "47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU="
+        );
+        let log = log(3);
+        let leaves: Vec<Hash> = (0..3).map(|i| entry(i).leaf_hash()).collect();
+        assert_eq!(root(&log.subtrees(1).unwrap()), leaves[0]);
+        assert_eq!(
+            root(&log.subtrees(3).unwrap()),
+            node(&node(&leaves[0], &leaves[1]), &leaves[2])
+        );
+    }
+
+    #[test]
+    fn log_survives_reopening() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.log");
+        let mut log = AuditLog::open(&path).unwrap();
+        assert_eq!(log.append(entry(0)).unwrap(), 0);
+        assert_eq!(log.append(entry(1)).unwrap(), 1);
+        let first_root = root(&log.subtrees(2).unwrap());
+        drop(log);
+
+        let mut log = AuditLog::open(&path).unwrap();
+        assert_eq!(log.size(), 2);
+        assert_eq!(log.entry(1), Some(&entry(1)));
+        assert_eq!(root(&log.subtrees(2).unwrap()), first_root);
+        assert_eq!(log.append(entry(2)).unwrap(), 2);
+
+        std::fs::write(&path, "not json\n").unwrap();
+        assert!(AuditLog::open(&path).is_err());
+    }
+
+    #[test]
+    fn torn_last_line_is_truncated() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.log");
+        let mut log = AuditLog::open(&path).unwrap();
+        log.append(entry(0)).unwrap();
+        log.append(entry(1)).unwrap();
+        drop(log);
+        let complete = std::fs::read(&path).unwrap();
+
+        // A crash part way through appending an entry
+        let mut torn = complete.clone();
+        torn.extend_from_slice(br#"{"words_hash":"AAEC","time"#);
+        std::fs::write(&path, &torn).unwrap();
+        let mut log = AuditLog::open(&path).unwrap();
+        assert_eq!(log.size(), 2);
+        assert_eq!(std::fs::read(&path).unwrap(), complete);
+        assert_eq!(log.append(entry(2)).unwrap(), 2);
+        drop(log);
+        assert_eq!(AuditLog::open(&path).unwrap().entry(2), Some(&entry(2)));
+
+        // Only the last line may be incomplete
+        std::fs::write(&path, [&b"{\n"[..], &complete].concat()).unwrap();
+        assert!(AuditLog::open(&path).is_err());
+    }
+
+    #[test]
+    fn failed_append_is_cut_off() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.log");
+        let mut log = AuditLog::open(&path).unwrap();
+        log.append(entry(0)).unwrap();
+
+        // What a write that failed part way through, and couldn't be cut off
+        // then, leaves behind
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(br#"{"words_hash":"AAEC""#).unwrap();
+        assert_eq!(log.append(entry(1)).unwrap(), 1);
+        drop(log);
+        let log = AuditLog::open(&path).unwrap();
+        assert_eq!(log.size(), 2);
+        assert_eq!(log.entry(1), Some(&entry(1)));
+    }
+}
@@ -0,0 +1,252 @@
+//
+// A Backend produces the signatures for signer. Keeping the key material
+// behind a trait lets the signing path treat every failure as an error to
+// report to the caller rather than a panic, lets tests substitute a backend
+// that fails on demand, and lets keys be held outside signer's process: the
+// in-process backends here sign with keys loaded from files, while the
+// PKCS#11 and remote backends sign with keys held by a hardware module or a
+// signing service. Signing may block on those, so callers run it on the
+// blocking thread pool.
+//
+// Each key's algorithm is fixed by its type: RSA keys sign with RSA-PSS,
+// P-256 keys with ECDSA and Ed25519 keys with EdDSA. Algorithms are
+// identified by their JOSE names, which are returned with each signature so
+// a verifier knows how to check it.
+//
+
+use ring::{
+    rand::SystemRandom,
+    signature::{
+        EcdsaKeyPair, Ed25519KeyPair, KeyPair, RsaKeyPair, UnparsedPublicKey,
+        VerificationAlgorithm, ECDSA_P256_SHA256_FIXED, ED25519, RSA_PSS_2048_8192_SHA256,
+        RSA_PSS_SHA256,
+    },
+};
+use std::{error::Error as StdError, fmt};
+
+pub trait Backend: Send + Sync {
+    /// Signature algorithm of the backend's key
+    fn algorithm(&self) -> Algorithm;
+
+    /// Signs the message, returning the raw signature bytes
+    fn sign(&self, message: &[u8]) -> Result<Vec<u8>, BackendError>;
+
+    /// The public key in ring's encoding for the algorithm: a DER
+    /// RSAPublicKey, an uncompressed P-256 point or the raw Ed25519 key
+    fn public_key(&self) -> &[u8];
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Algorithm {
+    /// RSASSA-PSS with SHA-256
+    Ps256,
+    /// ECDSA on P-256 with SHA-256, signatures in fixed-length r || s form
+    Es256,
+    /// Ed25519
+    EdDsa,
+}
+
+impl Algorithm {
+    /// The JOSE (RFC 7518 / RFC 8037) name of the algorithm
+    pub fn id(&self) -> &'static str {
+        match self {
+            Algorithm::Ps256 => "PS256",
+            Algorithm::Es256 => "ES256",
+            Algorithm::EdDsa => "EdDSA",
+        }
+    }
+
+    /// The algorithm with the JOSE name, if it is one signer supports
+    pub fn from_id(id: &str) -> Option<Algorithm> {
+        [Algorithm::Ps256, Algorithm::Es256, Algorithm::EdDsa]
+            .iter()
+            .find(|algorithm| algorithm.id() == id)
+            .copied()
+    }
+
+    /// Checks a signature made with the algorithm
+    ///
+    /// # Arguments
+    ///
+    /// * `public_key` - Key in the form returned by `Backend::public_key`
+    /// * `message` - Message that was signed
+    /// * `signature` - Raw signature bytes
+    pub fn verify(&self, public_key: &[u8], message: &[u8], signature: &[u8]) -> bool {
+        let algorithm: &dyn VerificationAlgorithm = match self {
+            Algorithm::Ps256 => &RSA_PSS_2048_8192_SHA256,
+            Algorithm::Es256 => &ECDSA_P256_SHA256_FIXED,
+            Algorithm::EdDsa => &ED25519,
+        };
+        UnparsedPublicKey::new(algorithm, public_key)
+            .verify(message, signature)
+            .is_ok()
+    }
+}
+
+impl fmt::Display for Algorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.id())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BackendError {
+    /// The backend can't sign right now, but may be able to later. The
+    /// in-process backends never are, but those that hold keys elsewhere can be.
+    Unavailable(String),
+    /// The backend failed to produce a signature
+    Failed(String),
+}
+
+impl fmt::Display for BackendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BackendError::Unavailable(reason) => {
+                write!(f, "signing backend unavailable: {}", reason)
+            }
+            BackendError::Failed(reason) => write!(f, "signing failed: {}", reason),
+        }
+    }
+}
+
+impl StdError for BackendError {}
+
+/// Signs in process with an RSA key, using RSA-PSS with SHA-256
+pub struct RsaBackend {
+    key_pair: RsaKeyPair,
+    rng: SystemRandom,
+}
+
+impl RsaBackend {
+    pub fn new(key_pair: RsaKeyPair) -> RsaBackend {
+        RsaBackend {
+            key_pair,
+            rng: SystemRandom::new(),
+        }
+    }
+}
+
+impl Backend for RsaBackend {
+    fn algorithm(&self) -> Algorithm {
+        Algorithm::Ps256
+    }
+
+    fn sign(&self, message: &[u8]) -> Result<Vec<u8>, BackendError> {
+        let mut signature = vec![0; self.key_pair.public_modulus_len()];
+        self.key_pair
+            .sign(&RSA_PSS_SHA256, &self.rng, message, &mut signature)
+            .map_err(|_| BackendError::Failed(String::from("RSA signing error")))?;
+        Ok(signature)
+    }
+
+    fn public_key(&self) -> &[u8] {
+        self.key_pair.public_key().as_ref()
+    }
+}
+
+/// Signs in process with a P-256 key, using ECDSA with SHA-256
+pub struct EcdsaBackend {
+    key_pair: EcdsaKeyPair,
+    rng: SystemRandom,
+}
+
+impl EcdsaBackend {
+    pub fn new(key_pair: EcdsaKeyPair) -> EcdsaBackend {
+        EcdsaBackend {
+            key_pair,
+            rng: SystemRandom::new(),
+        }
+    }
+}
+
+impl Backend for EcdsaBackend {
+    fn algorithm(&self) -> Algorithm {
+        Algorithm::Es256
+    }
+
+    fn sign(&self, message: &[u8]) -> Result<Vec<u8>, BackendError> {
+        let signature = self
+            .key_pair
+            .sign(&self.rng, message)
+            .map_err(|_| BackendError::Failed(String::from("ECDSA signing error")))?;
+        Ok(signature.as_ref().to_vec())
+    }
+
+    fn public_key(&self) -> &[u8] {
+        self.key_pair.public_key().as_ref()
+    }
+}
+
+/// Signs in process with an Ed25519 key
+pub struct Ed25519Backend {
+    key_pair: Ed25519KeyPair,
+}
+
+impl Ed25519Backend {
+    pub fn new(key_pair: Ed25519KeyPair) -> Ed25519Backend {
+        Ed25519Backend { key_pair }
+    }
+}
+
+impl Backend for Ed25519Backend {
+    fn algorithm(&self) -> Algorithm {
+        Algorithm::EdDsa
+    }
+
+    fn sign(&self, message: &[u8]) -> Result<Vec<u8>, BackendError> {
+        // Ed25519 signing is deterministic and can't fail
+        Ok(self.key_pair.sign(message).as_ref().to_vec())
+    }
+
+    fn public_key(&self) -> &[u8] {
+        self.key_pair.public_key().as_ref()
+    }
+}
+
+// Unit tests
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use ring::signature::ECDSA_P256_SHA256_FIXED_SIGNING;
+
+    const MESSAGE: &[u8] = b"happy hungry hare";
+
+    // Signs with the backend and checks the signature verifies against its
+    // public key, and doesn't for a different message
+    fn assert_round_trip(backend: &dyn Backend) {
+        let signature = backend.sign(MESSAGE).unwrap();
+        let algorithm = backend.algorithm();
+        assert!(algorithm.verify(backend.public_key(), MESSAGE, &signature));
+        assert!(!algorithm.verify(backend.public_key(), b"sad hungry hare", &signature));
+    }
+
+    #[test]
+    fn rsa_signature_verifies() {
+        let key_pair = RsaKeyPair::from_pkcs8(include_bytes!("../keys/test/rsa2048.pk8")).unwrap();
+        let backend = RsaBackend::new(key_pair);
+        assert_eq!(backend.algorithm().id(), "PS256");
+        assert_round_trip(&backend);
+    }
+
+    #[test]
+    fn ecdsa_signature_verifies() {
+        let rng = SystemRandom::new();
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng).unwrap();
+        let key_pair =
+            EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, pkcs8.as_ref()).unwrap();
+        let backend = EcdsaBackend::new(key_pair);
+        assert_eq!(backend.algorithm().id(), "ES256");
+        assert_round_trip(&backend);
+    }
+
+    #[test]
+    fn ed25519_signature_verifies() {
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+        let key_pair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap();
+        let backend = Ed25519Backend::new(key_pair);
+        assert_eq!(backend.algorithm().id(), "EdDSA");
+        assert_round_trip(&backend);
+    }
+}
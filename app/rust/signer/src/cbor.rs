@@ -0,0 +1,145 @@
+//
+// A minimal CBOR (RFC 8949) encoder covering the data items COSE messages are
+// built from: integers, byte and text strings, arrays, maps and tags. Items
+// are always written in their shortest form with definite lengths, and map
+// entries in the order given, so callers control the deterministic encoding
+// COSE signatures depend on.
+//
+
+// Major types
+const UNSIGNED: u8 = 0;
+const NEGATIVE: u8 = 1;
+const BYTES: u8 = 2;
+const TEXT: u8 = 3;
+const ARRAY: u8 = 4;
+const MAP: u8 = 5;
+const TAG: u8 = 6;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Int(i64),
+    Bytes(Vec<u8>),
+    Text(String),
+    Array(Vec<Value>),
+    Map(Vec<(Value, Value)>),
+    Tag(u64, Box<Value>),
+}
+
+impl Value {
+    /// Encodes the item and everything it contains
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buffer = Vec::new();
+        self.encode_into(&mut buffer);
+        buffer
+    }
+
+    fn encode_into(&self, buffer: &mut Vec<u8>) {
+        match self {
+            Value::Int(n) if *n >= 0 => head(buffer, UNSIGNED, *n as u64),
+            // -1 - n, computed without overflowing for i64::MIN
+            Value::Int(n) => head(buffer, NEGATIVE, !(*n as u64)),
+            Value::Bytes(bytes) => {
+                head(buffer, BYTES, bytes.len() as u64);
+                buffer.extend_from_slice(bytes);
+            }
+            Value::Text(text) => {
+                head(buffer, TEXT, text.len() as u64);
+                buffer.extend_from_slice(text.as_bytes());
+            }
+            Value::Array(items) => {
+                head(buffer, ARRAY, items.len() as u64);
+                for item in items {
+                    item.encode_into(buffer);
+                }
+            }
+            Value::Map(entries) => {
+                head(buffer, MAP, entries.len() as u64);
+                for (key, value) in entries {
+                    key.encode_into(buffer);
+                    value.encode_into(buffer);
+                }
+            }
+            Value::Tag(tag, item) => {
+                head(buffer, TAG, *tag);
+                item.encode_into(buffer);
+            }
+        }
+    }
+}
+
+// Writes the initial byte of an item and its argument in the fewest bytes
+fn head(buffer: &mut Vec<u8>, major: u8, argument: u64) {
+    let major = major << 5;
+    match argument {
+        0..=23 => buffer.push(major | argument as u8),
+        24..=0xff => buffer.extend_from_slice(&[major | 24, argument as u8]),
+        0x100..=0xffff => {
+            buffer.push(major | 25);
+            buffer.extend_from_slice(&(argument as u16).to_be_bytes());
+        }
+        0x1_0000..=0xffff_ffff => {
+            buffer.push(major | 26);
+            buffer.extend_from_slice(&(argument as u32).to_be_bytes());
+        }
+        _ => {
+            buffer.push(major | 27);
+            buffer.extend_from_slice(&argument.to_be_bytes());
+        }
+    }
+}
+
+// Unit tests
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn hex(value: Value) -> String {
+        value
+            .encode()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+
+    // Examples from RFC 8949 Appendix A
+    #[test]
+    fn encode_integers() {
+        assert_eq!(hex(Value::Int(0)), "00");
+        assert_eq!(hex(Value::Int(23)), "17");
+        assert_eq!(hex(Value::Int(24)), "1818");
+        assert_eq!(hex(Value::Int(1000)), "1903e8");
+        assert_eq!(hex(Value::Int(1_000_000)), "1a000f4240");
+        assert_eq!(hex(Value::Int(1_000_000_000_000)), "1b000000e8d4a51000");
+        assert_eq!(hex(Value::Int(-1)), "20");
+        assert_eq!(hex(Value::Int(-100)), "3863");
+        assert_eq!(hex(Value::Int(i64::MIN)), "3b7fffffffffffffff");
+    }
+
+    #[test]
+    fn encode_strings_and_containers() {
+        assert_eq!(hex(Value::Bytes(vec![1, 2, 3, 4])), "4401020304");
+        assert_eq!(hex(Value::Text(String::from("IETF"))), "6449455446");
+        assert_eq!(hex(Value::Text(String::from("\u{00fc}"))), "62c3bc");
+        assert_eq!(
+            hex(Value::Array(vec![
+                Value::Int(1),
+                Value::Array(vec![Value::Int(2), Value::Int(3)]),
+            ])),
+            "8201820203"
+        );
+        assert_eq!(
+            hex(Value::Map(vec![
+                (Value::Int(1), Value::Int(2)),
+                (Value::Int(3), Value::Int(4)),
+            ])),
+            "a201020304"
+        );
+        assert_eq!(
+            hex(Value::Tag(1, Box::new(Value::Int(1_363_896_240)))),
+            "c11a514b67b0"
+        );
+        assert_eq!(hex(Value::Bytes(vec![0; 300]))[..6], *"59012c");
+    }
+}
@@ -0,0 +1,272 @@
+//
+// Signing of words as a COSE_Sign1 message (RFC 9052), for consumers that
+// want a compact binary form rather than JSON. The payload is a CBOR map of
+// the words, the timestamp and the time the signature expires, both in
+// milliseconds. The algorithm is carried in
+// the protected header and the kid of the signing key in the unprotected one.
+// The message is returned with the COSE_Sign1 tag.
+//
+
+use crate::{
+    backend::{Algorithm, Backend, BackendError},
+    cbor::Value,
+};
+
+// CBOR tag of a COSE_Sign1 message
+const COSE_SIGN1_TAG: u64 = 18;
+
+// Header labels
+const HEADER_ALG: i64 = 1;
+const HEADER_KID: i64 = 4;
+
+// Context string of the structure a COSE_Sign1 signature is made over
+const SIGNATURE1_CONTEXT: &str = "Signature1";
+
+/// The COSE (RFC 9053 / RFC 8230) identifier of the algorithm
+pub fn algorithm_id(algorithm: Algorithm) -> i64 {
+    match algorithm {
+        Algorithm::Ps256 => -37,
+        Algorithm::Es256 => -7,
+        Algorithm::EdDsa => -8,
+    }
+}
+
+// Encodes the words and times as the CBOR payload of the message
+fn payload(words: &[String], timestamp: u64, expires_at: u64) -> Vec<u8> {
+    Value::Map(vec![
+        (
+            Value::Text(String::from("words")),
+            Value::Array(words.iter().cloned().map(Value::Text).collect()),
+        ),
+        (
+            Value::Text(String::from("timestamp")),
+            // Millisecond timestamps stay far below i64::MAX
+            Value::Int(timestamp as i64),
+        ),
+        (
+            Value::Text(String::from("expires_at")),
+            Value::Int(expires_at as i64),
+        ),
+    ])
+    .encode()
+}
+
+// The Sig_structure a COSE_Sign1 signature is made over, without external
+// additional authenticated data
+fn signature_input(protected: &[u8], payload: &[u8]) -> Vec<u8> {
+    Value::Array(vec![
+        Value::Text(String::from(SIGNATURE1_CONTEXT)),
+        Value::Bytes(protected.to_vec()),
+        Value::Bytes(Vec::new()),
+        Value::Bytes(payload.to_vec()),
+    ])
+    .encode()
+}
+
+/// Signs the words, returning the tagged COSE_Sign1 message
+///
+/// # Arguments
+///
+/// * `backend` - Backend holding the signing key
+/// * `kid` - Id of the signing key
+/// * `words` - Words being signed
+/// * `timestamp` - Milliseconds since the unix epoch
+/// * `expires_at` - Milliseconds since the unix epoch the signature expires at
+pub fn sign(
+    backend: &dyn Backend,
+    kid: &str,
+    words: &[String],
+    timestamp: u64,
+    expires_at: u64,
+) -> Result<Vec<u8>, BackendError> {
+    let protected = Value::Map(vec![(
+        Value::Int(HEADER_ALG),
+        Value::Int(algorithm_id(backend.algorithm())),
+    )])
+    .encode();
+    let payload = payload(words, timestamp, expires_at);
+    let signature = backend.sign(&signature_input(&protected, &payload))?;
+
+    let message = Value::Array(vec![
+        Value::Bytes(protected),
+        Value::Map(vec![(
+            Value::Int(HEADER_KID),
+            Value::Bytes(kid.as_bytes().to_vec()),
+        )]),
+        Value::Bytes(payload),
+        Value::Bytes(signature),
+    ]);
+    Ok(Value::Tag(COSE_SIGN1_TAG, Box::new(message)).encode())
+}
+
+// Unit tests
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::keys;
+    use std::{convert::TryInto, path::Path};
+
+    // Decodes the first CBOR item of the input, returning it and the rest of
+    // the input. Only the items the encoder writes are understood.
+    fn decode(input: &[u8]) -> (Value, &[u8]) {
+        let major = input[0] >> 5;
+        let (argument, mut rest) = match input[0] & 0x1f {
+            n @ 0..=23 => (n as u64, &input[1..]),
+            24 => (input[1] as u64, &input[2..]),
+            25 => (
+                u16::from_be_bytes(input[1..3].try_into().unwrap()) as u64,
+                &input[3..],
+            ),
+            26 => (
+                u32::from_be_bytes(input[1..5].try_into().unwrap()) as u64,
+                &input[5..],
+            ),
+            27 => (
+                u64::from_be_bytes(input[1..9].try_into().unwrap()),
+                &input[9..],
+            ),
+            n => panic!("unsupported additional info {}", n),
+        };
+        let len = argument as usize;
+        let value = match major {
+            0 => Value::Int(argument as i64),
+            1 => Value::Int(!argument as i64),
+            2 => {
+                let (bytes, tail) = rest.split_at(len);
+                rest = tail;
+                Value::Bytes(bytes.to_vec())
+            }
+            3 => {
+                let (text, tail) = rest.split_at(len);
+                rest = tail;
+                Value::Text(String::from_utf8(text.to_vec()).unwrap())
+            }
+            4 => {
+                let mut items = Vec::new();
+                for _ in 0..len {
+                    let (item, tail) = decode(rest);
+                    items.push(item);
+                    rest = tail;
+                }
+                Value::Array(items)
+            }
+            5 => {
+                let mut entries = Vec::new();
+                for _ in 0..len {
+                    let (key, tail) = decode(rest);
+                    let (value, tail) = decode(tail);
+                    entries.push((key, value));
+                    rest = tail;
+                }
+                Value::Map(entries)
+            }
+            6 => {
+                let (item, tail) = decode(rest);
+                rest = tail;
+                Value::Tag(argument, Box::new(item))
+            }
+            _ => panic!("unsupported major type {}", major),
+        };
+        (value, rest)
+    }
+
+    // Checks a COSE_Sign1 message against the backend's key, returning its
+    // decoded payload and kid if the signature is valid
+    fn verify(backend: &dyn Backend, message: &[u8]) -> Option<(Value, Vec<u8>)> {
+        let (value, rest) = decode(message);
+        assert!(rest.is_empty());
+        let parts = match value {
+            Value::Tag(COSE_SIGN1_TAG, item) => match *item {
+                Value::Array(parts) => parts,
+                other => panic!("expected an array, got {:?}", other),
+            },
+            other => panic!("expected a COSE_Sign1 tag, got {:?}", other),
+        };
+        let (protected, kid, payload, signature) = match parts.as_slice() {
+            [Value::Bytes(protected), Value::Map(unprotected), Value::Bytes(payload), Value::Bytes(signature)] =>
+            {
+                let kid = match unprotected.as_slice() {
+                    [(Value::Int(HEADER_KID), Value::Bytes(kid))] => kid.clone(),
+                    other => panic!("unexpected unprotected header {:?}", other),
+                };
+                (protected, kid, payload, signature)
+            }
+            other => panic!("unexpected COSE_Sign1 structure {:?}", other),
+        };
+        assert_eq!(
+            decode(protected).0,
+            Value::Map(vec![(
+                Value::Int(HEADER_ALG),
+                Value::Int(algorithm_id(backend.algorithm()))
+            )])
+        );
+        match backend.algorithm().verify(
+            backend.public_key(),
+            &signature_input(protected, payload),
+            signature,
+        ) {
+            true => Some((decode(payload).0, kid)),
+            false => None,
+        }
+    }
+
+    fn load(fixture: &str) -> Box<dyn Backend> {
+        keys::load_key(&Path::new("keys/test").join(fixture)).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    #[test]
+    fn round_trip_with_each_key_type() {
+        let words = vec![String::from("briny"), String::from("gherkin")];
+        for fixture in &["rsa2048.der", "p256.pk8", "ed25519.pem"] {
+            let backend = load(fixture);
+            let message = sign(
+                backend.as_ref(),
+                "k1",
+                &words,
+                1_630_000_000_123,
+                1_630_000_060_123,
+            )
+            .unwrap();
+
+            let (payload, kid) = verify(backend.as_ref(), &message)
+                .unwrap_or_else(|| panic!("{} signature did not verify", fixture));
+            assert_eq!(kid, b"k1");
+            assert_eq!(
+                payload,
+                Value::Map(vec![
+                    (
+                        Value::Text(String::from("words")),
+                        Value::Array(vec![
+                            Value::Text(String::from("briny")),
+                            Value::Text(String::from("gherkin")),
+                        ])
+                    ),
+                    (
+                        Value::Text(String::from("timestamp")),
+                        Value::Int(1_630_000_000_123)
+                    ),
+                    (
+                        Value::Text(String::from("expires_at")),
+                        Value::Int(1_630_000_060_123)
+                    ),
+                ])
+            );
+        }
+    }
+
+    #[test]
+    fn tampered_payload_fails_verification() {
+        let backend = load("ed25519.pem");
+        let words = vec![String::from("dill")];
+        let mut message = sign(backend.as_ref(), "k1", &words, 7, 9).unwrap();
+
+        // The last byte of the payload is the expiry time, just before the
+        // 64 byte signature and its two byte head
+        let expires_at = message.len() - 64 - 2 - 1;
+        assert_eq!(message[expires_at], 9);
+        message[expires_at] = 10;
+        assert!(verify(backend.as_ref(), &message).is_none());
+    }
+}
@@ -0,0 +1,265 @@
+//
+// A minimal DER (X.690) encoder covering the types signer writes: integers,
+// octet strings, object identifiers, times, and sequences and sets of them.
+// Elements are built bottom up as byte vectors, so a structure is encoded by
+// nesting calls in the order of its ASN.1 definition. Elements can be split
+// off encoded input again, to pick fields out of structures like
+// certificates.
+//
+
+pub const INTEGER: u8 = 0x02;
+pub const BIT_STRING: u8 = 0x03;
+pub const OCTET_STRING: u8 = 0x04;
+pub const OBJECT_IDENTIFIER: u8 = 0x06;
+pub const GENERALIZED_TIME: u8 = 0x18;
+pub const SEQUENCE: u8 = 0x30;
+pub const SET: u8 = 0x31;
+
+/// Tag of a context-specific element, `[number]` in ASN.1
+///
+/// # Arguments
+///
+/// * `number` - Tag number, below 31
+/// * `constructed` - Whether the element holds other elements, as an
+///   EXPLICIT tag or an IMPLICIT tag on a sequence or set does
+pub fn context(number: u8, constructed: bool) -> u8 {
+    0x80 | (u8::from(constructed) << 5) | number
+}
+
+/// Encodes an element with the tag and contents
+pub fn element(tag: u8, contents: &[u8]) -> Vec<u8> {
+    let mut element = vec![tag];
+    let len = contents.len();
+    if len < 0x80 {
+        element.push(len as u8);
+    } else {
+        let octets = len.to_be_bytes();
+        let zeros = octets.iter().take_while(|b| **b == 0).count();
+        element.push(0x80 | (octets.len() - zeros) as u8);
+        element.extend_from_slice(&octets[zeros..]);
+    }
+    element.extend_from_slice(contents);
+    element
+}
+
+/// Splits an element with the tag off the front of the input, returning the
+/// whole element, its contents and the rest of the input, or None if the
+/// input doesn't start with such an element
+pub fn split(input: &[u8], tag: u8) -> Option<(&[u8], &[u8], &[u8])> {
+    if input.len() < 2 || input[0] != tag {
+        return None;
+    }
+    let (len, header) = match input[1] {
+        short if short < 0x80 => (short as usize, 2),
+        long => {
+            let octets = (long & 0x7f) as usize;
+            if octets == 0 || octets > 4 || input.len() < 2 + octets {
+                return None;
+            }
+            let len = input[2..2 + octets]
+                .iter()
+                .fold(0usize, |len, b| (len << 8) | *b as usize);
+            (len, 2 + octets)
+        }
+    };
+    let end = header.checked_add(len).filter(|end| *end <= input.len())?;
+    Some((&input[..end], &input[header..end], &input[end..]))
+}
+
+/// Encodes a SEQUENCE of the elements, in order
+pub fn sequence(elements: &[Vec<u8>]) -> Vec<u8> {
+    element(SEQUENCE, &elements.concat())
+}
+
+/// Encodes a SET OF the elements, sorted by their encodings as DER requires
+pub fn set(elements: &[Vec<u8>]) -> Vec<u8> {
+    let mut elements = elements.to_vec();
+    elements.sort();
+    element(SET, &elements.concat())
+}
+
+/// Replaces the tag of an encoded element, as an IMPLICIT tag does
+pub fn retag(tag: u8, mut element: Vec<u8>) -> Vec<u8> {
+    element[0] = tag;
+    element
+}
+
+/// Encodes a non-negative INTEGER from its big-endian bytes
+pub fn integer(unsigned: &[u8]) -> Vec<u8> {
+    let zeros = unsigned.iter().take_while(|b| **b == 0).count();
+    let mut int = unsigned[zeros.min(unsigned.len().saturating_sub(1))..].to_vec();
+    // A set top bit would make the integer negative
+    if int.first().copied().unwrap_or(0x80) & 0x80 != 0 {
+        int.insert(0, 0);
+    }
+    element(INTEGER, &int)
+}
+
+/// Encodes an INTEGER
+pub fn uint(n: u64) -> Vec<u8> {
+    integer(&n.to_be_bytes())
+}
+
+pub fn octet_string(bytes: &[u8]) -> Vec<u8> {
+    element(OCTET_STRING, bytes)
+}
+
+/// Encodes an OBJECT IDENTIFIER from its arcs, which must number at least
+/// two, the first at most 2 and the second below 40
+pub fn oid(arcs: &[u64]) -> Vec<u8> {
+    let mut contents = Vec::new();
+    let first = arcs[0] * 40 + arcs[1];
+    for arc in std::iter::once(first).chain(arcs[2..].iter().copied()) {
+        // Base 128, most significant group first, with the top bit set on
+        // all but the last
+        let mut groups = vec![(arc & 0x7f) as u8];
+        let mut rest = arc >> 7;
+        while rest > 0 {
+            groups.push(0x80 | (rest & 0x7f) as u8);
+            rest >>= 7;
+        }
+        contents.extend(groups.iter().rev());
+    }
+    element(OBJECT_IDENTIFIER, &contents)
+}
+
+/// Parses an object identifier in dotted form, like `1.2.840.113549`
+pub fn parse_oid(dotted: &str) -> Option<Vec<u64>> {
+    let arcs = dotted
+        .split('.')
+        .map(|arc| arc.parse().ok())
+        .collect::<Option<Vec<u64>>>()?;
+    match arcs.as_slice() {
+        [first, second, ..] if *first <= 2 && (*second < 40 || *first == 2) => {
+            // The first two arcs share an encoded value
+            first.checked_mul(40)?.checked_add(*second)?;
+            Some(arcs)
+        }
+        _ => None,
+    }
+}
+
+/// Encodes a GeneralizedTime in UTC, with the milliseconds as a fraction
+/// when there are any
+///
+/// # Arguments
+///
+/// * `millis` - Milliseconds since the unix epoch
+pub fn generalized_time(millis: u64) -> Vec<u8> {
+    let seconds = millis / 1000;
+    let (year, month, day) = civil_date(seconds / 86400);
+    let time = seconds % 86400;
+    let mut text = format!(
+        "{:04}{:02}{:02}{:02}{:02}{:02}",
+        year,
+        month,
+        day,
+        time / 3600,
+        time % 3600 / 60,
+        time % 60
+    );
+    // DER drops trailing zeros from the fraction, and the point with them
+    let fraction = format!("{:03}", millis % 1000);
+    let fraction = fraction.trim_end_matches('0');
+    if !fraction.is_empty() {
+        text.push('.');
+        text.push_str(fraction);
+    }
+    text.push('Z');
+    element(GENERALIZED_TIME, text.as_bytes())
+}
+
+// The year, month and day of a count of days since the unix epoch, from
+// Howard Hinnant's civil_from_days
+fn civil_date(days: u64) -> (u64, u64, u64) {
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+    (year, month, day)
+}
+
+// Unit tests
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn encode_lengths() {
+        assert_eq!(element(OCTET_STRING, &[1, 2]), vec![OCTET_STRING, 2, 1, 2]);
+        assert_eq!(element(OCTET_STRING, &[0; 0x80])[..3], [OCTET_STRING, 0x81, 0x80]);
+        assert_eq!(
+            element(OCTET_STRING, &[0; 0x10000])[..5],
+            [OCTET_STRING, 0x83, 1, 0, 0]
+        );
+    }
+
+    #[test]
+    fn split_elements() {
+        let input = [sequence(&[uint(1)]), octet_string(&[0; 200])].concat();
+        let (element, contents, rest) = split(&input, SEQUENCE).unwrap();
+        assert_eq!(element, [SEQUENCE, 3, INTEGER, 1, 1]);
+        assert_eq!(contents, [INTEGER, 1, 1]);
+        let (_, contents, rest) = split(rest, OCTET_STRING).unwrap();
+        assert_eq!((contents.len(), rest.len()), (200, 0));
+
+        assert_eq!(split(&input, SET), None);
+        assert_eq!(split(&input[..4], SEQUENCE), None);
+        assert_eq!(split(&[OCTET_STRING, 0x81], OCTET_STRING), None);
+    }
+
+    #[test]
+    fn encode_integers() {
+        assert_eq!(uint(0), vec![INTEGER, 1, 0]);
+        assert_eq!(uint(127), vec![INTEGER, 1, 0x7f]);
+        assert_eq!(uint(128), vec![INTEGER, 2, 0, 0x80]);
+        assert_eq!(uint(256), vec![INTEGER, 2, 1, 0]);
+        assert_eq!(integer(&[0, 0, 0xc1]), vec![INTEGER, 2, 0, 0xc1]);
+    }
+
+    #[test]
+    fn encode_oids() {
+        // sha256 and rsassa-pss, from RFC 5754 and RFC 4055
+        assert_eq!(
+            oid(&[2, 16, 840, 1, 101, 3, 4, 2, 1]),
+            vec![OBJECT_IDENTIFIER, 9, 0x60, 0x86, 0x48, 1, 0x65, 3, 4, 2, 1]
+        );
+        assert_eq!(
+            oid(&[1, 2, 840, 113549, 1, 1, 10]),
+            vec![OBJECT_IDENTIFIER, 9, 0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 1, 1, 10]
+        );
+        assert_eq!(parse_oid("1.2.840.113549"), Some(vec![1, 2, 840, 113549]));
+        assert_eq!(parse_oid("2.999.1"), Some(vec![2, 999, 1]));
+        for bad in &["", "1", "3.1", "1.40", "1.2.x", "1..2"] {
+            assert_eq!(parse_oid(bad), None, "{}", bad);
+        }
+    }
+
+    #[test]
+    fn sets_are_sorted() {
+        assert_eq!(
+            set(&[uint(2), uint(1)]),
+            vec![SET, 6, INTEGER, 1, 1, INTEGER, 1, 2]
+        );
+    }
+
+    #[test]
+    fn encode_times() {
+        assert_eq!(generalized_time(0), element(GENERALIZED_TIME, b"19700101000000Z"));
+        assert_eq!(
+            generalized_time(951_825_600_120),
+            element(GENERALIZED_TIME, b"20000229120000.12Z")
+        );
+        assert_eq!(
+            generalized_time(1_700_000_000_005),
+            element(GENERALIZED_TIME, b"20231114221320.005Z")
+        );
+    }
+}
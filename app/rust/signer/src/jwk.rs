@@ -0,0 +1,188 @@
+//
+// Conversion of signer's public keys to JSON Web Keys (RFC 7517), so that
+// consumers can fetch them as a standard JWKS document. RSA keys become
+// `RSA` JWKs with their modulus and exponent, P-256 keys `EC` JWKs with their
+// curve point, and Ed25519 keys `OKP` JWKs (RFC 8037). Binary members are
+// base64url encoded without padding.
+//
+
+use crate::backend::{Algorithm, Backend};
+use base64::{encode_config, URL_SAFE_NO_PAD};
+use dill::dill::PublicKey;
+
+// ASN.1 DER tags in an RSAPublicKey
+const DER_SEQUENCE: u8 = 0x30;
+const DER_INTEGER: u8 = 0x02;
+
+// Leading byte of an uncompressed elliptic curve point
+const EC_UNCOMPRESSED: u8 = 0x04;
+
+/// Returns the backend's public key as a JWK
+///
+/// # Arguments
+///
+/// * `kid` - Id of the key
+/// * `backend` - Backend holding the key
+pub fn public_key(kid: &str, backend: &dyn Backend) -> Result<PublicKey, String> {
+    let algorithm = backend.algorithm();
+    let key = backend.public_key();
+    let mut jwk = PublicKey {
+        kid: kid.to_string(),
+        alg: algorithm.id().to_string(),
+        ..Default::default()
+    };
+    match algorithm {
+        Algorithm::Ps256 => {
+            let (n, e) = rsa_components(key)?;
+            jwk.kty = String::from("RSA");
+            jwk.n = Some(base64url(n));
+            jwk.e = Some(base64url(e));
+        }
+        Algorithm::Es256 => {
+            if key.len() != 65 || key[0] != EC_UNCOMPRESSED {
+                return Err(String::from(
+                    "P-256 public key is not an uncompressed point",
+                ));
+            }
+            jwk.kty = String::from("EC");
+            jwk.crv = Some(String::from("P-256"));
+            jwk.x = Some(base64url(&key[1..33]));
+            jwk.y = Some(base64url(&key[33..]));
+        }
+        Algorithm::EdDsa => {
+            jwk.kty = String::from("OKP");
+            jwk.crv = Some(String::from("Ed25519"));
+            jwk.x = Some(base64url(key));
+        }
+    }
+    Ok(jwk)
+}
+
+fn base64url(bytes: &[u8]) -> String {
+    encode_config(bytes, URL_SAFE_NO_PAD)
+}
+
+// Returns the modulus and public exponent of a DER RSAPublicKey, without the
+// sign padding DER adds to integers with the top bit set
+fn rsa_components(der: &[u8]) -> Result<(&[u8], &[u8]), String> {
+    let (sequence, _) = der_element(der, DER_SEQUENCE)?;
+    let (n, rest) = der_element(sequence, DER_INTEGER)?;
+    let (e, _) = der_element(rest, DER_INTEGER)?;
+    Ok((strip_leading_zeros(n), strip_leading_zeros(e)))
+}
+
+// Splits a DER element with the tag off the front of the input, returning its
+// contents and the rest of the input
+fn der_element(input: &[u8], tag: u8) -> Result<(&[u8], &[u8]), String> {
+    let malformed = || String::from("malformed RSA public key");
+    if input.len() < 2 || input[0] != tag {
+        return Err(malformed());
+    }
+    let (len, header) = match input[1] {
+        short if short < 0x80 => (short as usize, 2),
+        long => {
+            let octets = (long & 0x7f) as usize;
+            if octets == 0 || octets > 4 || input.len() < 2 + octets {
+                return Err(malformed());
+            }
+            let len = input[2..2 + octets]
+                .iter()
+                .fold(0usize, |len, b| (len << 8) | *b as usize);
+            (len, 2 + octets)
+        }
+    };
+    if input.len() < header + len {
+        return Err(malformed());
+    }
+    Ok((&input[header..header + len], &input[header + len..]))
+}
+
+fn strip_leading_zeros(int: &[u8]) -> &[u8] {
+    let zeros = int.iter().take_while(|b| **b == 0).count();
+    &int[zeros.min(int.len().saturating_sub(1))..]
+}
+
+// Unit tests
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::keys;
+    use base64::decode_config;
+    use ring::signature::{
+        RsaPublicKeyComponents, UnparsedPublicKey, ECDSA_P256_SHA256_FIXED, ED25519,
+        RSA_PSS_2048_8192_SHA256,
+    };
+    use std::path::Path;
+
+    const MESSAGE: &[u8] = b"happy hungry hare";
+
+    fn load(fixture: &str) -> Box<dyn Backend> {
+        keys::load_key(&Path::new("keys/test").join(fixture)).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    fn member(value: &Option<String>) -> Vec<u8> {
+        decode_config(value.as_ref().unwrap(), URL_SAFE_NO_PAD).unwrap()
+    }
+
+    #[test]
+    fn rsa_jwk_verifies_signature() {
+        let backend = load("rsa3072.pem");
+        let jwk = public_key("k1", backend.as_ref()).unwrap();
+        assert_eq!((jwk.kty.as_str(), jwk.alg.as_str()), ("RSA", "PS256"));
+        assert_eq!(jwk.e, Some(String::from("AQAB")));
+
+        let n = member(&jwk.n);
+        assert_eq!(n.len(), 384);
+        let components = RsaPublicKeyComponents {
+            n: &n,
+            e: &member(&jwk.e),
+        };
+        let signature = backend.sign(MESSAGE).unwrap();
+        assert!(components
+            .verify(&RSA_PSS_2048_8192_SHA256, MESSAGE, &signature)
+            .is_ok());
+    }
+
+    #[test]
+    fn ec_jwk_verifies_signature() {
+        let backend = load("p256.pk8");
+        let jwk = public_key("k2", backend.as_ref()).unwrap();
+        assert_eq!(jwk.kty, "EC");
+        assert_eq!(jwk.crv, Some(String::from("P-256")));
+
+        let mut point = vec![EC_UNCOMPRESSED];
+        point.extend(member(&jwk.x));
+        point.extend(member(&jwk.y));
+        let signature = backend.sign(MESSAGE).unwrap();
+        assert!(UnparsedPublicKey::new(&ECDSA_P256_SHA256_FIXED, point)
+            .verify(MESSAGE, &signature)
+            .is_ok());
+    }
+
+    #[test]
+    fn okp_jwk_verifies_signature() {
+        let backend = load("ed25519.pem");
+        let jwk = public_key("k3", backend.as_ref()).unwrap();
+        assert_eq!(jwk.kty, "OKP");
+        assert_eq!(jwk.crv, Some(String::from("Ed25519")));
+        assert_eq!(jwk.y, None);
+
+        let signature = backend.sign(MESSAGE).unwrap();
+        assert!(UnparsedPublicKey::new(&ED25519, member(&jwk.x))
+            .verify(MESSAGE, &signature)
+            .is_ok());
+    }
+
+    #[test]
+    fn malformed_rsa_keys() {
+        assert!(rsa_components(&[]).is_err());
+        assert!(rsa_components(&[DER_SEQUENCE, 0x82, 0x01]).is_err());
+        assert!(rsa_components(&[DER_SEQUENCE, 3, DER_INTEGER, 5, 1]).is_err());
+        assert_eq!(
+            rsa_components(&[DER_SEQUENCE, 7, DER_INTEGER, 2, 0, 0xc1, DER_INTEGER, 1, 3]),
+            Ok((&[0xc1][..], &[3][..]))
+        );
+    }
+}
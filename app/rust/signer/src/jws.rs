@@ -0,0 +1,121 @@
+//
+// Signing of words as a JWS in compact serialization (RFC 7515), so they can
+// be verified with any JOSE library and the keys published as a JWKS. The
+// protected header carries the algorithm, the kid of the signing key, the
+// time of signing in `iat` and the time the signature expires in `exp`, and
+// the payload is a JSON object holding the words.
+//
+
+use crate::backend::{Backend, BackendError};
+use base64::{encode_config, URL_SAFE_NO_PAD};
+use rocket::serde::Serialize;
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+struct Header<'a> {
+    alg: &'a str,
+    kid: &'a str,
+    // Both in seconds since the unix epoch
+    iat: u64,
+    exp: u64,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+struct Payload<'a> {
+    words: &'a [String],
+}
+
+/// Signs the words, returning the compact JWS
+///
+/// # Arguments
+///
+/// * `backend` - Backend holding the signing key
+/// * `kid` - Id of the signing key
+/// * `words` - Words being signed
+/// * `timestamp` - Milliseconds since the unix epoch
+/// * `expires_at` - Milliseconds since the unix epoch the signature expires at
+pub fn sign(
+    backend: &dyn Backend,
+    kid: &str,
+    words: &[String],
+    timestamp: u64,
+    expires_at: u64,
+) -> Result<String, BackendError> {
+    let header = Header {
+        alg: backend.algorithm().id(),
+        kid,
+        iat: timestamp / 1000,
+        exp: expires_at / 1000,
+    };
+    let signing_input = format!(
+        "{}.{}",
+        encode_json(&header)?,
+        encode_json(&Payload { words })?
+    );
+    let signature = backend.sign(signing_input.as_bytes())?;
+    Ok(format!(
+        "{}.{}",
+        signing_input,
+        encode_config(signature, URL_SAFE_NO_PAD)
+    ))
+}
+
+fn encode_json<T: Serialize>(value: &T) -> Result<String, BackendError> {
+    let json = serde_json::to_vec(value).map_err(|e| BackendError::Failed(e.to_string()))?;
+    Ok(encode_config(json, URL_SAFE_NO_PAD))
+}
+
+// Unit tests
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::keys;
+    use base64::decode_config;
+    use serde_json::Value;
+    use std::path::Path;
+
+    fn decode_json(part: &str) -> Value {
+        serde_json::from_slice(&decode_config(part, URL_SAFE_NO_PAD).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn jws_verifies_with_each_key_type() {
+        let words = vec![String::from("crunchy"), String::from("dill")];
+        for (fixture, alg) in &[
+            ("rsa2048.der", "PS256"),
+            ("p256.pk8", "ES256"),
+            ("ed25519.pem", "EdDSA"),
+        ] {
+            let backend = keys::load_key(&Path::new("keys/test").join(fixture))
+                .unwrap_or_else(|e| panic!("{}", e));
+            let jws = sign(
+                backend.as_ref(),
+                "k1",
+                &words,
+                1_630_000_000_123,
+                1_630_000_060_123,
+            )
+            .unwrap();
+
+            let parts: Vec<&str> = jws.split('.').collect();
+            assert_eq!(parts.len(), 3);
+            let header = decode_json(parts[0]);
+            assert_eq!(header["alg"], *alg);
+            assert_eq!(header["kid"], "k1");
+            assert_eq!(header["iat"], 1_630_000_000);
+            assert_eq!(header["exp"], 1_630_000_060);
+            assert_eq!(decode_json(parts[1])["words"][1], "dill");
+
+            let signing_input = format!("{}.{}", parts[0], parts[1]);
+            let signature = decode_config(parts[2], URL_SAFE_NO_PAD).unwrap();
+            assert!(backend.algorithm().verify(
+                backend.public_key(),
+                signing_input.as_bytes(),
+                &signature
+            ));
+        }
+    }
+}
@@ -0,0 +1,341 @@
+//
+// A Keyring holds signer's keys, each identified by a key id (kid). One key is
+// active and signs new requests; the others are retired, kept so signatures
+// made before a rotation can still be verified.
+//
+// The key path may name a single key file, or a directory holding one file
+// per key named `<kid>.<ext>`. When a directory holds more than one key, a
+// file named `active` holds the kid of the active key. The keyring is
+// reloaded when signer receives SIGHUP or a file under the key path changes,
+// so keys can be rotated without a restart. A reload that fails leaves the
+// current keyring in place.
+//
+
+use crate::{
+    backend::Backend,
+    keys::{self, KeyError},
+};
+use log::info;
+use reload::{self, Shared};
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+// Name of the file holding the active kid in a keyring directory
+const ACTIVE_FILE: &str = "active";
+
+pub struct Keyring {
+    active: String,
+    keys: BTreeMap<String, Box<dyn Backend>>,
+}
+
+impl Keyring {
+    /// Creates a keyring holding just the key
+    pub fn single(kid: &str, backend: Box<dyn Backend>) -> Keyring {
+        let mut keys = BTreeMap::new();
+        keys.insert(kid.to_string(), backend);
+        Keyring {
+            active: kid.to_string(),
+            keys,
+        }
+    }
+
+    /// Loads the keyring from a key file or directory
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - Key file, whose kid is its file stem, or keyring directory
+    pub fn load(path: &Path) -> Result<Keyring, KeyError> {
+        let metadata = fs::metadata(path).map_err(|source| KeyError::Read {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        if !metadata.is_dir() {
+            return Ok(Keyring::single(&kid(path)?, keys::load_key(path)?));
+        }
+
+        let mut keys = BTreeMap::new();
+        for file in key_files(path)? {
+            let kid = kid(&file)?;
+            if keys.contains_key(&kid) {
+                return Err(invalid(path, format!("more than one key with kid {}", kid)));
+            }
+            keys.insert(kid, keys::load_key(&file)?);
+        }
+
+        let active_path = path.join(ACTIVE_FILE);
+        let active = match fs::read_to_string(&active_path) {
+            Ok(active) => active.trim().to_string(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound && keys.len() == 1 => {
+                keys.keys().next().cloned().unwrap_or_default()
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                return Err(invalid(
+                    path,
+                    format!("{} keys found but no {} file", keys.len(), ACTIVE_FILE),
+                ))
+            }
+            Err(source) => {
+                return Err(KeyError::Read {
+                    path: active_path,
+                    source,
+                })
+            }
+        };
+        if !keys.contains_key(&active) {
+            return Err(invalid(path, format!("no key for active kid {:?}", active)));
+        }
+        Ok(Keyring { active, keys })
+    }
+
+    /// The kid and backend of the key that signs new requests
+    pub fn active(&self) -> (&str, &dyn Backend) {
+        let backend = self.keys[&self.active].as_ref();
+        (&self.active, backend)
+    }
+
+    /// The backend of the key with the kid, if the keyring holds it
+    pub fn get(&self, kid: &str) -> Option<&dyn Backend> {
+        self.keys.get(kid).map(|backend| backend.as_ref())
+    }
+
+    /// The kids and backends of all the keys, active and retired
+    pub fn keys(&self) -> impl Iterator<Item = (&str, &dyn Backend)> {
+        self.keys
+            .iter()
+            .map(|(kid, backend)| (kid.as_str(), backend.as_ref()))
+    }
+
+    /// Kids of the keys kept only for verification
+    pub fn retired(&self) -> impl Iterator<Item = &str> {
+        self.keys
+            .keys()
+            .filter(move |kid| **kid != self.active)
+            .map(String::as_str)
+    }
+}
+
+/// The current keyring, replaced as a whole when keys are reloaded
+pub type SharedKeyring = Shared<Keyring>;
+
+/// Loads the keyring from a key file or keyring directory off the runtime,
+/// as loading a key may open a token or a network client, which block
+pub async fn load(path: PathBuf) -> Result<Keyring, KeyError> {
+    let load_path = path.clone();
+    match tokio::task::spawn_blocking(move || Keyring::load(&load_path)).await {
+        Ok(loaded) => loaded,
+        Err(e) => Err(invalid(&path, e.to_string())),
+    }
+}
+
+/// Logs the keys in a keyring
+pub fn log_keyring(keyring: &Keyring, path: &Path) {
+    let (kid, backend) = keyring.active();
+    info!(
+        "Signing with {} key {} from {}",
+        backend.algorithm(),
+        kid,
+        path.display()
+    );
+    for kid in keyring.retired() {
+        info!("Keeping retired key {} for verification", kid);
+    }
+}
+
+/// Reloads the keyring on SIGHUP, or when a file under the key path changes
+///
+/// # Arguments
+///
+/// * `path` - Key file or keyring directory
+/// * `keyring` - Keyring to replace on reload
+/// * `poll` - Interval between checks for changed files
+pub async fn watch(path: PathBuf, keyring: Arc<SharedKeyring>, poll: Duration) {
+    reload::watch("keys", vec![path.clone()], keyring, poll, move || {
+        let keyring = Keyring::load(&path)?;
+        log_keyring(&keyring, &path);
+        Ok::<_, KeyError>(keyring)
+    })
+    .await
+}
+
+// Key files in a keyring directory, skipping the active file, hidden files
+// and subdirectories
+fn key_files(dir: &Path) -> Result<Vec<PathBuf>, KeyError> {
+    let read_error = |source| KeyError::Read {
+        path: dir.to_path_buf(),
+        source,
+    };
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir).map_err(read_error)? {
+        let path = entry.map_err(read_error)?.path();
+        let name = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or_default();
+        if name == ACTIVE_FILE || name.starts_with('.') || !path.is_file() {
+            continue;
+        }
+        files.push(path);
+    }
+    if files.is_empty() {
+        return Err(invalid(dir, String::from("no key files found")));
+    }
+    Ok(files)
+}
+
+fn kid(file: &Path) -> Result<String, KeyError> {
+    match file.file_stem().and_then(|s| s.to_str()) {
+        Some(stem) if !stem.is_empty() => Ok(stem.to_string()),
+        _ => Err(invalid(
+            file,
+            String::from("can't derive a kid from the file name"),
+        )),
+    }
+}
+
+fn invalid(path: &Path, reason: String) -> KeyError {
+    KeyError::Keyring {
+        path: path.to_path_buf(),
+        reason,
+    }
+}
+
+// Unit tests
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::backend::Algorithm;
+    use tempfile::TempDir;
+
+    // Creates a keyring directory holding copies of the test keys, named by kid
+    fn keyring_dir(keys: &[(&str, &str)], active: Option<&str>) -> TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        for (kid, fixture) in keys {
+            let extension = Path::new(fixture).extension().unwrap().to_str().unwrap();
+            fs::copy(
+                Path::new("keys/test").join(fixture),
+                dir.path().join(format!("{}.{}", kid, extension)),
+            )
+            .unwrap();
+        }
+        if let Some(active) = active {
+            fs::write(dir.path().join(ACTIVE_FILE), format!("{}\n", active)).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn load_single_key_file() {
+        let keyring = Keyring::load(Path::new("keys/test/ed25519.pem")).unwrap();
+        let (kid, backend) = keyring.active();
+        assert_eq!(kid, "ed25519");
+        assert_eq!(backend.algorithm(), Algorithm::EdDsa);
+        assert_eq!(keyring.retired().count(), 0);
+    }
+
+    #[test]
+    fn load_keyring_directory() {
+        let dir = keyring_dir(
+            &[("2021-08", "rsa2048.der"), ("2021-09", "p256.pk8")],
+            Some("2021-09"),
+        );
+        let keyring = Keyring::load(dir.path()).unwrap();
+        let (kid, backend) = keyring.active();
+        assert_eq!(kid, "2021-09");
+        assert_eq!(backend.algorithm(), Algorithm::Es256);
+        assert_eq!(keyring.retired().collect::<Vec<&str>>(), vec!["2021-08"]);
+
+        let dir = keyring_dir(&[("only", "ed25519.pem")], None);
+        assert_eq!(Keyring::load(dir.path()).unwrap().active().0, "only");
+    }
+
+    #[test]
+    fn reject_bad_keyrings() {
+        let no_active = keyring_dir(&[("a", "rsa2048.der"), ("b", "p256.pk8")], None);
+        assert!(matches!(
+            Keyring::load(no_active.path()),
+            Err(KeyError::Keyring { .. })
+        ));
+
+        let unknown_active = keyring_dir(&[("a", "rsa2048.der")], Some("b"));
+        assert!(matches!(
+            Keyring::load(unknown_active.path()),
+            Err(KeyError::Keyring { .. })
+        ));
+
+        let duplicate = keyring_dir(&[("a", "rsa2048.der"), ("a", "p256.pk8")], Some("a"));
+        assert!(matches!(
+            Keyring::load(duplicate.path()),
+            Err(KeyError::Keyring { .. })
+        ));
+
+        let bad_key = keyring_dir(&[("a", "rsa2048.der"), ("b", "p384.pem")], Some("a"));
+        assert!(matches!(
+            Keyring::load(bad_key.path()),
+            Err(KeyError::Rejected { .. })
+        ));
+    }
+
+    #[test]
+    fn reload_keeps_keys_on_failure() {
+        let dir = keyring_dir(&[("a", "rsa2048.der"), ("b", "p256.pk8")], Some("a"));
+        let shared = SharedKeyring::new(Keyring::load(dir.path()).unwrap());
+
+        fs::write(dir.path().join(ACTIVE_FILE), "b").unwrap();
+        shared.reload(|| Keyring::load(dir.path())).unwrap();
+        assert_eq!(shared.current().active().0, "b");
+
+        fs::write(dir.path().join(ACTIVE_FILE), "c").unwrap();
+        assert!(shared.reload(|| Keyring::load(dir.path())).is_err());
+        assert_eq!(shared.current().active().0, "b");
+    }
+
+    // A remote key builds a blocking HTTP client, which panics in debug
+    // builds if made on a runtime thread
+    #[tokio::test]
+    async fn load_remote_key_on_runtime() {
+        let dir = tempfile::tempdir().unwrap();
+        let public_key = keys::load_key(Path::new("keys/test/ed25519.pem"))
+            .unwrap()
+            .public_key()
+            .to_vec();
+        let config = serde_json::json!({
+            "url": "http://127.0.0.1:1/sign",
+            "algorithm": "EdDSA",
+            "public_key": base64::encode(public_key),
+        });
+        fs::write(dir.path().join("remote.remote"), config.to_string()).unwrap();
+
+        let keyring = load(dir.path().to_path_buf()).await.unwrap();
+        let (kid, backend) = keyring.active();
+        assert_eq!(kid, "remote");
+        assert_eq!(backend.algorithm(), Algorithm::EdDsa);
+    }
+
+    #[tokio::test]
+    async fn watch_reloads_changed_keys() {
+        let dir = keyring_dir(&[("a", "rsa2048.der"), ("b", "p256.pk8")], Some("a"));
+        let shared = Arc::new(SharedKeyring::new(Keyring::load(dir.path()).unwrap()));
+        tokio::spawn(watch(
+            dir.path().to_path_buf(),
+            shared.clone(),
+            Duration::from_millis(10),
+        ));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        fs::write(dir.path().join(ACTIVE_FILE), "b").unwrap();
+        for _ in 0..100 {
+            if shared.current().active().0 == "b" {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(shared.current().active().0, "b");
+    }
+}
@@ -3,39 +3,89 @@
 // opentelemetry-jaeger to record tracing events.
 //
 
+mod audit;
+mod backend;
+mod cbor;
+mod cose;
+mod der;
+mod jwk;
+mod jws;
+mod keyring;
+mod keys;
+mod message;
+mod metrics;
+mod pkcs11;
+mod pool;
+mod remote;
+mod timestamp;
+mod validity;
+
+use audit::{AuditLog, Entry};
 use b3::ExMetadataMap;
-use base64::encode;
-use bytes::BytesMut;
-use dill::dill::{
-    sign_words_server::{SignWords, SignWordsServer},
-    {SignRequest, WordsResponse},
+use backend::{Backend, BackendError};
+use base64::{decode, encode};
+use dill::{
+    details::{self, bad_request, error_info, retry_after, to_rpc_status},
+    dill::{
+        audit_log_server::{AuditLog as AuditLogService, AuditLogServer},
+        sign_result,
+        sign_words_server::{SignWords, SignWordsServer},
+        {
+            AuditEntry, ConsistencyProofRequest, ConsistencyProofResponse, InclusionProofRequest,
+            InclusionProofResponse, PublicKeysRequest, PublicKeysResponse, SignBatchRequest,
+            SignBatchResponse, SignRequest, SignResult, SignatureFormat, SignedTreeHead,
+            TimestampRequest, TimestampResponse, TreeHeadRequest, VerifyRequest, VerifyResponse,
+            WordsResponse,
+        },
+    },
 };
-use futures::FutureExt;
+use futures::{channel::mpsc, stream, FutureExt, SinkExt, StreamExt};
+use keyring::{Keyring, SharedKeyring};
 use log::{error, info, warn};
+use message::Version;
 use opentelemetry::{
     global,
     global::shutdown_tracer_provider,
-    trace::{noop::NoopTracerProvider, Span, Tracer},
+    trace::{noop::NoopTracerProvider, Span, StatusCode, TraceContextExt, Tracer},
+    Context,
 };
-use rand::SystemRandom;
-use ring::{
-    rand,
-    signature::{
-        RsaKeyPair,
-        RSA_PSS_SHA256,
-    },
+use pool::{PoolError, SigningPool};
+use rocket::{
+    config::{Config, Shutdown},
+    http::ContentType,
+    serde::Deserialize,
+    State,
 };
-use rocket::serde::Deserialize;
-use simple_error::SimpleError;
 use std::{
+    collections::HashSet,
     convert::TryFrom,
-    fs::File,
-    io::Read,
-    time::{SystemTime, UNIX_EPOCH},
+    error::Error as StdError,
+    net::Ipv4Addr,
+    path::PathBuf,
+    process,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 use structopt::StructOpt;
+use timestamp::TimestampAuthority;
 use tokio::{signal, sync::oneshot};
-use tonic::{transport::Server, Request, Response, Status};
+use tonic::{transport::Server, Code, Request, Response, Status};
+use validity::Validity;
+
+// Domain of the ErrorInfo reasons returned by the service
+const ERROR_DOMAIN: &str = "dill.SignWords";
+
+// Domain of the ErrorInfo reasons returned by the audit log service
+const AUDIT_ERROR_DOMAIN: &str = "dill.AuditLog";
+
+// Largest number of requests in a batch; larger jobs can use SignStream
+const MAX_BATCH: usize = 1000;
+
+// How long callers should wait before retrying when the backend is unavailable
+const BACKEND_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+// How long callers should wait before retrying when the signing queue is full
+const QUEUE_RETRY_DELAY: Duration = Duration::from_millis(100);
 
 #[derive(StructOpt, Deserialize)]
 struct Args {
@@ -58,10 +108,455 @@ struct Args {
         default_value = "http://collector.linkerd-jaeger:14268/api/traces"
     )]
     trace_collector_endpoint: String,
+
+    // signing key file, PKCS#1 or PKCS#8 in DER or PEM form, or a directory of
+    // key files named by kid
+    #[structopt(
+        short = "k",
+        long = "key-path",
+        env = "SIGNER_KEY_PATH",
+        default_value = "keys/pickle_key.der",
+        parse(from_os_str)
+    )]
+    key_path: PathBuf,
+
+    // seconds between checks for changed key files
+    #[structopt(
+        long = "key-poll-interval",
+        default_value = "10",
+        parse(try_from_str = reload::parse_poll_interval)
+    )]
+    key_poll_interval: u64,
+
+    // seconds signatures stay valid when the caller doesn't ask for a ttl
+    #[structopt(long = "default-ttl", default_value = "3600")]
+    default_ttl: u64,
+
+    // longest ttl in seconds a caller may ask for
+    #[structopt(long = "max-ttl", default_value = "86400")]
+    max_ttl: u64,
+
+    // seconds of clock difference tolerated when checking signature times
+    #[structopt(long = "clock-skew", default_value = "60")]
+    clock_skew: u64,
+
+    // append-only log recording every signature
+    #[structopt(
+        long = "audit-log",
+        env = "SIGNER_AUDIT_LOG",
+        default_value = "audit.log",
+        parse(from_os_str)
+    )]
+    audit_log: PathBuf,
+
+    // requests of a batch or stream signed at once, by default the number of
+    // cpus
+    #[structopt(long = "batch-parallelism")]
+    batch_parallelism: Option<usize>,
+
+    // threads signing requests, by default the number of cpus
+    #[structopt(long = "signing-threads")]
+    signing_threads: Option<usize>,
+
+    // requests that can wait for a signing thread before more are refused
+    #[structopt(long = "signing-queue", default_value = "256")]
+    signing_queue: usize,
+
+    // port for the prometheus metrics endpoint to listen on
+    #[structopt(long = "metrics-port", default_value = "9091")]
+    metrics_port: u16,
+
+    // object identifier of the policy RFC 3161 timestamp tokens are issued
+    // under; without one, signer doesn't issue them
+    #[structopt(long = "tsa-policy", env = "SIGNER_TSA_POLICY")]
+    tsa_policy: Option<String>,
+
+    // X.509 certificate for the active key, DER or PEM, included in timestamp
+    // tokens so standard tools can check them
+    #[structopt(
+        long = "tsa-certificate",
+        env = "SIGNER_TSA_CERTIFICATE",
+        parse(from_os_str)
+    )]
+    tsa_certificate: Option<PathBuf>,
 }
 
+#[derive(Clone)]
 pub struct MySignWords {
-    rsa_key_pair: RsaKeyPair,
+    keyring: Arc<SharedKeyring>,
+    validity: Validity,
+    audit: Arc<Mutex<AuditLog>>,
+    pool: Arc<SigningPool>,
+    tsa: Option<Arc<TimestampAuthority>>,
+    batch_parallelism: usize,
+}
+
+pub struct MyAuditLog {
+    keyring: Arc<SharedKeyring>,
+    audit: Arc<Mutex<AuditLog>>,
+    pool: Arc<SigningPool>,
+}
+
+/// Returns the requested signature format, or INVALID_ARGUMENT if it isn't
+/// one the service knows
+#[allow(clippy::result_large_err)]
+fn signature_format(format: i32) -> Result<SignatureFormat, Status> {
+    SignatureFormat::from_i32(format).ok_or_else(|| {
+        details::with_details(
+            Code::InvalidArgument,
+            format!("unknown signature format {}", format),
+            vec![
+                error_info("UNKNOWN_FORMAT", ERROR_DOMAIN, &[]),
+                bad_request(&[("format", "must be RAW, JWS or COSE")]),
+            ],
+        )
+    })
+}
+
+/// Milliseconds since the unix epoch, failing if the clock is set before
+/// the epoch or too far after it
+fn timestamp_millis(now: SystemTime) -> Result<u64, Box<dyn StdError>> {
+    let millis = now.duration_since(UNIX_EPOCH)?.as_millis();
+    Ok(u64::try_from(millis)?)
+}
+
+/// Maps a failure of the signing backend to the status returned to the caller
+fn backend_error(e: &BackendError) -> Status {
+    match e {
+        BackendError::Unavailable(_) => details::with_details(
+            Code::Unavailable,
+            "signing backend unavailable",
+            vec![
+                error_info("BACKEND_UNAVAILABLE", ERROR_DOMAIN, &[]),
+                retry_after(BACKEND_RETRY_DELAY),
+            ],
+        ),
+        BackendError::Failed(_) => details::with_details(
+            Code::Internal,
+            "signing failed",
+            vec![error_info("SIGNING_FAILED", ERROR_DOMAIN, &[])],
+        ),
+    }
+}
+
+/// Returns the timestamp authority, or FAILED_PRECONDITION if signer isn't
+/// one
+#[allow(clippy::result_large_err)]
+fn timestamp_authority(
+    tsa: &Option<Arc<TimestampAuthority>>,
+) -> Result<Arc<TimestampAuthority>, Status> {
+    tsa.clone().ok_or_else(|| {
+        details::with_details(
+            Code::FailedPrecondition,
+            "signer is not a timestamp authority",
+            vec![error_info("TIMESTAMPING_DISABLED", ERROR_DOMAIN, &[])],
+        )
+    })
+}
+
+/// Maps a failure to run signing work on the pool to the status returned to
+/// the caller. A full queue is reported as RESOURCE_EXHAUSTED so callers back
+/// off rather than adding to the load.
+fn pool_error(e: &PoolError) -> Status {
+    match e {
+        PoolError::Full => details::with_details(
+            Code::ResourceExhausted,
+            "signing queue is full",
+            vec![
+                error_info("SIGNING_QUEUE_FULL", ERROR_DOMAIN, &[]),
+                retry_after(QUEUE_RETRY_DELAY),
+            ],
+        ),
+        PoolError::Failed => details::with_details(
+            Code::Internal,
+            "signing failed",
+            vec![error_info("SIGNING_FAILED", ERROR_DOMAIN, &[])],
+        ),
+    }
+}
+
+/// The current time in milliseconds since the unix epoch, failing the call
+/// if the system clock is invalid
+#[allow(clippy::result_large_err)]
+fn now_millis<S: Span>(span: &mut S) -> Result<u64, Status> {
+    timestamp_millis(SystemTime::now()).map_err(|e| failed(span, e.as_ref(), clock_invalid()))
+}
+
+fn clock_invalid() -> Status {
+    details::with_details(
+        Code::Internal,
+        "system clock is invalid",
+        vec![error_info("CLOCK_INVALID", ERROR_DOMAIN, &[])],
+    )
+}
+
+// Records the error on the span, returning the status to fail the call with
+fn failed<S: Span>(span: &mut S, error: &dyn StdError, status: Status) -> Status {
+    warn!("{}: {}", status.message(), error);
+    span.record_exception(error);
+    span.set_status(StatusCode::Error, status.message().to_string());
+    span.end();
+    status
+}
+
+// Records that the request was rejected on the span, returning the status
+fn rejected<S: Span>(span: &mut S, status: Status) -> Status {
+    span.set_status(StatusCode::Error, status.message().to_string());
+    span.end();
+    status
+}
+
+/// Hex trace id of the span, or empty if it isn't being traced
+fn trace_id<S: Span>(span: &S) -> String {
+    let context = span.span_context();
+    match context.is_valid() {
+        true => context.trace_id().to_hex(),
+        false => String::new(),
+    }
+}
+
+/// Locks the audit log. Appending leaves the log consistent at every step,
+/// so a panic while it was held doesn't make it unusable.
+fn lock_audit(audit: &Mutex<AuditLog>) -> std::sync::MutexGuard<'_, AuditLog> {
+    match audit.lock() {
+        Ok(log) => log,
+        Err(poisoned) => poisoned.into_inner(),
+    }
+}
+
+/// Appends the entry to the audit log, returning its index. A signature that
+/// can't be recorded fails, so none is returned without an entry.
+fn record(audit: &Mutex<AuditLog>, entry: Entry) -> Result<u64, BackendError> {
+    lock_audit(audit)
+        .append(entry)
+        .map_err(|e| BackendError::Failed(format!("failed to write audit log: {}", e)))
+}
+
+/// Returns INVALID_ARGUMENT, or OUT_OF_RANGE for a tree size the log may
+/// yet grow to, for a proof request field
+fn proof_error(code: Code, reason: &str, field: &str, description: &str) -> Status {
+    details::with_details(
+        code,
+        format!("{} {}", field, description),
+        vec![
+            error_info(reason, AUDIT_ERROR_DOMAIN, &[]),
+            bad_request(&[(field, description)]),
+        ],
+    )
+}
+
+/// Checks a requested tree size against the log, returning OUT_OF_RANGE if
+/// the log hasn't grown to it yet
+#[allow(clippy::result_large_err)]
+fn tree_size(log: &AuditLog, requested: Option<u64>, field: &str) -> Result<u64, Status> {
+    match requested {
+        None => Ok(log.size()),
+        Some(size) if size > log.size() => Err(proof_error(
+            Code::OutOfRange,
+            "TREE_SIZE_OUT_OF_RANGE",
+            field,
+            &format!("must be at most the log size {}", log.size()),
+        )),
+        Some(size) => Ok(size),
+    }
+}
+
+/// Signs the words with the active key in the format requested
+///
+/// # Arguments
+///
+/// * `keyring` - Keyring holding the active key
+/// * `format` - Format of the signature
+/// * `words` - Words to sign, returned in the response
+/// * `timestamp` - Milliseconds since the unix epoch
+/// * `expires_at` - Milliseconds since the unix epoch the signature expires at
+fn sign(
+    keyring: &Keyring,
+    format: SignatureFormat,
+    words: Vec<String>,
+    timestamp: u64,
+    expires_at: u64,
+) -> Result<WordsResponse, BackendError> {
+    let (kid, backend) = keyring.active();
+    let mut reply = WordsResponse {
+        algorithm: Some(backend.algorithm().to_string()),
+        kid: Some(kid.to_string()),
+        expires_at: Some(expires_at),
+        ..Default::default()
+    };
+    match format {
+        SignatureFormat::Raw => {
+            let message = message::encode(Version::CURRENT, &words, timestamp, Some(expires_at));
+            reply.timestamp = Some(timestamp);
+            reply.signature = Some(encode(backend.sign(&message)?));
+            reply.version = Some(Version::CURRENT as u32);
+        }
+        SignatureFormat::Jws => {
+            reply.jws = Some(jws::sign(backend, kid, &words, timestamp, expires_at)?);
+        }
+        SignatureFormat::Cose => {
+            reply.timestamp = Some(timestamp);
+            reply.cose = Some(cose::sign(backend, kid, &words, timestamp, expires_at)?);
+        }
+    }
+    reply.words = words;
+    Ok(reply)
+}
+
+/// Checks a signature against the key named by the request's kid, or against
+/// every key in the keyring if it has none. Without a message version, each
+/// supported version the request has the fields for is tried, newest first.
+/// A matching signature is only valid if it is fresh at `now`.
+fn verify(
+    keyring: &Keyring,
+    validity: &Validity,
+    request: &VerifyRequest,
+    now: u64,
+) -> VerifyResponse {
+    let invalid = |reason: String| VerifyResponse {
+        valid: false,
+        reason: Some(reason),
+        kid: None,
+        version: None,
+    };
+    let signature = match decode(&request.signature) {
+        Ok(signature) => signature,
+        Err(_) => return invalid(String::from("signature is not valid base64")),
+    };
+    let candidates: Vec<(&str, &dyn Backend)> = match &request.kid {
+        Some(kid) => match keyring.get(kid) {
+            Some(backend) => vec![(kid.as_str(), backend)],
+            None => return invalid(format!("unknown key id {}", kid)),
+        },
+        None => keyring.keys().collect(),
+    };
+    let versions = match (request.version, request.expires_at) {
+        (Some(version), expires_at) => match Version::from_u32(version) {
+            Some(Version::Expiring) if expires_at.is_none() => {
+                return invalid(format!("expires_at is required for version {}", version))
+            }
+            Some(version) => vec![version],
+            None => return invalid(format!("unsupported message version {}", version)),
+        },
+        (None, Some(_)) => vec![Version::Expiring],
+        (None, None) => vec![Version::Canonical, Version::Legacy],
+    };
+
+    for version in versions {
+        let expires_at = match version {
+            Version::Expiring => request.expires_at,
+            _ => None,
+        };
+        let message = message::encode(version, &request.words, request.timestamp, expires_at);
+        if !message::acceptable(version, &message) {
+            continue;
+        }
+        for (kid, backend) in &candidates {
+            if backend
+                .algorithm()
+                .verify(backend.public_key(), &message, &signature)
+            {
+                if let Err(reason) = validity.check(request.timestamp, expires_at, now) {
+                    return invalid(reason);
+                }
+                return VerifyResponse {
+                    valid: true,
+                    reason: None,
+                    kid: Some(kid.to_string()),
+                    version: Some(version as u32),
+                };
+            }
+        }
+    }
+    invalid(String::from("signature does not match"))
+}
+
+impl MySignWords {
+    /// Signs the words of one request with the active key and records the
+    /// signature in the audit log, returning the status to fail the request
+    /// with if they can't be signed. The request is traced in a span of its
+    /// own, which records why it failed.
+    ///
+    /// # Arguments
+    ///
+    /// * `request` - Words to sign and how
+    /// * `cx` - Context of the span tracing the request
+    async fn sign_one(&self, request: SignRequest, cx: Context) -> Result<WordsResponse, Status> {
+        let mut span = global::tracer("signer").start_with_context("signing words", cx);
+        let trace_id = trace_id(&span);
+
+        let words = request.words;
+        let format =
+            signature_format(request.format).map_err(|status| rejected(&mut span, status))?;
+        let tsa = match request.timestamp_token {
+            true => {
+                Some(timestamp_authority(&self.tsa).map_err(|status| rejected(&mut span, status))?)
+            }
+            false => None,
+        };
+
+        // Time the words are signed at, and until when the signature is valid
+        let timestamp = now_millis(&mut span)?;
+        let expires_at = match self.validity.expires_at(timestamp, request.ttl_seconds) {
+            Ok(expires_at) => expires_at,
+            Err(reason) => {
+                let status = details::with_details(
+                    Code::InvalidArgument,
+                    format!("ttl_seconds {}", reason),
+                    vec![
+                        error_info("TTL_OUT_OF_RANGE", ERROR_DOMAIN, &[]),
+                        bad_request(&[("ttl_seconds", &reason)]),
+                    ],
+                );
+                return Err(rejected(&mut span, status));
+            }
+        };
+
+        // Signing is cpu heavy and backends may block on a token or a remote
+        // service, and the audit log on its file, so both run on the signing
+        // pool
+        let keyring = self.keyring.current();
+        let audit = self.audit.clone();
+        let signed = self
+            .pool
+            .run(move || {
+                let mut reply = sign(&keyring, format, words, timestamp, expires_at)?;
+                let (kid, backend) = keyring.active();
+                let entry = Entry::new(&reply.words, timestamp, expires_at, kid, &trace_id);
+                if let Some(tsa) = tsa {
+                    reply.timestamp_token =
+                        Some(tsa.token(backend, &entry.words_hash, timestamp, None)?);
+                }
+                reply.log_index = Some(record(&audit, entry)?);
+                Ok(reply)
+            })
+            .await;
+        match signed {
+            Ok(Ok(reply)) => {
+                span.add_event("signed words".to_string(), Vec::new());
+                span.end();
+                Ok(reply)
+            }
+            Ok(Err(e)) => {
+                let status = backend_error(&e);
+                Err(failed(&mut span, &e, status))
+            }
+            Err(e) => {
+                let status = pool_error(&e);
+                Err(failed(&mut span, &e, status))
+            }
+        }
+    }
+}
+
+/// The result of one request of a batch or stream
+fn sign_result(signed: Result<WordsResponse, Status>) -> SignResult {
+    SignResult {
+        result: Some(match signed {
+            Ok(response) => sign_result::Result::Response(response),
+            Err(status) => sign_result::Result::Error(to_rpc_status(&status)),
+        }),
+    }
 }
 
 #[tonic::async_trait]
@@ -73,50 +568,348 @@ impl SignWords for MySignWords {
         let cx = global::get_text_map_propagator(|propagator| {
             propagator.extract(&ExMetadataMap(request.metadata()))
         });
-        let mut span = global::tracer("signer").start_with_context("signing words", cx);
+        let reply = self.sign_one(request.into_inner(), cx).await?;
+        Ok(Response::new(reply))
+    }
 
-        // Prepare the message buffer for signing
-        let words = request.into_inner().words;
-        let mut buffer = BytesMut::new();
-        for w in &words {
-            buffer.extend_from_slice(w.as_bytes());
+    async fn sign_batch(
+        &self,
+        request: Request<SignBatchRequest>,
+    ) -> Result<Response<SignBatchResponse>, Status> {
+        let cx = global::get_text_map_propagator(|propagator| {
+            propagator.extract(&ExMetadataMap(request.metadata()))
+        });
+        let mut span = global::tracer("signer").start_with_context("signing batch", cx.clone());
+
+        let requests = request.into_inner().requests;
+        if requests.len() > MAX_BATCH {
+            let status = details::with_details(
+                Code::InvalidArgument,
+                format!("{} requests is more than {}", requests.len(), MAX_BATCH),
+                vec![
+                    error_info("BATCH_TOO_LARGE", ERROR_DOMAIN, &[]),
+                    bad_request(&[(
+                        "requests",
+                        &format!("must have at most {} requests", MAX_BATCH),
+                    )]),
+                ],
+            );
+            return Err(rejected(&mut span, status));
         }
-        let millis = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_millis();
-        let timestamp = u64::try_from(millis).unwrap();
-        buffer.extend_from_slice(&timestamp.to_ne_bytes());
-        let message = buffer.freeze();
-
-        // Sign the message
-        let key_pair = &self.rsa_key_pair;
-        let rng = SystemRandom::new();
-        let mut signature = vec![0; key_pair.public_modulus_len()];
-        key_pair.sign(
-            &RSA_PSS_SHA256,
-            &rng,
-            &message,
-            &mut signature
-        ).map_err(|_| {
-            warn!("OOM signing message");
-            span.record_exception(&SimpleError::new("OOM while signing message"));
-        }).unwrap();
-        let signature = encode(signature);
-        span.add_event("signed words".to_string(), Vec::new());
 
+        // Results are collected in the order of the requests, with at most
+        // batch_parallelism of them being signed at once, each in a span
+        // under the batch's
+        let cx = cx.with_span(span);
+        let results = stream::iter(requests)
+            .map(|request| self.sign_one(request, cx.clone()))
+            .buffered(self.batch_parallelism)
+            .map(sign_result)
+            .collect::<Vec<SignResult>>()
+            .await;
+        cx.span()
+            .add_event(format!("signed batch of {}", results.len()), Vec::new());
+        cx.span().end();
+
+        Ok(Response::new(SignBatchResponse { results }))
+    }
+
+    type SignStreamStream = mpsc::Receiver<Result<SignResult, Status>>;
+
+    async fn sign_stream(
+        &self,
+        request: Request<tonic::Streaming<SignRequest>>,
+    ) -> Result<Response<Self::SignStreamStream>, Status> {
+        let cx = global::get_text_map_propagator(|propagator| {
+            propagator.extract(&ExMetadataMap(request.metadata()))
+        });
+        let span = global::tracer("signer").start_with_context("signing stream", cx.clone());
+
+        // Requests are signed as they arrive, batch_parallelism at a time,
+        // and results sent in the order of the requests, each in a span under
+        // the stream's. Signing stops when the caller stops reading results.
+        let (mut sender, receiver) = mpsc::channel(self.batch_parallelism);
+        let sw = self.clone();
+        let cx = cx.with_span(span);
+        let requests = request.into_inner();
+        tokio::spawn(async move {
+            let mut results = requests
+                .map(|request| {
+                    let (sw, cx) = (sw.clone(), cx.clone());
+                    async move {
+                        match request {
+                            Ok(request) => Ok(sign_result(sw.sign_one(request, cx).await)),
+                            Err(status) => Err(status),
+                        }
+                    }
+                })
+                .buffered(sw.batch_parallelism);
+            let mut count = 0;
+            while let Some(result) = results.next().await {
+                let failed = result.is_err();
+                if sender.send(result).await.is_err() || failed {
+                    break;
+                }
+                count += 1;
+            }
+            cx.span()
+                .add_event(format!("signed stream of {}", count), Vec::new());
+            cx.span().end();
+        });
+
+        Ok(Response::new(receiver))
+    }
+
+    async fn verify_words(
+        &self,
+        request: Request<VerifyRequest>,
+    ) -> Result<Response<VerifyResponse>, Status> {
+        let cx = global::get_text_map_propagator(|propagator| {
+            propagator.extract(&ExMetadataMap(request.metadata()))
+        });
+        let mut span = global::tracer("signer").start_with_context("verifying words", cx);
+
+        let verify_request = request.into_inner();
+        let now = now_millis(&mut span)?;
+
+        let reply = verify(
+            &self.keyring.current(),
+            &self.validity,
+            &verify_request,
+            now,
+        );
+        span.add_event(
+            match reply.valid {
+                true => "signature valid".to_string(),
+                false => "signature invalid".to_string(),
+            },
+            Vec::new(),
+        );
+        span.end();
+
+        Ok(Response::new(reply))
+    }
+
+    async fn get_public_keys(
+        &self,
+        request: Request<PublicKeysRequest>,
+    ) -> Result<Response<PublicKeysResponse>, Status> {
+        let cx = global::get_text_map_propagator(|propagator| {
+            propagator.extract(&ExMetadataMap(request.metadata()))
+        });
+        let mut span = global::tracer("signer").start_with_context("listing public keys", cx);
+
+        let keyring = self.keyring.current();
+        let mut keys = Vec::new();
+        for (kid, backend) in keyring.keys() {
+            match jwk::public_key(kid, backend) {
+                Ok(key) => keys.push(key),
+                Err(e) => {
+                    let e: Box<dyn StdError> = format!("key {}: {}", kid, e).into();
+                    return Err(failed(
+                        &mut span,
+                        e.as_ref(),
+                        Status::internal("failed to export public keys"),
+                    ));
+                }
+            }
+        }
         span.end();
 
-        let reply = WordsResponse {
-            words: words,
-            timestamp: Some(timestamp),
-            signature: Some(signature),
+        Ok(Response::new(PublicKeysResponse { keys }))
+    }
+
+    async fn timestamp(
+        &self,
+        request: Request<TimestampRequest>,
+    ) -> Result<Response<TimestampResponse>, Status> {
+        let cx = global::get_text_map_propagator(|propagator| {
+            propagator.extract(&ExMetadataMap(request.metadata()))
+        });
+        let mut span = global::tracer("signer").start_with_context("timestamping hash", cx);
+
+        let request = request.into_inner();
+        if request.message_hash.len() != timestamp::HASH_LEN {
+            span.end();
+            return Err(details::with_details(
+                Code::InvalidArgument,
+                format!(
+                    "message_hash of {} bytes is not a SHA-256 hash",
+                    request.message_hash.len()
+                ),
+                vec![
+                    error_info("INVALID_HASH", ERROR_DOMAIN, &[]),
+                    bad_request(&[("message_hash", "must be a 32 byte SHA-256 hash")]),
+                ],
+            ));
+        }
+        let tsa = match timestamp_authority(&self.tsa) {
+            Ok(tsa) => tsa,
+            Err(status) => {
+                span.end();
+                return Err(status);
+            }
         };
+        let timestamp = now_millis(&mut span)?;
 
-        Ok(Response::new(reply))
+        let keyring = self.keyring.current();
+        let signed = self
+            .pool
+            .run(move || {
+                let (kid, backend) = keyring.active();
+                let token = tsa.token(backend, &request.message_hash, timestamp, request.nonce)?;
+                Ok(TimestampResponse {
+                    token,
+                    timestamp,
+                    kid: kid.to_string(),
+                })
+            })
+            .await;
+        match signed {
+            Ok(Ok(response)) => {
+                span.end();
+                Ok(Response::new(response))
+            }
+            Ok(Err(e)) => {
+                let status = backend_error(&e);
+                Err(failed(&mut span, &e, status))
+            }
+            Err(e) => {
+                let status = pool_error(&e);
+                Err(failed(&mut span, &e, status))
+            }
+        }
     }
 }
 
+#[allow(clippy::result_large_err)]
+#[tonic::async_trait]
+impl AuditLogService for MyAuditLog {
+    async fn get_tree_head(
+        &self,
+        request: Request<TreeHeadRequest>,
+    ) -> Result<Response<SignedTreeHead>, Status> {
+        let cx = global::get_text_map_propagator(|propagator| {
+            propagator.extract(&ExMetadataMap(request.metadata()))
+        });
+        let mut span = global::tracer("signer").start_with_context("signing tree head", cx);
+        let timestamp = now_millis(&mut span)?;
+
+        // Hashing the tree and signing may both block. Only the roots of
+        // the tree's complete subtrees are copied under the lock, and hashed
+        // into its root after.
+        let keyring = self.keyring.current();
+        let audit = self.audit.clone();
+        let signed = self
+            .pool
+            .run(move || {
+                let (tree_size, subtrees) = {
+                    let log = lock_audit(&audit);
+                    let size = log.size();
+                    (size, log.subtrees(size).map_err(BackendError::Failed)?)
+                };
+                let root_hash = audit::root(&subtrees);
+                let (kid, backend) = keyring.active();
+                let message = audit::tree_head_message(tree_size, timestamp, &root_hash);
+                Ok(SignedTreeHead {
+                    tree_size,
+                    root_hash: root_hash.to_vec(),
+                    timestamp,
+                    kid: kid.to_string(),
+                    algorithm: backend.algorithm().to_string(),
+                    signature: encode(backend.sign(&message)?),
+                })
+            })
+            .await;
+        match signed {
+            Ok(Ok(tree_head)) => {
+                span.end();
+                Ok(Response::new(tree_head))
+            }
+            Ok(Err(e)) => {
+                let status = backend_error(&e);
+                Err(failed(&mut span, &e, status))
+            }
+            Err(e) => {
+                let status = pool_error(&e);
+                Err(failed(&mut span, &e, status))
+            }
+        }
+    }
+
+    async fn get_inclusion_proof(
+        &self,
+        request: Request<InclusionProofRequest>,
+    ) -> Result<Response<InclusionProofResponse>, Status> {
+        let request = request.into_inner();
+        let audit = self.audit.clone();
+        tokio::task::spawn_blocking(move || {
+            let log = lock_audit(&audit);
+            let tree_size = tree_size(&log, request.tree_size, "tree_size")?;
+            let hashes = log
+                .inclusion_proof(request.leaf_index, tree_size)
+                .map_err(|reason| {
+                    proof_error(
+                        Code::InvalidArgument,
+                        "LEAF_INDEX_OUT_OF_RANGE",
+                        "leaf_index",
+                        &reason,
+                    )
+                })?;
+            let entry = log.entry(request.leaf_index).map(|entry| AuditEntry {
+                words_hash: entry.words_hash.clone(),
+                timestamp: entry.timestamp,
+                kid: entry.kid.clone(),
+                trace_id: entry.trace_id.clone(),
+            });
+            Ok(Response::new(InclusionProofResponse {
+                leaf_index: request.leaf_index,
+                tree_size,
+                entry,
+                hashes: hashes.iter().map(|hash| hash.to_vec()).collect(),
+            }))
+        })
+        .await
+        .unwrap_or_else(|e| Err(Status::internal(e.to_string())))
+    }
+
+    async fn get_consistency_proof(
+        &self,
+        request: Request<ConsistencyProofRequest>,
+    ) -> Result<Response<ConsistencyProofResponse>, Status> {
+        let request = request.into_inner();
+        let audit = self.audit.clone();
+        tokio::task::spawn_blocking(move || {
+            let log = lock_audit(&audit);
+            let second = tree_size(&log, request.second_tree_size, "second_tree_size")?;
+            let hashes = log
+                .consistency_proof(request.first_tree_size, second)
+                .map_err(|reason| {
+                    proof_error(
+                        Code::InvalidArgument,
+                        "TREE_SIZE_OUT_OF_RANGE",
+                        "first_tree_size",
+                        &reason,
+                    )
+                })?;
+            Ok(Response::new(ConsistencyProofResponse {
+                first_tree_size: request.first_tree_size,
+                second_tree_size: second,
+                hashes: hashes.iter().map(|hash| hash.to_vec()).collect(),
+            }))
+        })
+        .await
+        .unwrap_or_else(|e| Err(Status::internal(e.to_string())))
+    }
+}
+
+// Metrics of the signing pool, for prometheus to scrape
+#[rocket::get("/metrics")]
+fn serve_metrics(pool: &State<Arc<SigningPool>>) -> (ContentType, String) {
+    let content_type = ContentType::with_params("text", "plain", ("version", "0.0.4"));
+    (content_type, pool.render_metrics())
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     env_logger::init();
@@ -142,14 +935,116 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     };
 
     // Setup signing key
-    let mut bytes: [u8; 1192] = [0; 1192];
-    let mut file = File::open("keys/pickle_key.der")?;
-    file.read(&mut bytes[..])?;
-    let key_pair = RsaKeyPair::from_der(&bytes).unwrap();
+    let keyring = match keyring::load(args.key_path.clone()).await {
+        Ok(keyring) => keyring,
+        Err(e) => {
+            error!("Failed to load signing keys: {}", e);
+            process::exit(1);
+        }
+    };
+    keyring::log_keyring(&keyring, &args.key_path);
+    let keyring = Arc::new(SharedKeyring::new(keyring));
+    tokio::spawn(keyring::watch(
+        args.key_path.clone(),
+        keyring.clone(),
+        Duration::from_secs(args.key_poll_interval),
+    ));
+    let validity = Validity {
+        default_ttl: Duration::from_secs(args.default_ttl),
+        max_ttl: Duration::from_secs(args.max_ttl),
+        clock_skew: Duration::from_secs(args.clock_skew),
+    };
+    if validity.default_ttl > validity.max_ttl {
+        error!(
+            "Default ttl of {}s is more than the maximum of {}s",
+            args.default_ttl, args.max_ttl
+        );
+        process::exit(1);
+    }
+    let audit = match AuditLog::open(&args.audit_log) {
+        Ok(audit) => Arc::new(Mutex::new(audit)),
+        Err(e) => {
+            error!(
+                "Failed to open audit log {}: {}",
+                args.audit_log.display(),
+                e
+            );
+            process::exit(1);
+        }
+    };
+    info!(
+        "Recording signatures in audit log {}",
+        args.audit_log.display()
+    );
+    let cpus = num_cpus::get();
+    let signing_threads = args.signing_threads.unwrap_or(cpus).max(1);
+    let pool = Arc::new(SigningPool::new(signing_threads, args.signing_queue));
+    info!(
+        "Signing on {} threads with a queue of {}",
+        signing_threads, args.signing_queue
+    );
+    let tsa_certificate = match args.tsa_certificate.as_deref().map(keys::load_certificate) {
+        Some(Ok(certificate)) => Some(certificate),
+        Some(Err(e)) => {
+            error!("Failed to load timestamp authority certificate: {}", e);
+            process::exit(1);
+        }
+        None => None,
+    };
+    let tsa = match &args.tsa_policy {
+        Some(policy) => match TimestampAuthority::new(policy, tsa_certificate.as_deref()) {
+            Ok(tsa) => {
+                info!("Issuing timestamp tokens under policy {}", policy);
+                let current = keyring.current();
+                let (kid, backend) = current.active();
+                if tsa_certificate.is_some() && !tsa.certifies(backend) {
+                    warn!(
+                        "Timestamp authority certificate is not for the active key {}",
+                        kid
+                    );
+                }
+                Some(Arc::new(tsa))
+            }
+            Err(e) => {
+                error!("Invalid timestamp authority: {}", e);
+                process::exit(1);
+            }
+        },
+        None => None,
+    };
+    let al = MyAuditLog {
+        keyring: keyring.clone(),
+        audit: audit.clone(),
+        pool: pool.clone(),
+    };
     let sw = MySignWords {
-        rsa_key_pair: key_pair,
+        keyring,
+        validity,
+        audit,
+        pool: pool.clone(),
+        tsa,
+        batch_parallelism: args.batch_parallelism.unwrap_or(cpus).max(1),
+    };
+
+    // Serve metrics. The grpc server handles shutdown, so rocket mustn't
+    // listen for signals itself.
+    let config = Config {
+        address: Ipv4Addr::UNSPECIFIED.into(),
+        port: args.metrics_port,
+        shutdown: Shutdown {
+            ctrlc: false,
+            signals: HashSet::new(),
+            ..Default::default()
+        },
+        ..Config::release_default()
     };
-    drop(file);
+    let metrics_server = rocket::custom(config)
+        .manage(pool)
+        .mount("/", rocket::routes![serve_metrics])
+        .ignite()
+        .await?;
+    let metrics_shutdown = metrics_server.shutdown();
+    let metrics_server = tokio::spawn(metrics_server.launch());
 
     // Start service
     let addr = format!("0.0.0.0:{}", args.port).parse()?;
@@ -159,6 +1054,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let server = tokio::spawn(async move {
         Server::builder()
             .add_service(SignWordsServer::new(sw))
+            .add_service(AuditLogServer::new(al))
             .serve_with_shutdown(addr, rx.map(drop))
             .await
             .unwrap();
@@ -172,7 +1068,742 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         }
     };
     tx.send(()).unwrap();
+    metrics_shutdown.notify();
     server.await.unwrap();
+    if let Err(e) = metrics_server.await? {
+        error!("Metrics server failed: {}", e);
+    }
     shutdown_tracer_provider();
     Ok(())
 }
+
+// Unit tests
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use backend::Algorithm;
+    use std::path::Path;
+
+    // A backend that returns a fixed signature, or fails with the error
+    struct MockBackend(Result<Vec<u8>, BackendError>);
+
+    impl Backend for MockBackend {
+        fn algorithm(&self) -> Algorithm {
+            Algorithm::EdDsa
+        }
+
+        fn sign(&self, _message: &[u8]) -> Result<Vec<u8>, BackendError> {
+            self.0.clone()
+        }
+
+        fn public_key(&self) -> &[u8] {
+            &[]
+        }
+    }
+
+    const VALIDITY: Validity = Validity {
+        default_ttl: Duration::from_secs(60),
+        max_ttl: Duration::from_secs(600),
+        clock_skew: Duration::from_secs(5),
+    };
+
+    fn service(keyring: Keyring) -> MySignWords {
+        MySignWords {
+            keyring: Arc::new(SharedKeyring::new(keyring)),
+            validity: VALIDITY,
+            audit: Arc::new(Mutex::new(AuditLog::in_memory())),
+            pool: Arc::new(SigningPool::new(2, 16)),
+            tsa: Some(Arc::new(TimestampAuthority::new("1.2.3.4.1", None).unwrap())),
+            batch_parallelism: 4,
+        }
+    }
+
+    fn words(words: &[&str]) -> Vec<String> {
+        words.iter().map(|w| w.to_string()).collect()
+    }
+
+    // Signs the words with the service, returning the request to verify them
+    async fn signed(sw: &MySignWords, words: Vec<String>) -> VerifyRequest {
+        let response = sw
+            .sign_words(Request::new(SignRequest {
+                words,
+                ..Default::default()
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        VerifyRequest {
+            words: response.words,
+            timestamp: response.timestamp.unwrap(),
+            signature: response.signature.unwrap(),
+            kid: response.kid,
+            version: response.version,
+            expires_at: response.expires_at,
+        }
+    }
+
+    async fn verify_with(sw: &MySignWords, request: VerifyRequest) -> VerifyResponse {
+        sw.verify_words(Request::new(request))
+            .await
+            .unwrap()
+            .into_inner()
+    }
+
+    #[tokio::test]
+    async fn verify_signed_words() {
+        let sw = service(Keyring::load(Path::new("keys/test/ed25519.pem")).unwrap());
+        let request = signed(&sw, words(&["happy", "hungry", "hare"])).await;
+
+        let response = verify_with(&sw, request.clone()).await;
+        assert!(response.valid);
+        assert_eq!(response.kid, Some(String::from("ed25519")));
+
+        let tampered = VerifyRequest {
+            words: words(&["sad", "hungry", "hare"]),
+            ..request.clone()
+        };
+        let response = verify_with(&sw, tampered).await;
+        assert!(!response.valid);
+        assert_eq!(
+            response.reason,
+            Some(String::from("signature does not match"))
+        );
+
+        let unknown_kid = VerifyRequest {
+            kid: Some(String::from("2019-01")),
+            ..request.clone()
+        };
+        let response = verify_with(&sw, unknown_kid).await;
+        assert_eq!(
+            response.reason,
+            Some(String::from("unknown key id 2019-01"))
+        );
+
+        let not_base64 = VerifyRequest {
+            signature: String::from("not base64!"),
+            ..request
+        };
+        assert!(!verify_with(&sw, not_base64).await.valid);
+    }
+
+    #[tokio::test]
+    async fn verify_legacy_signature() {
+        let keyring = Keyring::load(Path::new("keys/test/ed25519.pem")).unwrap();
+        let (_, backend) = keyring.active();
+        let legacy = message::encode(Version::Legacy, &words(&["ab", "c"]), 7, None);
+        let request = VerifyRequest {
+            words: words(&["ab", "c"]),
+            timestamp: 7,
+            signature: encode(backend.sign(&legacy).unwrap()),
+            kid: None,
+            version: None,
+            expires_at: None,
+        };
+        let sw = service(keyring);
+
+        let response = verify_with(&sw, request.clone()).await;
+        assert!(response.valid);
+        assert_eq!(response.version, Some(Version::Legacy as u32));
+
+        let canonical_only = VerifyRequest {
+            version: Some(Version::Canonical as u32),
+            ..request.clone()
+        };
+        assert!(!verify_with(&sw, canonical_only).await.valid);
+
+        let unsupported = VerifyRequest {
+            version: Some(9),
+            ..request
+        };
+        assert_eq!(
+            verify_with(&sw, unsupported).await.reason,
+            Some(String::from("unsupported message version 9"))
+        );
+    }
+
+    #[tokio::test]
+    async fn canonical_signature_is_unambiguous() {
+        let sw = service(Keyring::load(Path::new("keys/test/ed25519.pem")).unwrap());
+        let request = signed(&sw, words(&["ab", "c"])).await;
+        assert_eq!(request.version, Some(Version::CURRENT as u32));
+
+        let resplit = VerifyRequest {
+            words: words(&["a", "bc"]),
+            ..request.clone()
+        };
+        assert!(!verify_with(&sw, resplit.clone()).await.valid);
+        let resplit_any_version = VerifyRequest {
+            version: None,
+            ..resplit
+        };
+        assert!(!verify_with(&sw, resplit_any_version).await.valid);
+    }
+
+    #[tokio::test]
+    async fn signatures_expire() {
+        let sw = service(Keyring::load(Path::new("keys/test/ed25519.pem")).unwrap());
+        let request = signed(&sw, words(&["pickle"])).await;
+        assert_eq!(
+            request.expires_at,
+            Some(request.timestamp + VALIDITY.default_ttl.as_millis() as u64)
+        );
+
+        // The expiry time is signed, so can't be extended
+        let extended = VerifyRequest {
+            expires_at: request.expires_at.map(|t| t + 1),
+            ..request.clone()
+        };
+        assert!(!verify_with(&sw, extended).await.valid);
+        let without_expiry = VerifyRequest {
+            expires_at: None,
+            ..request.clone()
+        };
+        assert_eq!(
+            verify_with(&sw, without_expiry).await.reason,
+            Some(String::from("expires_at is required for version 3"))
+        );
+
+        // Once the lifetime and clock skew have passed, it is rejected
+        let keyring = sw.keyring.current();
+        let later = request.expires_at.unwrap() + 5_001;
+        let response = verify(&keyring, &VALIDITY, &request, later);
+        assert!(!response.valid);
+        assert_eq!(
+            response.reason,
+            Some(format!(
+                "signature expired at {}",
+                request.expires_at.unwrap()
+            ))
+        );
+        let earlier = request.timestamp - 5_001;
+        let response = verify(&keyring, &VALIDITY, &request, earlier);
+        assert_eq!(
+            response.reason,
+            Some(format!("timestamp {} is in the future", request.timestamp))
+        );
+    }
+
+    #[tokio::test]
+    async fn ttl_bounded_by_config() {
+        let sw = service(Keyring::load(Path::new("keys/test/ed25519.pem")).unwrap());
+        let sign = |ttl_seconds| {
+            sw.sign_words(Request::new(SignRequest {
+                words: words(&["pickle"]),
+                ttl_seconds: Some(ttl_seconds),
+                ..Default::default()
+            }))
+        };
+
+        let response = sign(600).await.unwrap().into_inner();
+        assert_eq!(response.expires_at, response.timestamp.map(|t| t + 600_000));
+
+        let status = sign(601).await.unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+        assert!(details::error_details(&status).contains(&bad_request(&[(
+            "ttl_seconds",
+            "must be at most 600 seconds"
+        )])));
+    }
+
+    #[tokio::test]
+    async fn public_keys_for_every_key() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::copy("keys/test/rsa2048.der", dir.path().join("rsa.der")).unwrap();
+        std::fs::copy("keys/test/p256.pk8", dir.path().join("ec.pk8")).unwrap();
+        std::fs::copy("keys/test/ed25519.pem", dir.path().join("okp.pem")).unwrap();
+        std::fs::write(dir.path().join("active"), "okp").unwrap();
+        let sw = service(Keyring::load(dir.path()).unwrap());
+
+        let keys = sw
+            .get_public_keys(Request::new(PublicKeysRequest {}))
+            .await
+            .unwrap()
+            .into_inner()
+            .keys;
+        let summary = keys
+            .iter()
+            .map(|k| (k.kid.as_str(), k.kty.as_str(), k.alg.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            vec![
+                ("ec", "EC", "ES256"),
+                ("okp", "OKP", "EdDSA"),
+                ("rsa", "RSA", "PS256")
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn verify_with_retired_key() {
+        let old = service(Keyring::load(Path::new("keys/test/ed25519.pem")).unwrap());
+        let request = signed(&old, words(&["pickle"])).await;
+
+        // Rotate to a new active key, keeping the old one
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::copy("keys/test/ed25519.pem", dir.path().join("ed25519.pem")).unwrap();
+        std::fs::copy("keys/test/p256.pk8", dir.path().join("p256.pk8")).unwrap();
+        std::fs::write(dir.path().join("active"), "p256").unwrap();
+        let new = service(Keyring::load(dir.path()).unwrap());
+
+        let response = verify_with(&new, request.clone()).await;
+        assert!(response.valid);
+        assert_eq!(response.kid, Some(String::from("ed25519")));
+
+        let without_kid = VerifyRequest {
+            kid: None,
+            ..request
+        };
+        let response = verify_with(&new, without_kid).await;
+        assert!(response.valid);
+        assert_eq!(response.kid, Some(String::from("ed25519")));
+    }
+
+    async fn sign_with(backend: MockBackend) -> Result<Response<WordsResponse>, Status> {
+        let sw = service(Keyring::single("test", Box::new(backend)));
+        sw.sign_words(Request::new(SignRequest {
+            words: vec![String::from("pickle")],
+            ..Default::default()
+        }))
+        .await
+    }
+
+    #[tokio::test]
+    async fn sign_words_as_jws() {
+        let sw = service(Keyring::single(
+            "test",
+            Box::new(MockBackend(Ok(vec![1, 2, 3]))),
+        ));
+        let response = sw
+            .sign_words(Request::new(SignRequest {
+                words: vec![String::from("pickle")],
+                format: SignatureFormat::Jws as i32,
+                ..Default::default()
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        let jws = response.jws.unwrap();
+        assert!(jws.ends_with(".AQID"));
+        assert_eq!(jws.split('.').count(), 3);
+        assert_eq!(response.kid, Some(String::from("test")));
+        assert_eq!((response.signature, response.timestamp), (None, None));
+
+        let status = sw
+            .sign_words(Request::new(SignRequest {
+                words: vec![String::from("pickle")],
+                format: 7,
+                ..Default::default()
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+    }
+
+    #[tokio::test]
+    async fn sign_words_as_cose() {
+        let sw = service(Keyring::single(
+            "test",
+            Box::new(MockBackend(Ok(vec![1, 2, 3]))),
+        ));
+        let response = sw
+            .sign_words(Request::new(SignRequest {
+                words: vec![String::from("pickle")],
+                format: SignatureFormat::Cose as i32,
+                ..Default::default()
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        let cose = response.cose.unwrap();
+        // Tag 18, then an array of 4 items ending with the signature
+        assert_eq!(cose[..2], [0xd2, 0x84]);
+        assert!(cose.ends_with(&[0x43, 1, 2, 3]));
+        assert!(response.timestamp.is_some());
+        assert_eq!((response.signature, response.jws), (None, None));
+    }
+
+    #[tokio::test]
+    async fn sign_words_with_backend() {
+        let response = sign_with(MockBackend(Ok(vec![1, 2, 3])))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(response.words, vec![String::from("pickle")]);
+        assert_eq!(response.signature, Some(encode(vec![1, 2, 3])));
+        assert_eq!(response.algorithm, Some(String::from("EdDSA")));
+        assert_eq!(response.kid, Some(String::from("test")));
+        assert!(response.timestamp.is_some());
+    }
+
+    #[tokio::test]
+    async fn sign_words_backend_failed_is_internal() {
+        let e = sign_with(MockBackend(Err(BackendError::Failed(String::from("oops")))))
+            .await
+            .unwrap_err();
+        assert_eq!(e.code(), Code::Internal);
+        assert_eq!(
+            details::error_details(&e),
+            vec![error_info("SIGNING_FAILED", ERROR_DOMAIN, &[])]
+        );
+    }
+
+    #[tokio::test]
+    async fn sign_words_backend_unavailable_is_unavailable() {
+        let e = sign_with(MockBackend(Err(BackendError::Unavailable(String::from(
+            "hsm offline",
+        )))))
+        .await
+        .unwrap_err();
+        assert_eq!(e.code(), Code::Unavailable);
+        assert!(details::error_details(&e).contains(&retry_after(BACKEND_RETRY_DELAY)));
+    }
+
+    #[tokio::test]
+    async fn sign_words_with_timestamp_token() {
+        let backend = keys::load_key(Path::new("keys/test/ed25519.pem")).unwrap();
+        let mut sw = service(Keyring::single("test", backend));
+        let request = || {
+            Request::new(SignRequest {
+                words: words(&["pickle"]),
+                timestamp_token: true,
+                ..Default::default()
+            })
+        };
+        let response = sw.sign_words(request()).await.unwrap().into_inner();
+        // A token over the words hash from the audit log, whose signature
+        // over its signed attributes verifies with the active key, and whose
+        // attributes hold the digest of the TSTInfo they sign for
+        let token = timestamp::tests::open(&response.timestamp_token.unwrap());
+        let words_hash = lock_audit(&sw.audit).entry(0).unwrap().words_hash.clone();
+        assert_eq!(token.hash(), words_hash);
+        let keyring = sw.keyring.current();
+        let (_, backend) = keyring.active();
+        assert!(backend.algorithm().verify(
+            backend.public_key(),
+            &token.attributes,
+            &token.signature
+        ));
+        let message_digest = ring::digest::digest(&ring::digest::SHA512, &token.tst_info);
+        assert!(token
+            .attributes
+            .windows(message_digest.as_ref().len())
+            .any(|w| w == message_digest.as_ref()));
+
+        sw.tsa = None;
+        let e = sw.sign_words(request()).await.unwrap_err();
+        assert_eq!(e.code(), Code::FailedPrecondition);
+    }
+
+    #[tokio::test]
+    async fn timestamp_hash() {
+        let backend = keys::load_key(Path::new("keys/test/ed25519.pem")).unwrap();
+        let mut sw = service(Keyring::single("test", backend));
+        let request = |message_hash: Vec<u8>| {
+            Request::new(TimestampRequest {
+                message_hash,
+                nonce: Some(42),
+            })
+        };
+        let response = sw
+            .timestamp(request(vec![7; timestamp::HASH_LEN]))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(response.kid, "test");
+        assert!(response.timestamp > 0);
+        assert_eq!(response.token[0], der::SEQUENCE);
+        assert_eq!(lock_audit(&sw.audit).size(), 0);
+
+        let e = sw.timestamp(request(vec![7; 20])).await.unwrap_err();
+        assert_eq!(e.code(), Code::InvalidArgument);
+        assert!(details::error_details(&e).contains(&bad_request(&[(
+            "message_hash",
+            "must be a 32 byte SHA-256 hash"
+        )])));
+
+        sw.tsa = None;
+        let e = sw
+            .timestamp(request(vec![7; timestamp::HASH_LEN]))
+            .await
+            .unwrap_err();
+        assert_eq!(e.code(), Code::FailedPrecondition);
+        assert_eq!(
+            details::error_details(&e),
+            vec![error_info("TIMESTAMPING_DISABLED", ERROR_DOMAIN, &[])]
+        );
+    }
+
+    // A backend that blocks signing until released, telling the test when
+    // it has started
+    struct GatedBackend {
+        started: Mutex<std::sync::mpsc::Sender<()>>,
+        release: Mutex<std::sync::mpsc::Receiver<()>>,
+    }
+
+    impl Backend for GatedBackend {
+        fn algorithm(&self) -> Algorithm {
+            Algorithm::EdDsa
+        }
+
+        fn sign(&self, _message: &[u8]) -> Result<Vec<u8>, BackendError> {
+            let _ = self.started.lock().unwrap().send(());
+            let _ = self.release.lock().unwrap().recv();
+            Ok(vec![1, 2, 3])
+        }
+
+        fn public_key(&self) -> &[u8] {
+            &[]
+        }
+    }
+
+    #[tokio::test]
+    async fn sign_words_queue_full_is_resource_exhausted() {
+        let (started_sender, started) = std::sync::mpsc::channel();
+        let (release, release_receiver) = std::sync::mpsc::channel();
+        let mut sw = service(Keyring::single(
+            "test",
+            Box::new(GatedBackend {
+                started: Mutex::new(started_sender),
+                release: Mutex::new(release_receiver),
+            }),
+        ));
+        // One thread and room to queue one request
+        sw.pool = Arc::new(SigningPool::new(1, 1));
+        let request = || {
+            Request::new(SignRequest {
+                words: words(&["pickle"]),
+                ..Default::default()
+            })
+        };
+
+        let spawn_signing = || {
+            let sw = sw.clone();
+            let request = request();
+            tokio::spawn(async move { sw.sign_words(request).await })
+        };
+        let signing = spawn_signing();
+        tokio::task::spawn_blocking(move || started.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        let queued = spawn_signing();
+        while !sw.pool.render_metrics().contains("signer_pool_queued 1\n") {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+
+        // The thread is signing and the queue full, so this is refused
+        let e = sw.sign_words(request()).await.unwrap_err();
+        assert_eq!(e.code(), Code::ResourceExhausted);
+        assert_eq!(
+            details::error_details(&e),
+            vec![
+                error_info("SIGNING_QUEUE_FULL", ERROR_DOMAIN, &[]),
+                retry_after(QUEUE_RETRY_DELAY),
+            ]
+        );
+        assert!(sw
+            .pool
+            .render_metrics()
+            .contains("signer_pool_rejected_total 1\n"));
+
+        release.send(()).unwrap();
+        release.send(()).unwrap();
+        assert!(signing.await.unwrap().is_ok());
+        assert!(queued.await.unwrap().is_ok());
+    }
+
+    #[tokio::test]
+    async fn signatures_recorded_in_audit_log() {
+        let backend = keys::load_key(Path::new("keys/test/ed25519.pem")).unwrap();
+        let sw = service(Keyring::single("test", backend));
+        let al = MyAuditLog {
+            keyring: sw.keyring.clone(),
+            audit: sw.audit.clone(),
+            pool: sw.pool.clone(),
+        };
+        for expected in 0..3 {
+            let response = sw
+                .sign_words(Request::new(SignRequest {
+                    words: words(&["audited", "dill"]),
+                    ..Default::default()
+                }))
+                .await
+                .unwrap()
+                .into_inner();
+            assert_eq!(response.log_index, Some(expected));
+        }
+
+        let head = al
+            .get_tree_head(Request::new(TreeHeadRequest {}))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!((head.tree_size, head.kid.as_str()), (3, "test"));
+        let keyring = sw.keyring.current();
+        let backend = keyring.get("test").unwrap();
+        let mut root_hash = [0; 32];
+        root_hash.copy_from_slice(&head.root_hash);
+        assert!(backend.algorithm().verify(
+            backend.public_key(),
+            &audit::tree_head_message(head.tree_size, head.timestamp, &root_hash),
+            &decode(&head.signature).unwrap()
+        ));
+
+        let proof = al
+            .get_inclusion_proof(Request::new(InclusionProofRequest {
+                leaf_index: 2,
+                tree_size: None,
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!((proof.tree_size, proof.hashes.len()), (3, 1));
+        assert_eq!(proof.entry.unwrap().kid, "test");
+        let proof = al
+            .get_consistency_proof(Request::new(ConsistencyProofRequest {
+                first_tree_size: 1,
+                second_tree_size: Some(3),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(proof.hashes.len(), 2);
+
+        let e = al
+            .get_inclusion_proof(Request::new(InclusionProofRequest {
+                leaf_index: 0,
+                tree_size: Some(4),
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(e.code(), Code::OutOfRange);
+        let e = al
+            .get_inclusion_proof(Request::new(InclusionProofRequest {
+                leaf_index: 3,
+                tree_size: None,
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(e.code(), Code::InvalidArgument);
+    }
+
+    fn batch_request(words: &[&str]) -> SignRequest {
+        SignRequest {
+            words: words.iter().map(|w| w.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    // The words signed by a result, or the code it failed with
+    fn outcome(result: &SignResult) -> Result<Vec<String>, Code> {
+        match result.result.as_ref().unwrap() {
+            sign_result::Result::Response(response) => Ok(response.words.clone()),
+            sign_result::Result::Error(status) => Err(Code::from_i32(status.code)),
+        }
+    }
+
+    #[tokio::test]
+    async fn sign_batch_in_order() {
+        let sw = service(Keyring::single(
+            "test",
+            Box::new(MockBackend(Ok(vec![1, 2, 3]))),
+        ));
+        let mut requests: Vec<SignRequest> = (0..20)
+            .map(|i| batch_request(&[&format!("word{}", i)]))
+            .collect();
+        requests[7] = SignRequest {
+            format: 99,
+            ..batch_request(&["unknown"])
+        };
+        let results = sw
+            .sign_batch(Request::new(SignBatchRequest { requests }))
+            .await
+            .unwrap()
+            .into_inner()
+            .results;
+
+        assert_eq!(results.len(), 20);
+        assert_eq!(outcome(&results[0]), Ok(words(&["word0"])));
+        assert_eq!(outcome(&results[19]), Ok(words(&["word19"])));
+        assert_eq!(outcome(&results[7]), Err(Code::InvalidArgument));
+        // The failed request's details are returned with it
+        match results[7].result.as_ref().unwrap() {
+            sign_result::Result::Error(status) => {
+                let status = details::from_rpc_status(status);
+                assert!(details::error_details(&status).contains(&error_info(
+                    "UNKNOWN_FORMAT",
+                    ERROR_DOMAIN,
+                    &[]
+                )));
+            }
+            r => panic!("expected an error, got {:?}", r),
+        }
+        assert_eq!(sw.audit.lock().unwrap().size(), 19);
+
+        let requests = vec![batch_request(&["pickle"]); MAX_BATCH + 1];
+        let e = sw
+            .sign_batch(Request::new(SignBatchRequest { requests }))
+            .await
+            .unwrap_err();
+        assert_eq!(e.code(), Code::InvalidArgument);
+    }
+
+    #[tokio::test]
+    async fn sign_stream_in_order() {
+        use dill::dill::sign_words_client::SignWordsClient;
+        use std::net::TcpListener;
+
+        let sw = service(Keyring::single(
+            "test",
+            Box::new(MockBackend(Ok(vec![1, 2, 3]))),
+        ));
+        let addr = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        tokio::spawn(
+            Server::builder()
+                .add_service(SignWordsServer::new(sw))
+                .serve(addr),
+        );
+        let mut client = None;
+        for _ in 0..50 {
+            match SignWordsClient::connect(format!("http://{}", addr)).await {
+                Ok(connected) => {
+                    client = Some(connected);
+                    break;
+                }
+                Err(_) => tokio::time::sleep(Duration::from_millis(20)).await,
+            }
+        }
+
+        let mut requests: Vec<SignRequest> = (0..10)
+            .map(|i| batch_request(&[&format!("word{}", i)]))
+            .collect();
+        requests[3].ttl_seconds = Some(0);
+        let mut results = client
+            .unwrap()
+            .sign_stream(Request::new(stream::iter(requests)))
+            .await
+            .unwrap()
+            .into_inner();
+        let mut outcomes = Vec::new();
+        while let Some(result) = results.message().await.unwrap() {
+            outcomes.push(outcome(&result));
+        }
+        assert_eq!(outcomes.len(), 10);
+        assert_eq!(outcomes[0], Ok(words(&["word0"])));
+        assert_eq!(outcomes[3], Err(Code::InvalidArgument));
+        assert_eq!(outcomes[9], Ok(words(&["word9"])));
+    }
+
+    #[test]
+    fn timestamp_from_clock() {
+        let now = UNIX_EPOCH + Duration::from_millis(1_630_000_000_123);
+        assert_eq!(timestamp_millis(now).unwrap(), 1_630_000_000_123);
+        assert!(timestamp_millis(UNIX_EPOCH - Duration::from_secs(1)).is_err());
+    }
+}
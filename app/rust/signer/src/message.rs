@@ -0,0 +1,168 @@
+//
+// The message signer signs for a list of words and a timestamp. Signing and
+// verification both build the message here, so they always agree on it.
+//
+// Messages are versioned. The legacy version concatenates the words with no
+// separators, so ["ab", "c"] and ["a", "bc"] sign identically, and appends the
+// timestamp in the platform's byte order. The canonical version starts with a
+// domain separation tag, length-prefixes every word and writes all integers
+// big-endian. The expiring version is the canonical one with a tag of its own
+// and the time the signature expires after the timestamp. New signatures
+// always use the expiring version; the older ones are kept so signatures made
+// before it can still be verified.
+//
+
+use bytes::{Bytes, BytesMut};
+
+// Start every canonical and expiring message, so they can't be mistaken for a
+// message signed by another protocol, for a legacy message or for each other
+const DOMAIN_TAG: &[u8] = b"dill.SignWords.v2\0";
+const EXPIRING_DOMAIN_TAG: &[u8] = b"dill.SignWords.v3\0";
+
+// Common prefix of the domain tags
+const DOMAIN_TAG_PREFIX: &[u8] = b"dill.SignWords.v";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Version {
+    /// Words concatenated, then the timestamp in native byte order
+    Legacy = 1,
+    /// Domain tag, word count, length-prefixed words, then the timestamp,
+    /// with all integers as big-endian u32s except the u64 timestamp
+    Canonical = 2,
+    /// As canonical, with its own domain tag and the u64 expiry time after
+    /// the timestamp
+    Expiring = 3,
+}
+
+impl Version {
+    /// The version new signatures are made with
+    pub const CURRENT: Version = Version::Expiring;
+
+    pub fn from_u32(version: u32) -> Option<Version> {
+        match version {
+            1 => Some(Version::Legacy),
+            2 => Some(Version::Canonical),
+            3 => Some(Version::Expiring),
+            _ => None,
+        }
+    }
+}
+
+/// Encodes the words and timestamp as the message to sign
+///
+/// # Arguments
+///
+/// * `version` - Message layout to use
+/// * `words` - Words being signed
+/// * `timestamp` - Milliseconds since the unix epoch
+/// * `expires_at` - Milliseconds since the unix epoch the signature expires
+///   at, for the expiring version
+pub fn encode(
+    version: Version,
+    words: &[String],
+    timestamp: u64,
+    expires_at: Option<u64>,
+) -> Bytes {
+    let mut buffer = BytesMut::new();
+    match version {
+        Version::Legacy => {
+            for w in words {
+                buffer.extend_from_slice(w.as_bytes());
+            }
+            buffer.extend_from_slice(&timestamp.to_ne_bytes());
+        }
+        Version::Canonical | Version::Expiring => {
+            buffer.extend_from_slice(match version {
+                Version::Expiring => EXPIRING_DOMAIN_TAG,
+                _ => DOMAIN_TAG,
+            });
+            buffer.extend_from_slice(&(words.len() as u32).to_be_bytes());
+            for w in words {
+                buffer.extend_from_slice(&(w.len() as u32).to_be_bytes());
+                buffer.extend_from_slice(w.as_bytes());
+            }
+            buffer.extend_from_slice(&timestamp.to_be_bytes());
+            if version == Version::Expiring {
+                buffer.extend_from_slice(&expires_at.unwrap_or_default().to_be_bytes());
+            }
+        }
+    }
+    buffer.freeze()
+}
+
+/// Whether a signature over the message may be accepted for the version.
+/// A legacy message that starts like a domain tag could be a newer message
+/// split into words to look like a legacy one, so is never accepted.
+pub fn acceptable(version: Version, message: &[u8]) -> bool {
+    version != Version::Legacy || !message.starts_with(DOMAIN_TAG_PREFIX)
+}
+
+// Unit tests
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn words(words: &[&str]) -> Vec<String> {
+        words.iter().map(|w| w.to_string()).collect()
+    }
+
+    #[test]
+    fn canonical_layout() {
+        let message = encode(Version::Canonical, &words(&["ab", "c"]), 0x0102, None);
+        let mut expected = DOMAIN_TAG.to_vec();
+        expected.extend_from_slice(&[0, 0, 0, 2]);
+        expected.extend_from_slice(&[0, 0, 0, 2, b'a', b'b']);
+        expected.extend_from_slice(&[0, 0, 0, 1, b'c']);
+        expected.extend_from_slice(&[0, 0, 0, 0, 0, 0, 1, 2]);
+        assert_eq!(message.as_ref(), expected.as_slice());
+    }
+
+    #[test]
+    fn canonical_is_unambiguous() {
+        assert_eq!(
+            encode(Version::Legacy, &words(&["ab", "c"]), 7, None),
+            encode(Version::Legacy, &words(&["a", "bc"]), 7, None)
+        );
+        assert_ne!(
+            encode(Version::Canonical, &words(&["ab", "c"]), 7, None),
+            encode(Version::Canonical, &words(&["a", "bc"]), 7, None)
+        );
+        assert_ne!(
+            encode(Version::Canonical, &words(&["abc"]), 7, None),
+            encode(Version::Canonical, &words(&["abc", ""]), 7, None)
+        );
+    }
+
+    #[test]
+    fn expiring_layout() {
+        let message = encode(Version::Expiring, &words(&["ab"]), 0x0102, Some(0x0304));
+        let mut expected = EXPIRING_DOMAIN_TAG.to_vec();
+        expected.extend_from_slice(&[0, 0, 0, 1]);
+        expected.extend_from_slice(&[0, 0, 0, 2, b'a', b'b']);
+        expected.extend_from_slice(&[0, 0, 0, 0, 0, 0, 1, 2]);
+        expected.extend_from_slice(&[0, 0, 0, 0, 0, 0, 3, 4]);
+        assert_eq!(message.as_ref(), expected.as_slice());
+    }
+
+    #[test]
+    fn legacy_never_accepts_tagged_messages() {
+        let canonical = encode(Version::Canonical, &words(&["dill"]), 7, None);
+        assert!(acceptable(Version::Canonical, &canonical));
+        assert!(!acceptable(Version::Legacy, &canonical));
+        assert!(acceptable(
+            Version::Legacy,
+            &encode(Version::Legacy, &words(&["dill"]), 7, None)
+        ));
+    }
+
+    #[test]
+    fn versions_from_numbers() {
+        assert_eq!(Version::from_u32(1), Some(Version::Legacy));
+        assert_eq!(Version::from_u32(2), Some(Version::Canonical));
+        assert_eq!(Version::from_u32(3), Some(Version::Expiring));
+        assert_eq!(Version::from_u32(4), None);
+        assert_eq!(Version::CURRENT as u32, 3);
+    }
+}
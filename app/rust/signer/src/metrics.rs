@@ -0,0 +1,106 @@
+//
+// Minimal metrics kept with atomics and rendered in the Prometheus text
+// exposition format, so signer's signing pool can be scraped without pulling
+// a metrics library into the service.
+//
+
+use std::{
+    fmt::Write,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+// Upper bounds of the histogram buckets, in seconds
+const BUCKETS: [f64; 12] = [
+    0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5,
+];
+
+/// A histogram of durations with fixed buckets
+#[derive(Default)]
+pub struct Histogram {
+    // Observations at or below each bound, not cumulative
+    buckets: [AtomicU64; BUCKETS.len()],
+    count: AtomicU64,
+    sum_micros: AtomicU64,
+}
+
+impl Histogram {
+    /// Records a duration
+    pub fn observe(&self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        if let Some(i) = BUCKETS.iter().position(|bound| seconds <= *bound) {
+            self.buckets[i].fetch_add(1, Ordering::Relaxed);
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_micros
+            .fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    /// The number of durations recorded
+    pub fn count(&self) -> u64 {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    /// Appends the histogram to a Prometheus text exposition
+    ///
+    /// # Arguments
+    ///
+    /// * `out` - Exposition being written
+    /// * `name` - Metric name, ending in `_seconds`
+    /// * `help` - Description of the metric
+    pub fn render(&self, out: &mut String, name: &str, help: &str) {
+        let _ = writeln!(out, "# HELP {} {}", name, help);
+        let _ = writeln!(out, "# TYPE {} histogram", name);
+        let mut cumulative = 0;
+        for (bound, bucket) in BUCKETS.iter().zip(&self.buckets) {
+            cumulative += bucket.load(Ordering::Relaxed);
+            let _ = writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, bound, cumulative);
+        }
+        let count = self.count();
+        let _ = writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, count);
+        let sum = self.sum_micros.load(Ordering::Relaxed) as f64 / 1e6;
+        let _ = writeln!(out, "{}_sum {}", name, sum);
+        let _ = writeln!(out, "{}_count {}", name, count);
+    }
+}
+
+/// Appends a counter or gauge to a Prometheus text exposition
+///
+/// # Arguments
+///
+/// * `out` - Exposition being written
+/// * `name` - Metric name
+/// * `kind` - `counter` or `gauge`
+/// * `help` - Description of the metric
+/// * `value` - Current value
+pub fn render_value(out: &mut String, name: &str, kind: &str, help: &str, value: u64) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+    let _ = writeln!(out, "{} {}", name, value);
+}
+
+// Unit tests
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn histogram_buckets_are_cumulative() {
+        let histogram = Histogram::default();
+        histogram.observe(Duration::from_micros(300));
+        histogram.observe(Duration::from_millis(20));
+        histogram.observe(Duration::from_secs(10));
+
+        let mut out = String::new();
+        histogram.render(&mut out, "wait_seconds", "Time waited");
+        assert!(out.contains("# TYPE wait_seconds histogram\n"));
+        assert!(out.contains("wait_seconds_bucket{le=\"0.0005\"} 1\n"));
+        assert!(out.contains("wait_seconds_bucket{le=\"0.025\"} 2\n"));
+        assert!(out.contains("wait_seconds_bucket{le=\"2.5\"} 2\n"));
+        assert!(out.contains("wait_seconds_bucket{le=\"+Inf\"} 3\n"));
+        assert!(out.contains("wait_seconds_sum 10.0203\n"));
+        assert!(out.contains("wait_seconds_count 3\n"));
+    }
+}
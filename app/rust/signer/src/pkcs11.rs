@@ -0,0 +1,832 @@
+//
+// A backend that signs with a key held in a PKCS#11 token, such as an HSM or
+// SoftHSM, so the private key never leaves the token. A key is configured by
+// a reference file named `<kid>.pkcs11` in place of a key file, holding JSON
+// like:
+//
+//   {
+//     "module": "/usr/lib/softhsm/libsofthsm2.so",
+//     "token": "pickle",
+//     "key_label": "pickle",
+//     "pin_env": "SIGNER_PKCS11_PIN"
+//   }
+//
+// The PIN is read from the named environment variable so it stays out of the
+// key directory. The private key is found by its label, and its type selects
+// the algorithm: RSA keys sign with RSA-PSS, P-256 keys with ECDSA and Ed25519
+// keys with EdDSA, as the in-process backends do. The public key is read from
+// the token when the key is loaded.
+//
+// The module is loaded with dlopen and called through the function list it
+// returns; only the functions signer needs are declared. The module is
+// initialized for use from several threads, and the session is shared behind
+// a mutex since a session can only run one operation at a time. A session
+// that's lost, say because the token was removed, is closed and a new one
+// opened and logged in to; the session is closed when the backend is dropped,
+// as the keyring is on reload.
+//
+
+use crate::{
+    backend::{Algorithm, Backend, BackendError},
+    der,
+};
+use libc::{c_ulong, c_void, dlerror, dlopen, dlsym, RTLD_NOW};
+use log::warn;
+use ring::digest::{digest, SHA256};
+use rocket::serde::Deserialize;
+use std::{
+    env,
+    ffi::{CStr, CString},
+    ptr,
+    sync::Mutex,
+};
+
+type Ulong = c_ulong;
+type Rv = Ulong;
+
+// Return values
+const CKR_OK: Rv = 0;
+const CKR_DEVICE_ERROR: Rv = 0x30;
+const CKR_DEVICE_REMOVED: Rv = 0x32;
+const CKR_SESSION_CLOSED: Rv = 0xb0;
+const CKR_SESSION_HANDLE_INVALID: Rv = 0xb3;
+const CKR_TOKEN_NOT_PRESENT: Rv = 0xe0;
+const CKR_USER_ALREADY_LOGGED_IN: Rv = 0x100;
+const CKR_CRYPTOKI_ALREADY_INITIALIZED: Rv = 0x191;
+
+// Flags, user types, object classes, key types and attributes
+const CKF_OS_LOCKING_OK: Ulong = 0x2;
+const CKF_SERIAL_SESSION: Ulong = 0x4;
+const CKU_USER: Ulong = 1;
+const CKO_PUBLIC_KEY: Ulong = 2;
+const CKO_PRIVATE_KEY: Ulong = 3;
+const CKK_RSA: Ulong = 0;
+const CKK_EC: Ulong = 3;
+const CKK_EC_EDWARDS: Ulong = 0x40;
+const CKA_CLASS: Ulong = 0;
+const CKA_LABEL: Ulong = 3;
+const CKA_KEY_TYPE: Ulong = 0x100;
+const CKA_MODULUS: Ulong = 0x120;
+const CKA_PUBLIC_EXPONENT: Ulong = 0x122;
+const CKA_EC_POINT: Ulong = 0x181;
+
+// Mechanisms
+const CKM_SHA256_RSA_PKCS_PSS: Ulong = 0x43;
+const CKM_SHA256: Ulong = 0x250;
+const CKG_MGF1_SHA256: Ulong = 0x2;
+const CKM_ECDSA: Ulong = 0x1041;
+const CKM_EDDSA: Ulong = 0x1057;
+
+// Length of a token label in CK_TOKEN_INFO, padded with spaces
+const TOKEN_LABEL_LEN: usize = 32;
+
+// Largest signature signer expects, from an RSA 8192 key
+const MAX_SIGNATURE_LEN: usize = 1024;
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct Config {
+    module: String,
+    token: String,
+    key_label: String,
+    pin_env: String,
+}
+
+#[repr(C)]
+struct Attribute {
+    kind: Ulong,
+    value: *mut c_void,
+    len: Ulong,
+}
+
+#[repr(C)]
+struct Mechanism {
+    kind: Ulong,
+    parameter: *mut c_void,
+    len: Ulong,
+}
+
+#[repr(C)]
+struct PssParams {
+    hash: Ulong,
+    mgf: Ulong,
+    salt_len: Ulong,
+}
+
+#[repr(C)]
+struct InitializeArgs {
+    create_mutex: *mut c_void,
+    destroy_mutex: *mut c_void,
+    lock_mutex: *mut c_void,
+    unlock_mutex: *mut c_void,
+    flags: Ulong,
+    reserved: *mut c_void,
+}
+
+type Unused = Option<unsafe extern "C" fn()>;
+
+// The start of CK_FUNCTION_LIST, up to C_Sign, in the order the standard
+// defines. Fields signer doesn't call are left untyped.
+#[repr(C)]
+struct FunctionList {
+    version: [u8; 2],
+    initialize: unsafe extern "C" fn(*mut InitializeArgs) -> Rv,
+    _finalize: Unused,
+    _get_info: Unused,
+    _get_function_list: Unused,
+    get_slot_list: unsafe extern "C" fn(u8, *mut Ulong, *mut Ulong) -> Rv,
+    _get_slot_info: Unused,
+    get_token_info: unsafe extern "C" fn(Ulong, *mut u8) -> Rv,
+    _get_mechanism_list: Unused,
+    _get_mechanism_info: Unused,
+    _init_token: Unused,
+    _init_pin: Unused,
+    _set_pin: Unused,
+    open_session: unsafe extern "C" fn(Ulong, Ulong, *mut c_void, *mut c_void, *mut Ulong) -> Rv,
+    close_session: unsafe extern "C" fn(Ulong) -> Rv,
+    _close_all_sessions: Unused,
+    _get_session_info: Unused,
+    _get_operation_state: Unused,
+    _set_operation_state: Unused,
+    login: unsafe extern "C" fn(Ulong, Ulong, *const u8, Ulong) -> Rv,
+    _logout: Unused,
+    _create_object: Unused,
+    _copy_object: Unused,
+    _destroy_object: Unused,
+    _get_object_size: Unused,
+    get_attribute_value: unsafe extern "C" fn(Ulong, Ulong, *mut Attribute, Ulong) -> Rv,
+    _set_attribute_value: Unused,
+    find_objects_init: unsafe extern "C" fn(Ulong, *mut Attribute, Ulong) -> Rv,
+    find_objects: unsafe extern "C" fn(Ulong, *mut Ulong, Ulong, *mut Ulong) -> Rv,
+    find_objects_final: unsafe extern "C" fn(Ulong) -> Rv,
+    _encrypt_init: Unused,
+    _encrypt: Unused,
+    _encrypt_update: Unused,
+    _encrypt_final: Unused,
+    _decrypt_init: Unused,
+    _decrypt: Unused,
+    _decrypt_update: Unused,
+    _decrypt_final: Unused,
+    _digest_init: Unused,
+    _digest: Unused,
+    _digest_update: Unused,
+    _digest_key: Unused,
+    _digest_final: Unused,
+    sign_init: unsafe extern "C" fn(Ulong, *mut Mechanism, Ulong) -> Rv,
+    sign: unsafe extern "C" fn(Ulong, *const u8, Ulong, *mut u8, *mut Ulong) -> Rv,
+}
+
+type GetFunctionList = unsafe extern "C" fn(*mut *const FunctionList) -> Rv;
+
+/// Signs with a private key held in a PKCS#11 token
+pub struct Pkcs11Backend {
+    functions: &'static FunctionList,
+    token: String,
+    key_label: String,
+    pin: String,
+    // None once a lost session is closed, until one is opened again
+    session: Mutex<Option<Session>>,
+    algorithm: Algorithm,
+    public_key: Vec<u8>,
+}
+
+// A logged in session and the handle of the private key in it
+struct Session {
+    handle: Ulong,
+    key: Ulong,
+}
+
+// The module is initialized with CKF_OS_LOCKING_OK, so may be called from any
+// thread, and the session is only used while holding its mutex
+unsafe impl Send for Pkcs11Backend {}
+unsafe impl Sync for Pkcs11Backend {}
+
+impl Pkcs11Backend {
+    /// Creates a backend from the JSON of a `.pkcs11` reference file, loading
+    /// the module, logging in to the token and finding the key
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - Contents of the reference file
+    pub fn from_config(config: &[u8]) -> Result<Pkcs11Backend, String> {
+        let config: Config =
+            serde_json::from_slice(config).map_err(|e| format!("invalid PKCS#11 config: {}", e))?;
+        let pin = env::var(&config.pin_env)
+            .map_err(|_| format!("PIN environment variable {} is not set", config.pin_env))?;
+
+        let functions = load_module(&config.module)?;
+        let mut args = InitializeArgs {
+            create_mutex: ptr::null_mut(),
+            destroy_mutex: ptr::null_mut(),
+            lock_mutex: ptr::null_mut(),
+            unlock_mutex: ptr::null_mut(),
+            flags: CKF_OS_LOCKING_OK,
+            reserved: ptr::null_mut(),
+        };
+        // Another key may have initialized the module already
+        match unsafe { (functions.initialize)(&mut args) } {
+            CKR_OK | CKR_CRYPTOKI_ALREADY_INITIALIZED => {}
+            rv => return Err(failure("C_Initialize", rv)),
+        }
+
+        let mut backend = Pkcs11Backend {
+            functions,
+            token: config.token,
+            key_label: config.key_label,
+            pin,
+            session: Mutex::new(None),
+            algorithm: Algorithm::EdDsa,
+            public_key: Vec::new(),
+        };
+        let session = backend.open()?;
+        // Dropping the backend closes the session if the key can't be used
+        let key = (session.handle, session.key);
+        backend.session = Mutex::new(Some(session));
+        let (algorithm, public_key) = backend.describe(key.0, key.1)?;
+        backend.algorithm = algorithm;
+        backend.public_key = public_key;
+        Ok(backend)
+    }
+
+    // Opens a session with the token, logs in and finds the private key
+    fn open(&self) -> Result<Session, String> {
+        let functions = self.functions;
+        let slot = find_token(functions, &self.token)?;
+        let mut handle = 0;
+        check("C_OpenSession", unsafe {
+            (functions.open_session)(
+                slot,
+                CKF_SERIAL_SESSION,
+                ptr::null_mut(),
+                ptr::null_mut(),
+                &mut handle,
+            )
+        })?;
+        let pin = &self.pin;
+        // Sessions with a token share its login state
+        let key = match unsafe {
+            (functions.login)(handle, CKU_USER, pin.as_ptr(), pin.len() as Ulong)
+        } {
+            CKR_OK | CKR_USER_ALREADY_LOGGED_IN => {
+                find_key(functions, handle, CKO_PRIVATE_KEY, &self.key_label).and_then(|key| {
+                    key.ok_or_else(|| format!("no private key labelled {}", self.key_label))
+                })
+            }
+            rv => Err(failure("C_Login", rv)),
+        };
+        match key {
+            Ok(key) => Ok(Session { handle, key }),
+            Err(e) => {
+                unsafe { (functions.close_session)(handle) };
+                Err(e)
+            }
+        }
+    }
+
+    // The algorithm of the private key and its public key
+    fn describe(&self, session: Ulong, key: Ulong) -> Result<(Algorithm, Vec<u8>), String> {
+        let functions = self.functions;
+        let key_type = attribute(functions, session, key, CKA_KEY_TYPE)?;
+        let key_type = ulong_from_bytes(&key_type)?;
+        match key_type {
+            CKK_RSA => {
+                let modulus = attribute(functions, session, key, CKA_MODULUS)?;
+                let exponent = attribute(functions, session, key, CKA_PUBLIC_EXPONENT)?;
+                Ok((Algorithm::Ps256, rsa_public_key(&modulus, &exponent)))
+            }
+            CKK_EC | CKK_EC_EDWARDS => {
+                let public = find_key(functions, session, CKO_PUBLIC_KEY, &self.key_label)?
+                    .ok_or_else(|| format!("no public key labelled {}", self.key_label))?;
+                let point = attribute(functions, session, public, CKA_EC_POINT)?;
+                let point = ec_point(&point);
+                match (key_type, point.len()) {
+                    (CKK_EC, 65) => Ok((Algorithm::Es256, point.to_vec())),
+                    (CKK_EC_EDWARDS, 32) => Ok((Algorithm::EdDsa, point.to_vec())),
+                    _ => Err(String::from("not a P-256 or Ed25519 key")),
+                }
+            }
+            other => Err(format!("unsupported key type {:#x}", other)),
+        }
+    }
+
+    // Signs the data in the session, returning the failing function and
+    // its return value on error
+    fn sign_in(
+        &self,
+        session: &Session,
+        mechanism: &mut Mechanism,
+        data: &[u8],
+    ) -> Result<Vec<u8>, (&'static str, Rv)> {
+        let rv = unsafe { (self.functions.sign_init)(session.handle, mechanism, session.key) };
+        if rv != CKR_OK {
+            return Err(("C_SignInit", rv));
+        }
+        let mut signature = vec![0; MAX_SIGNATURE_LEN];
+        let mut len = signature.len() as Ulong;
+        let rv = unsafe {
+            (self.functions.sign)(
+                session.handle,
+                data.as_ptr(),
+                data.len() as Ulong,
+                signature.as_mut_ptr(),
+                &mut len,
+            )
+        };
+        if rv != CKR_OK {
+            return Err(("C_Sign", rv));
+        }
+        signature.truncate(len as usize);
+        Ok(signature)
+    }
+
+    fn close(&self, session: Session) {
+        unsafe { (self.functions.close_session)(session.handle) };
+    }
+}
+
+impl Drop for Pkcs11Backend {
+    fn drop(&mut self) {
+        let session = match self.session.get_mut() {
+            Ok(session) => session.take(),
+            Err(poisoned) => poisoned.into_inner().take(),
+        };
+        if let Some(session) = session {
+            self.close(session);
+        }
+    }
+}
+
+impl Backend for Pkcs11Backend {
+    fn algorithm(&self) -> Algorithm {
+        self.algorithm
+    }
+
+    fn sign(&self, message: &[u8]) -> Result<Vec<u8>, BackendError> {
+        let mut pss = PssParams {
+            hash: CKM_SHA256,
+            mgf: CKG_MGF1_SHA256,
+            salt_len: 32,
+        };
+        // The token hashes for RSA-PSS and signs Ed25519 messages whole, but
+        // ECDSA needs the digest
+        let (mut mechanism, data) = match self.algorithm {
+            Algorithm::Ps256 => (
+                Mechanism {
+                    kind: CKM_SHA256_RSA_PKCS_PSS,
+                    parameter: &mut pss as *mut PssParams as *mut c_void,
+                    len: std::mem::size_of::<PssParams>() as Ulong,
+                },
+                message.to_vec(),
+            ),
+            Algorithm::Es256 => (
+                mechanism(CKM_ECDSA),
+                digest(&SHA256, message).as_ref().to_vec(),
+            ),
+            Algorithm::EdDsa => (mechanism(CKM_EDDSA), message.to_vec()),
+        };
+
+        let mut session = match self.session.lock() {
+            Ok(session) => session,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        loop {
+            let fresh = session.is_none();
+            if fresh {
+                *session = Some(self.open().map_err(BackendError::Unavailable)?);
+            }
+            let signed = match session.as_ref() {
+                Some(current) => self.sign_in(current, &mut mechanism, &data),
+                None => continue,
+            };
+            let (function, rv) = match signed {
+                Ok(signature) => return Ok(signature),
+                Err(e) => e,
+            };
+            if !session_lost(rv) {
+                return Err(backend_error(function, rv));
+            }
+            if let Some(lost) = session.take() {
+                self.close(lost);
+            }
+            // A session opened for this signature being lost too means the
+            // token is gone for now; the next signature tries again
+            if fresh {
+                return Err(backend_error(function, rv));
+            }
+            warn!("PKCS#11 session lost, reopening: {}", failure(function, rv));
+        }
+    }
+
+    fn public_key(&self) -> &[u8] {
+        &self.public_key
+    }
+}
+
+fn mechanism(kind: Ulong) -> Mechanism {
+    Mechanism {
+        kind,
+        parameter: ptr::null_mut(),
+        len: 0,
+    }
+}
+
+fn failure(function: &str, rv: Rv) -> String {
+    format!("{} failed with {:#x}", function, rv)
+}
+
+fn check(function: &str, rv: Rv) -> Result<(), String> {
+    match rv {
+        CKR_OK => Ok(()),
+        rv => Err(failure(function, rv)),
+    }
+}
+
+// Whether the session can't be used again, so a new one must be opened
+fn session_lost(rv: Rv) -> bool {
+    matches!(
+        rv,
+        CKR_DEVICE_REMOVED
+            | CKR_SESSION_CLOSED
+            | CKR_SESSION_HANDLE_INVALID
+            | CKR_TOKEN_NOT_PRESENT
+    )
+}
+
+// A token that was removed or a session that was lost may come back, any
+// other failure won't
+fn backend_error(function: &str, rv: Rv) -> BackendError {
+    match rv {
+        CKR_DEVICE_ERROR
+        | CKR_DEVICE_REMOVED
+        | CKR_SESSION_CLOSED
+        | CKR_SESSION_HANDLE_INVALID
+        | CKR_TOKEN_NOT_PRESENT => BackendError::Unavailable(failure(function, rv)),
+        _ => BackendError::Failed(failure(function, rv)),
+    }
+}
+
+// Loads the module, returning its function list. The module stays loaded for
+// the life of the process.
+fn load_module(path: &str) -> Result<&'static FunctionList, String> {
+    let c_path = CString::new(path).map_err(|_| format!("invalid module path {}", path))?;
+    let symbol = CString::new("C_GetFunctionList").unwrap_or_default();
+    unsafe {
+        let handle = dlopen(c_path.as_ptr(), RTLD_NOW);
+        if handle.is_null() {
+            return Err(format!("failed to load {}: {}", path, last_dl_error()));
+        }
+        let get_function_list = dlsym(handle, symbol.as_ptr());
+        if get_function_list.is_null() {
+            return Err(format!("{} is not a PKCS#11 module", path));
+        }
+        let get_function_list: GetFunctionList = std::mem::transmute(get_function_list);
+        let mut functions: *const FunctionList = ptr::null();
+        check("C_GetFunctionList", get_function_list(&mut functions))?;
+        functions
+            .as_ref()
+            .ok_or_else(|| String::from("C_GetFunctionList returned no functions"))
+    }
+}
+
+fn last_dl_error() -> String {
+    unsafe {
+        let error = dlerror();
+        match error.is_null() {
+            true => String::from("unknown error"),
+            false => CStr::from_ptr(error).to_string_lossy().into_owned(),
+        }
+    }
+}
+
+// Returns the slot holding the token with the label
+fn find_token(functions: &FunctionList, label: &str) -> Result<Ulong, String> {
+    let mut count = 0;
+    check("C_GetSlotList", unsafe {
+        (functions.get_slot_list)(1, ptr::null_mut(), &mut count)
+    })?;
+    let mut slots = vec![0; count as usize];
+    check("C_GetSlotList", unsafe {
+        (functions.get_slot_list)(1, slots.as_mut_ptr(), &mut count)
+    })?;
+    slots.truncate(count as usize);
+
+    for slot in slots {
+        // CK_TOKEN_INFO starts with the label; the buffer is larger than the
+        // whole structure
+        let mut info = [0u8; 512];
+        check("C_GetTokenInfo", unsafe {
+            (functions.get_token_info)(slot, info.as_mut_ptr())
+        })?;
+        if token_label(&info[..TOKEN_LABEL_LEN]) == label {
+            return Ok(slot);
+        }
+    }
+    Err(format!("no token labelled {}", label))
+}
+
+fn token_label(padded: &[u8]) -> String {
+    String::from_utf8_lossy(padded).trim_end().to_string()
+}
+
+// Returns the first object of the class with the label
+fn find_key(
+    functions: &FunctionList,
+    session: Ulong,
+    class: Ulong,
+    label: &str,
+) -> Result<Option<Ulong>, String> {
+    let mut class = class;
+    let mut label = label.as_bytes().to_vec();
+    let mut template = [
+        Attribute {
+            kind: CKA_CLASS,
+            value: &mut class as *mut Ulong as *mut c_void,
+            len: std::mem::size_of::<Ulong>() as Ulong,
+        },
+        Attribute {
+            kind: CKA_LABEL,
+            value: label.as_mut_ptr() as *mut c_void,
+            len: label.len() as Ulong,
+        },
+    ];
+    check("C_FindObjectsInit", unsafe {
+        (functions.find_objects_init)(session, template.as_mut_ptr(), template.len() as Ulong)
+    })?;
+    let mut object = 0;
+    let mut found = 0;
+    let rv = unsafe { (functions.find_objects)(session, &mut object, 1, &mut found) };
+    unsafe { (functions.find_objects_final)(session) };
+    check("C_FindObjects", rv)?;
+    Ok(match found {
+        0 => None,
+        _ => Some(object),
+    })
+}
+
+// Reads an attribute of an object, asking for its length first
+fn attribute(
+    functions: &FunctionList,
+    session: Ulong,
+    object: Ulong,
+    kind: Ulong,
+) -> Result<Vec<u8>, String> {
+    let mut template = Attribute {
+        kind,
+        value: ptr::null_mut(),
+        len: 0,
+    };
+    check("C_GetAttributeValue", unsafe {
+        (functions.get_attribute_value)(session, object, &mut template, 1)
+    })?;
+    let mut value = vec![0u8; template.len as usize];
+    template.value = value.as_mut_ptr() as *mut c_void;
+    check("C_GetAttributeValue", unsafe {
+        (functions.get_attribute_value)(session, object, &mut template, 1)
+    })?;
+    value.truncate(template.len as usize);
+    Ok(value)
+}
+
+fn ulong_from_bytes(bytes: &[u8]) -> Result<Ulong, String> {
+    let mut ulong = [0u8; std::mem::size_of::<Ulong>()];
+    if bytes.len() != ulong.len() {
+        return Err(String::from("unexpected attribute length"));
+    }
+    ulong.copy_from_slice(bytes);
+    Ok(Ulong::from_ne_bytes(ulong))
+}
+
+// CKA_EC_POINT is a DER OCTET STRING holding the point, though some modules
+// return the bare point
+fn ec_point(value: &[u8]) -> &[u8] {
+    match value {
+        [der::OCTET_STRING, len, point @ ..] if *len as usize == point.len() && *len < 0x80 => point,
+        [der::OCTET_STRING, 0x81, len, point @ ..] if *len as usize == point.len() => point,
+        _ => value,
+    }
+}
+
+// Encodes a DER RSAPublicKey, the form ring verifies RSA signatures with
+fn rsa_public_key(modulus: &[u8], exponent: &[u8]) -> Vec<u8> {
+    der::sequence(&[der::integer(modulus), der::integer(exponent)])
+}
+
+// Unit tests
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::{fs, path::Path};
+
+    #[test]
+    fn encode_rsa_public_key() {
+        assert_eq!(
+            rsa_public_key(&[0xc1], &[3]),
+            vec![der::SEQUENCE, 7, der::INTEGER, 2, 0, 0xc1, der::INTEGER, 1, 3]
+        );
+        // A 2048 bit modulus needs a two byte length
+        let encoded = rsa_public_key(&[0xff; 256], &[1, 0, 1]);
+        assert_eq!(encoded[..4], [der::SEQUENCE, 0x82, 0x01, 0x0a]);
+        assert_eq!(encoded[4..9], [der::INTEGER, 0x82, 0x01, 0x01, 0]);
+    }
+
+    #[test]
+    fn unwrap_ec_points() {
+        let point = [0x04; 65];
+        let mut wrapped = vec![der::OCTET_STRING, 65];
+        wrapped.extend_from_slice(&point);
+        assert_eq!(ec_point(&wrapped), &point[..]);
+        assert_eq!(ec_point(&point), &point[..]);
+        assert_eq!(token_label(b"pickle                          "), "pickle");
+    }
+
+    #[test]
+    fn missing_module() {
+        std::env::set_var("PICKLE_TEST_PKCS11_PIN", "1234");
+        let config = br#"{
+            "module": "/nonexistent/libpkcs11.so",
+            "token": "pickle",
+            "key_label": "pickle",
+            "pin_env": "PICKLE_TEST_PKCS11_PIN"
+        }"#;
+        match Pkcs11Backend::from_config(config) {
+            Err(reason) => assert!(reason.contains("/nonexistent/libpkcs11.so")),
+            Ok(_) => panic!("expected a missing module to fail"),
+        }
+    }
+
+    // Signs with a key in SoftHSM, with SIGNER_PKCS11_TEST_CONFIG naming a
+    // reference file for one as set up in the README. Run with --ignored.
+    #[test]
+    #[ignore]
+    fn signs_with_token() {
+        let path = std::env::var("SIGNER_PKCS11_TEST_CONFIG")
+            .unwrap_or_else(|_| panic!("SIGNER_PKCS11_TEST_CONFIG is not set"));
+        let config = fs::read(Path::new(&path)).unwrap();
+        let backend = Pkcs11Backend::from_config(&config).unwrap_or_else(|e| panic!("{}", e));
+        let signature = backend.sign(b"pickle").unwrap();
+        assert!(backend
+            .algorithm()
+            .verify(backend.public_key(), b"pickle", &signature));
+    }
+
+    // A token whose sessions are lost after one signature, recording the
+    // sessions opened and closed
+    mod lossy {
+        use super::super::*;
+        use std::sync::atomic::{AtomicU64, Ordering};
+
+        pub static OPENED: AtomicU64 = AtomicU64::new(0);
+        // Bit n is set once session n is closed, or has signed
+        pub static CLOSED: AtomicU64 = AtomicU64::new(0);
+        static SIGNED: AtomicU64 = AtomicU64::new(0);
+
+        unsafe extern "C" fn initialize(_: *mut InitializeArgs) -> Rv {
+            CKR_OK
+        }
+        unsafe extern "C" fn get_slot_list(_: u8, slots: *mut Ulong, count: *mut Ulong) -> Rv {
+            if !slots.is_null() {
+                *slots = 1;
+            }
+            *count = 1;
+            CKR_OK
+        }
+        unsafe extern "C" fn get_token_info(_: Ulong, info: *mut u8) -> Rv {
+            ptr::copy_nonoverlapping(b"lossy".as_ptr(), info, 5);
+            ptr::write_bytes(info.add(5), b' ', TOKEN_LABEL_LEN - 5);
+            CKR_OK
+        }
+        unsafe extern "C" fn open_session(
+            _: Ulong,
+            _: Ulong,
+            _: *mut c_void,
+            _: *mut c_void,
+            session: *mut Ulong,
+        ) -> Rv {
+            *session = OPENED.fetch_add(1, Ordering::SeqCst) as Ulong + 1;
+            CKR_OK
+        }
+        unsafe extern "C" fn close_session(session: Ulong) -> Rv {
+            CLOSED.fetch_or(1 << session, Ordering::SeqCst);
+            CKR_OK
+        }
+        unsafe extern "C" fn login(_: Ulong, _: Ulong, _: *const u8, _: Ulong) -> Rv {
+            CKR_OK
+        }
+        unsafe extern "C" fn get_attribute_value(
+            _: Ulong,
+            _: Ulong,
+            _: *mut Attribute,
+            _: Ulong,
+        ) -> Rv {
+            CKR_DEVICE_ERROR
+        }
+        unsafe extern "C" fn find_objects_init(_: Ulong, _: *mut Attribute, _: Ulong) -> Rv {
+            CKR_OK
+        }
+        unsafe extern "C" fn find_objects(
+            _: Ulong,
+            object: *mut Ulong,
+            _: Ulong,
+            found: *mut Ulong,
+        ) -> Rv {
+            *object = 7;
+            *found = 1;
+            CKR_OK
+        }
+        unsafe extern "C" fn find_objects_final(_: Ulong) -> Rv {
+            CKR_OK
+        }
+        unsafe extern "C" fn sign_init(_: Ulong, _: *mut Mechanism, _: Ulong) -> Rv {
+            CKR_OK
+        }
+        // Each session signs once, then is lost
+        unsafe extern "C" fn sign(
+            session: Ulong,
+            _: *const u8,
+            _: Ulong,
+            signature: *mut u8,
+            len: *mut Ulong,
+        ) -> Rv {
+            if SIGNED.fetch_or(1 << session, Ordering::SeqCst) & 1 << session != 0 {
+                return CKR_SESSION_HANDLE_INVALID;
+            }
+            *signature = session as u8;
+            *len = 1;
+            CKR_OK
+        }
+
+        pub static FUNCTIONS: FunctionList = FunctionList {
+            version: [2, 40],
+            initialize,
+            _finalize: None,
+            _get_info: None,
+            _get_function_list: None,
+            get_slot_list,
+            _get_slot_info: None,
+            get_token_info,
+            _get_mechanism_list: None,
+            _get_mechanism_info: None,
+            _init_token: None,
+            _init_pin: None,
+            _set_pin: None,
+            open_session,
+            close_session,
+            _close_all_sessions: None,
+            _get_session_info: None,
+            _get_operation_state: None,
+            _set_operation_state: None,
+            login,
+            _logout: None,
+            _create_object: None,
+            _copy_object: None,
+            _destroy_object: None,
+            _get_object_size: None,
+            get_attribute_value,
+            _set_attribute_value: None,
+            find_objects_init,
+            find_objects,
+            find_objects_final,
+            _encrypt_init: None,
+            _encrypt: None,
+            _encrypt_update: None,
+            _encrypt_final: None,
+            _decrypt_init: None,
+            _decrypt: None,
+            _decrypt_update: None,
+            _decrypt_final: None,
+            _digest_init: None,
+            _digest: None,
+            _digest_update: None,
+            _digest_key: None,
+            _digest_final: None,
+            sign_init,
+            sign,
+        };
+    }
+
+    #[test]
+    fn reopens_lost_sessions() {
+        use std::sync::atomic::Ordering;
+
+        let backend = Pkcs11Backend {
+            functions: &lossy::FUNCTIONS,
+            token: String::from("lossy"),
+            key_label: String::from("pickle"),
+            pin: String::from("1234"),
+            session: Mutex::new(None),
+            algorithm: Algorithm::EdDsa,
+            public_key: Vec::new(),
+        };
+        // The first signature opens a session, the second finds it lost and
+        // signs in a new one
+        assert_eq!(backend.sign(b"pickle").unwrap(), [1]);
+        assert_eq!(backend.sign(b"pickle").unwrap(), [2]);
+        assert_eq!(lossy::CLOSED.load(Ordering::SeqCst), 0b10);
+
+        drop(backend);
+        assert_eq!(lossy::CLOSED.load(Ordering::SeqCst), 0b110);
+        assert_eq!(lossy::OPENED.load(Ordering::SeqCst), 2);
+    }
+}
@@ -0,0 +1,249 @@
+//
+// The signing pool runs signatures on a fixed set of threads of its own,
+// rather than on tokio's workers or its unbounded blocking pool, so slow RSA
+// operations, tokens or remote services can't stall the reactor or pile up
+// without limit. Work waits in a bounded queue; when the queue is full new
+// work is refused at once, so callers can back off instead of timing out.
+//
+// The pool keeps metrics separating the time work waits in the queue from
+// the time it takes to run, which signer serves for Prometheus.
+//
+
+use crate::metrics::{self, Histogram};
+use log::error;
+use std::{
+    error::Error as StdError,
+    fmt,
+    panic::{catch_unwind, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{sync_channel, Receiver, SyncSender, TrySendError},
+        Arc, Mutex,
+    },
+    thread,
+    time::Instant,
+};
+use tokio::sync::oneshot;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PoolError {
+    /// The queue is full, so the work was refused
+    Full,
+    /// The work panicked, or the pool stopped before running it
+    Failed,
+}
+
+impl fmt::Display for PoolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PoolError::Full => f.write_str("signing queue is full"),
+            PoolError::Failed => f.write_str("signing work failed to complete"),
+        }
+    }
+}
+
+impl StdError for PoolError {}
+
+#[derive(Default)]
+struct Metrics {
+    queue_wait: Histogram,
+    run_time: Histogram,
+    queued: AtomicU64,
+    running: AtomicU64,
+    rejected: AtomicU64,
+}
+
+pub struct SigningPool {
+    sender: SyncSender<Job>,
+    metrics: Arc<Metrics>,
+    threads: usize,
+    queue_limit: usize,
+}
+
+impl SigningPool {
+    /// Starts the pool's threads
+    ///
+    /// # Arguments
+    ///
+    /// * `threads` - Number of threads running work
+    /// * `queue_limit` - Most work that can wait for a thread
+    pub fn new(threads: usize, queue_limit: usize) -> SigningPool {
+        let (sender, receiver) = sync_channel::<Job>(queue_limit);
+        let receiver = Arc::new(Mutex::new(receiver));
+        for i in 0..threads {
+            let receiver = receiver.clone();
+            let spawned = thread::Builder::new()
+                .name(format!("signing-{}", i))
+                .spawn(move || work(&receiver));
+            if let Err(e) = spawned {
+                error!("Failed to start signing thread: {}", e);
+            }
+        }
+        SigningPool {
+            sender,
+            metrics: Arc::new(Metrics::default()),
+            threads,
+            queue_limit,
+        }
+    }
+
+    /// Runs the function on the pool, returning its result, or refuses it if
+    /// the queue is full
+    pub async fn run<T, F>(&self, f: F) -> Result<T, PoolError>
+    where
+        T: Send + 'static,
+        F: FnOnce() -> T + Send + 'static,
+    {
+        let (sender, receiver) = oneshot::channel();
+        let metrics = self.metrics.clone();
+        let enqueued = Instant::now();
+        let job: Job = Box::new(move || {
+            let started = Instant::now();
+            metrics.queue_wait.observe(started - enqueued);
+            metrics.queued.fetch_sub(1, Ordering::Relaxed);
+            metrics.running.fetch_add(1, Ordering::Relaxed);
+            let result = catch_unwind(AssertUnwindSafe(f));
+            metrics.running.fetch_sub(1, Ordering::Relaxed);
+            metrics.run_time.observe(started.elapsed());
+            if let Ok(result) = result {
+                let _ = sender.send(result);
+            }
+        });
+
+        self.metrics.queued.fetch_add(1, Ordering::Relaxed);
+        if let Err(e) = self.sender.try_send(job) {
+            self.metrics.queued.fetch_sub(1, Ordering::Relaxed);
+            return Err(match e {
+                TrySendError::Full(_) => {
+                    self.metrics.rejected.fetch_add(1, Ordering::Relaxed);
+                    PoolError::Full
+                }
+                TrySendError::Disconnected(_) => PoolError::Failed,
+            });
+        }
+        receiver.await.map_err(|_| PoolError::Failed)
+    }
+
+    /// The pool's metrics in the Prometheus text exposition format
+    pub fn render_metrics(&self) -> String {
+        let m = &self.metrics;
+        let mut out = String::new();
+        m.queue_wait.render(
+            &mut out,
+            "signer_pool_queue_wait_seconds",
+            "Time signing work waited for a thread",
+        );
+        m.run_time.render(
+            &mut out,
+            "signer_pool_run_seconds",
+            "Time signing work took to run",
+        );
+        for (name, kind, help, value) in &[
+            (
+                "signer_pool_queued",
+                "gauge",
+                "Signing work waiting for a thread",
+                m.queued.load(Ordering::Relaxed),
+            ),
+            (
+                "signer_pool_running",
+                "gauge",
+                "Signing work running",
+                m.running.load(Ordering::Relaxed),
+            ),
+            (
+                "signer_pool_rejected_total",
+                "counter",
+                "Signing work refused because the queue was full",
+                m.rejected.load(Ordering::Relaxed),
+            ),
+            (
+                "signer_pool_threads",
+                "gauge",
+                "Threads running signing work",
+                self.threads as u64,
+            ),
+            (
+                "signer_pool_queue_limit",
+                "gauge",
+                "Most signing work that can wait for a thread",
+                self.queue_limit as u64,
+            ),
+        ] {
+            metrics::render_value(&mut out, name, kind, help, *value);
+        }
+        out
+    }
+}
+
+// Runs jobs until the pool is dropped
+fn work(receiver: &Mutex<Receiver<Job>>) {
+    loop {
+        let job = match receiver.lock() {
+            Ok(receiver) => receiver.recv(),
+            Err(poisoned) => poisoned.into_inner().recv(),
+        };
+        match job {
+            Ok(job) => job(),
+            Err(_) => return,
+        }
+    }
+}
+
+// Unit tests
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::{sync::mpsc::channel, time::Duration};
+
+    #[tokio::test]
+    async fn runs_work() {
+        let pool = SigningPool::new(2, 4);
+        assert_eq!(pool.run(|| 6 * 7).await, Ok(42));
+        assert_eq!(
+            pool.run(|| -> u32 { panic!("oops") }).await,
+            Err(PoolError::Failed)
+        );
+        // A panic doesn't stop the thread it ran on
+        assert_eq!(pool.run(|| 1).await, Ok(1));
+        assert_eq!(pool.run(|| 2).await, Ok(2));
+        assert_eq!(pool.metrics.run_time.count(), 4);
+    }
+
+    #[tokio::test]
+    async fn refuses_work_when_full() {
+        let pool = Arc::new(SigningPool::new(1, 1));
+
+        // Block the only thread, then fill the queue
+        let (release, blocked) = channel::<()>();
+        let blocked = Mutex::new(blocked);
+        let running = tokio::spawn({
+            let pool = pool.clone();
+            async move { pool.run(move || blocked.lock().unwrap().recv().is_ok()).await }
+        });
+        while pool.metrics.running.load(Ordering::Relaxed) == 0 {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+        let queued = tokio::spawn({
+            let pool = pool.clone();
+            async move { pool.run(|| "queued").await }
+        });
+        while pool.metrics.queued.load(Ordering::Relaxed) == 0 {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+
+        assert_eq!(pool.run(|| "refused").await, Err(PoolError::Full));
+        release.send(()).unwrap();
+        assert_eq!(running.await.unwrap(), Ok(true));
+        assert_eq!(queued.await.unwrap(), Ok("queued"));
+
+        let metrics = pool.render_metrics();
+        assert!(metrics.contains("signer_pool_rejected_total 1\n"));
+        assert!(metrics.contains("signer_pool_queue_wait_seconds_count 2\n"));
+        assert!(metrics.contains("signer_pool_queued 0\n"));
+    }
+}
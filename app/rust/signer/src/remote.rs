@@ -0,0 +1,245 @@
+//
+// A backend that asks a remote signing service to sign, so the private key
+// never has to be in signer's container. A key is configured by a reference
+// file named `<kid>.remote` in place of a key file, holding JSON like:
+//
+//   {
+//     "url": "http://kms.pickle:8200/v1/sign/pickle",
+//     "algorithm": "ES256",
+//     "public_key": "<base64 public key>",
+//     "timeout_ms": 2000
+//   }
+//
+// The public key is in the encoding Backend::public_key returns, and is
+// configured rather than fetched, so loading keys never waits on the service.
+// signer POSTs `{"message": "<base64>"}` to the url and expects
+// `{"signature": "<base64>"}` back. The service being unreachable, timing out
+// or answering 429 or 5xx makes the backend unavailable, so callers retry;
+// any other failure fails the signature.
+//
+
+use crate::backend::{Algorithm, Backend, BackendError};
+use base64::{decode, encode};
+use reqwest::{blocking::Client, StatusCode};
+use rocket::serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+const DEFAULT_TIMEOUT_MS: u64 = 2000;
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct Config {
+    url: String,
+    algorithm: String,
+    public_key: String,
+    timeout_ms: Option<u64>,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+struct SignRequest {
+    message: String,
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct SignResponse {
+    signature: String,
+}
+
+/// Signs by calling a remote signing service
+pub struct RemoteBackend {
+    url: String,
+    algorithm: Algorithm,
+    public_key: Vec<u8>,
+    client: Client,
+}
+
+impl RemoteBackend {
+    /// Creates a backend from the JSON of a `.remote` reference file
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - Contents of the reference file
+    pub fn from_config(config: &[u8]) -> Result<RemoteBackend, String> {
+        let config: Config =
+            serde_json::from_slice(config).map_err(|e| format!("invalid remote config: {}", e))?;
+        let algorithm = Algorithm::from_id(&config.algorithm)
+            .ok_or_else(|| format!("unsupported algorithm {}", config.algorithm))?;
+        let public_key =
+            decode(&config.public_key).map_err(|e| format!("invalid public_key: {}", e))?;
+        let client = Client::builder()
+            .timeout(Duration::from_millis(
+                config.timeout_ms.unwrap_or(DEFAULT_TIMEOUT_MS),
+            ))
+            .build()
+            .map_err(|e| e.to_string())?;
+        Ok(RemoteBackend {
+            url: config.url,
+            algorithm,
+            public_key,
+            client,
+        })
+    }
+}
+
+impl Backend for RemoteBackend {
+    fn algorithm(&self) -> Algorithm {
+        self.algorithm
+    }
+
+    fn sign(&self, message: &[u8]) -> Result<Vec<u8>, BackendError> {
+        let response = self
+            .client
+            .post(&self.url)
+            .json(&SignRequest {
+                message: encode(message),
+            })
+            .send()
+            .map_err(|e| BackendError::Unavailable(format!("{}: {}", self.url, e)))?;
+
+        let status = response.status();
+        if status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error() {
+            return Err(BackendError::Unavailable(format!(
+                "{} answered {}",
+                self.url, status
+            )));
+        }
+        if !status.is_success() {
+            return Err(BackendError::Failed(format!(
+                "{} answered {}",
+                self.url, status
+            )));
+        }
+        let body: SignResponse = response
+            .json()
+            .map_err(|e| BackendError::Failed(format!("invalid response: {}", e)))?;
+        decode(&body.signature)
+            .map_err(|e| BackendError::Failed(format!("invalid signature: {}", e)))
+    }
+
+    fn public_key(&self) -> &[u8] {
+        &self.public_key
+    }
+}
+
+// Unit tests
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::keys;
+    use serde_json::{json, Value};
+    use std::{
+        io::{BufRead, BufReader, Read, Write},
+        net::TcpListener,
+        path::Path,
+        thread,
+    };
+
+    // A local stand-in for a signing service, answering each request with
+    // the status, signing the message with the backend when it is 200.
+    // Returns the url it serves.
+    fn serve(backend: Box<dyn Backend>, status: u16) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/sign", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line.trim().is_empty() {
+                        break;
+                    }
+                    let lower = line.to_ascii_lowercase();
+                    if let Some(value) = lower.strip_prefix("content-length:") {
+                        length = value.trim().parse().unwrap();
+                    }
+                }
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+
+                let request: Value = serde_json::from_slice(&body).unwrap();
+                let message = decode(request["message"].as_str().unwrap()).unwrap();
+                let reply = match status {
+                    200 => json!({ "signature": encode(backend.sign(&message).unwrap()) }),
+                    _ => json!({ "error": "mock failure" }),
+                }
+                .to_string();
+                write!(
+                    stream,
+                    "HTTP/1.1 {} Mock\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    reply.len(),
+                    reply
+                )
+                .unwrap();
+            }
+        });
+        url
+    }
+
+    fn ed25519() -> Box<dyn Backend> {
+        keys::load_key(Path::new("keys/test/ed25519.pem")).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    fn remote(url: &str, public_key: &[u8]) -> RemoteBackend {
+        let config = json!({
+            "url": url,
+            "algorithm": "EdDSA",
+            "public_key": encode(public_key),
+            "timeout_ms": 500,
+        });
+        RemoteBackend::from_config(config.to_string().as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn signs_with_remote_service() {
+        let local = ed25519();
+        let backend = remote(&serve(ed25519(), 200), local.public_key());
+
+        let signature = backend.sign(b"pickle").unwrap();
+        assert_eq!(backend.algorithm(), Algorithm::EdDsa);
+        assert!(backend
+            .algorithm()
+            .verify(backend.public_key(), b"pickle", &signature));
+    }
+
+    #[test]
+    fn remote_failures() {
+        let public_key = ed25519().public_key().to_vec();
+
+        let busy = remote(&serve(ed25519(), 503), &public_key);
+        assert!(matches!(
+            busy.sign(b"pickle"),
+            Err(BackendError::Unavailable(_))
+        ));
+        let rejected = remote(&serve(ed25519(), 400), &public_key);
+        assert!(matches!(
+            rejected.sign(b"pickle"),
+            Err(BackendError::Failed(_))
+        ));
+
+        // Nothing listening
+        let addr = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let down = remote(&format!("http://{}/sign", addr), &public_key);
+        assert!(matches!(
+            down.sign(b"pickle"),
+            Err(BackendError::Unavailable(_))
+        ));
+    }
+
+    #[test]
+    fn invalid_config() {
+        assert!(RemoteBackend::from_config(b"not json").is_err());
+        let config = json!({ "url": "http://kms", "algorithm": "HS256", "public_key": "" });
+        assert!(RemoteBackend::from_config(config.to_string().as_bytes()).is_err());
+    }
+}
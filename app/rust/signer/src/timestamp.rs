@@ -0,0 +1,445 @@
+//
+// Signer can act as a timestamp authority, issuing RFC 3161 TimeStampTokens
+// that prove a hash existed at a time, in a form standard tools like
+// `openssl ts` understand. A token is a CMS SignedData (RFC 5652) whose
+// content is a TSTInfo holding the hash, the time and the policy the token
+// was issued under, signed by the active key.
+//
+// Tools like `openssl ts` only accept tokens naming the certificate of their
+// signer, so the authority can be given a certificate for the active key. The
+// token then names it by issuer and serial number, carries it, and binds it
+// with a signing certificate attribute (RFC 5816). Without one, or once the
+// active key is rotated away from it, the signer is named by a subject key
+// identifier, the SHA-1 of its public key (RFC 5280 4.2.1.2), and tokens are
+// checked with the key from GetPublicKeys.
+//
+
+use crate::{
+    backend::{Algorithm, Backend, BackendError},
+    der,
+};
+use ring::{
+    digest::{digest, SHA1_FOR_LEGACY_USE_ONLY, SHA256, SHA512},
+    rand::{SecureRandom, SystemRandom},
+};
+
+// Object identifiers
+const ID_SIGNED_DATA: &[u64] = &[1, 2, 840, 113549, 1, 7, 2];
+const ID_CT_TST_INFO: &[u64] = &[1, 2, 840, 113549, 1, 9, 16, 1, 4];
+const ID_CONTENT_TYPE: &[u64] = &[1, 2, 840, 113549, 1, 9, 3];
+const ID_MESSAGE_DIGEST: &[u64] = &[1, 2, 840, 113549, 1, 9, 4];
+const ID_SIGNING_CERTIFICATE_V2: &[u64] = &[1, 2, 840, 113549, 1, 9, 16, 2, 47];
+const ID_SHA256: &[u64] = &[2, 16, 840, 1, 101, 3, 4, 2, 1];
+const ID_SHA512: &[u64] = &[2, 16, 840, 1, 101, 3, 4, 2, 3];
+const ID_RSASSA_PSS: &[u64] = &[1, 2, 840, 113549, 1, 1, 10];
+const ID_MGF1: &[u64] = &[1, 2, 840, 113549, 1, 1, 8];
+const ECDSA_WITH_SHA256: &[u64] = &[1, 2, 840, 10045, 4, 3, 2];
+const ID_ED25519: &[u64] = &[1, 3, 101, 112];
+
+/// Length of the SHA-256 hashes tokens are issued over
+pub const HASH_LEN: usize = 32;
+
+// Bytes of randomness in a token's serial number
+const SERIAL_LEN: usize = 16;
+
+pub struct TimestampAuthority {
+    policy: Vec<u64>,
+    certificate: Option<Certificate>,
+    rng: SystemRandom,
+}
+
+// The fields of an X.509 certificate a token needs
+struct Certificate {
+    der: Vec<u8>,
+    // Encoded issuer Name and serial number INTEGER
+    issuer: Vec<u8>,
+    serial: Vec<u8>,
+    // Contents of the subjectPublicKey BIT STRING, which is the key in the
+    // encoding backends use
+    public_key: Vec<u8>,
+}
+
+impl Certificate {
+    fn parse(der: &[u8]) -> Option<Certificate> {
+        let (_, certificate, _) = der::split(der, der::SEQUENCE)?;
+        let (_, tbs, _) = der::split(certificate, der::SEQUENCE)?;
+        // The version is optional
+        let tbs = match der::split(tbs, der::context(0, true)) {
+            Some((_, _, rest)) => rest,
+            None => tbs,
+        };
+        let (serial, _, rest) = der::split(tbs, der::INTEGER)?;
+        let (_, _, rest) = der::split(rest, der::SEQUENCE)?;
+        let (issuer, _, rest) = der::split(rest, der::SEQUENCE)?;
+        let (_, _, rest) = der::split(rest, der::SEQUENCE)?;
+        let (_, _, rest) = der::split(rest, der::SEQUENCE)?;
+        let (_, spki, _) = der::split(rest, der::SEQUENCE)?;
+        let (_, _, rest) = der::split(spki, der::SEQUENCE)?;
+        let (_, key, _) = der::split(rest, der::BIT_STRING)?;
+        // Keys are whole bytes, so no bits are unused
+        let public_key = match key.split_first()? {
+            (0, public_key) => public_key,
+            _ => return None,
+        };
+        Some(Certificate {
+            der: der.to_vec(),
+            issuer: issuer.to_vec(),
+            serial: serial.to_vec(),
+            public_key: public_key.to_vec(),
+        })
+    }
+}
+
+impl TimestampAuthority {
+    /// Creates an authority issuing tokens under the policy
+    ///
+    /// # Arguments
+    ///
+    /// * `policy` - Object identifier of the policy, in dotted form
+    /// * `certificate` - DER X.509 certificate for the active key
+    pub fn new(policy: &str, certificate: Option<&[u8]>) -> Result<TimestampAuthority, String> {
+        let policy = der::parse_oid(policy)
+            .ok_or_else(|| format!("{} is not an object identifier", policy))?;
+        let certificate = match certificate {
+            Some(der) => Some(
+                Certificate::parse(der)
+                    .ok_or_else(|| String::from("malformed X.509 certificate"))?,
+            ),
+            None => None,
+        };
+        Ok(TimestampAuthority {
+            policy,
+            certificate,
+            rng: SystemRandom::new(),
+        })
+    }
+
+    /// Whether the authority's certificate is for the backend's key
+    pub fn certifies(&self, backend: &dyn Backend) -> bool {
+        match &self.certificate {
+            Some(certificate) => certificate.public_key == backend.public_key(),
+            None => false,
+        }
+    }
+
+    /// Issues a DER TimeStampToken over the hash
+    ///
+    /// # Arguments
+    ///
+    /// * `backend` - Backend holding the signing key
+    /// * `hash` - SHA-256 hash being timestamped
+    /// * `timestamp` - Milliseconds since the unix epoch
+    /// * `nonce` - Nonce from the caller, returned in the token
+    pub fn token(
+        &self,
+        backend: &dyn Backend,
+        hash: &[u8],
+        timestamp: u64,
+        nonce: Option<u64>,
+    ) -> Result<Vec<u8>, BackendError> {
+        let mut serial = [0; SERIAL_LEN];
+        self.rng
+            .fill(&mut serial)
+            .map_err(|_| BackendError::Failed(String::from("no serial number")))?;
+
+        let mut tst_info = vec![
+            der::uint(1),
+            der::oid(&self.policy),
+            der::sequence(&[algorithm_identifier(ID_SHA256), der::octet_string(hash)]),
+            der::integer(&serial),
+            der::generalized_time(timestamp),
+        ];
+        if let Some(nonce) = nonce {
+            tst_info.push(der::uint(nonce));
+        }
+        let tst_info = der::sequence(&tst_info);
+        let certificate = match self.certifies(backend) {
+            true => self.certificate.as_ref(),
+            false => None,
+        };
+        sign(backend, certificate, &tst_info)
+    }
+}
+
+// Wraps the TSTInfo in a SignedData with one signer, as a ContentInfo
+fn sign(
+    backend: &dyn Backend,
+    certificate: Option<&Certificate>,
+    tst_info: &[u8],
+) -> Result<Vec<u8>, BackendError> {
+    let algorithm = backend.algorithm();
+    // Ed25519 signatures with signed attributes use SHA-512 (RFC 8419)
+    let (digest_algorithm, message_digest) = match algorithm {
+        Algorithm::EdDsa => (ID_SHA512, digest(&SHA512, tst_info)),
+        Algorithm::Ps256 | Algorithm::Es256 => (ID_SHA256, digest(&SHA256, tst_info)),
+    };
+
+    // The signature is over the attributes encoded as a SET, though they are
+    // sent with an implicit tag
+    let mut attributes = vec![
+        attribute(ID_CONTENT_TYPE, der::oid(ID_CT_TST_INFO)),
+        attribute(
+            ID_MESSAGE_DIGEST,
+            der::octet_string(message_digest.as_ref()),
+        ),
+    ];
+    if let Some(certificate) = certificate {
+        // A SigningCertificateV2 holding one ESSCertIDv2, with the default
+        // SHA-256 hash
+        let cert_hash = digest(&SHA256, &certificate.der);
+        let cert_id = der::sequence(&[der::octet_string(cert_hash.as_ref())]);
+        attributes.push(attribute(
+            ID_SIGNING_CERTIFICATE_V2,
+            der::sequence(&[der::sequence(&[cert_id])]),
+        ));
+    }
+    let signed_attributes = der::set(&attributes);
+    let signature = backend.sign(&signed_attributes)?;
+    let signature = match algorithm {
+        Algorithm::Es256 => ecdsa_signature(&signature)?,
+        Algorithm::Ps256 | Algorithm::EdDsa => signature,
+    };
+
+    let (version, signer_id) = match certificate {
+        Some(certificate) => (
+            1,
+            der::sequence(&[certificate.issuer.clone(), certificate.serial.clone()]),
+        ),
+        None => {
+            let key_id = digest(&SHA1_FOR_LEGACY_USE_ONLY, backend.public_key());
+            (3, der::element(der::context(0, false), key_id.as_ref()))
+        }
+    };
+    let signer_info = der::sequence(&[
+        der::uint(version),
+        signer_id,
+        algorithm_identifier(digest_algorithm),
+        der::retag(der::context(0, true), signed_attributes),
+        signature_algorithm(algorithm),
+        der::octet_string(&signature),
+    ]);
+    let mut signed_data = vec![
+        der::uint(3),
+        der::set(&[algorithm_identifier(digest_algorithm)]),
+        der::sequence(&[
+            der::oid(ID_CT_TST_INFO),
+            der::element(der::context(0, true), &der::octet_string(tst_info)),
+        ]),
+    ];
+    if let Some(certificate) = certificate {
+        signed_data.push(der::element(der::context(0, true), &certificate.der));
+    }
+    signed_data.push(der::set(&[signer_info]));
+    let signed_data = der::sequence(&signed_data);
+    Ok(der::sequence(&[
+        der::oid(ID_SIGNED_DATA),
+        der::element(der::context(0, true), &signed_data),
+    ]))
+}
+
+// An AlgorithmIdentifier without parameters
+fn algorithm_identifier(oid: &[u64]) -> Vec<u8> {
+    der::sequence(&[der::oid(oid)])
+}
+
+fn attribute(oid: &[u64], value: Vec<u8>) -> Vec<u8> {
+    der::sequence(&[der::oid(oid), der::set(&[value])])
+}
+
+// The AlgorithmIdentifier of the signature, with RSASSA-PSS parameters
+// matching ring's: SHA-256, MGF1 with SHA-256 and a 32 byte salt (RFC 4055)
+fn signature_algorithm(algorithm: Algorithm) -> Vec<u8> {
+    match algorithm {
+        Algorithm::Ps256 => der::sequence(&[
+            der::oid(ID_RSASSA_PSS),
+            der::sequence(&[
+                der::element(der::context(0, true), &algorithm_identifier(ID_SHA256)),
+                der::element(
+                    der::context(1, true),
+                    &der::sequence(&[der::oid(ID_MGF1), algorithm_identifier(ID_SHA256)]),
+                ),
+                der::element(der::context(2, true), &der::uint(32)),
+            ]),
+        ]),
+        Algorithm::Es256 => algorithm_identifier(ECDSA_WITH_SHA256),
+        Algorithm::EdDsa => algorithm_identifier(ID_ED25519),
+    }
+}
+
+// CMS carries ECDSA signatures as a DER Ecdsa-Sig-Value rather than the fixed
+// r || s form backends return
+fn ecdsa_signature(fixed: &[u8]) -> Result<Vec<u8>, BackendError> {
+    if fixed.is_empty() || !fixed.len().is_multiple_of(2) {
+        return Err(BackendError::Failed(format!(
+            "ECDSA signature of {} bytes",
+            fixed.len()
+        )));
+    }
+    let (r, s) = fixed.split_at(fixed.len() / 2);
+    Ok(der::sequence(&[der::integer(r), der::integer(s)]))
+}
+
+// Unit tests
+
+#[cfg(test)]
+pub(crate) mod tests {
+
+    use super::*;
+    use crate::keys;
+    use std::path::Path;
+
+    const POLICY: &str = "1.2.3.4.1";
+
+    // Splits an element with the tag off the front of the input, returning
+    // its contents and the rest of the input
+    fn read(input: &[u8], tag: u8) -> (&[u8], &[u8]) {
+        let (_, contents, rest) = der::split(input, tag).unwrap();
+        (contents, rest)
+    }
+
+    // The parts of a token the tests check, here and in main
+    pub(crate) struct Token {
+        pub tst_info: Vec<u8>,
+        pub certificates: Option<Vec<u8>>,
+        pub signer_version: Vec<u8>,
+        // The signed attributes, encoded as a SET as they are signed
+        pub attributes: Vec<u8>,
+        pub signature: Vec<u8>,
+    }
+
+    impl Token {
+        // The hash the token was issued over, from the TSTInfo's imprint
+        pub fn hash(&self) -> Vec<u8> {
+            let (tst_info, _) = read(&self.tst_info, der::SEQUENCE);
+            let (_, rest) = read(tst_info, der::INTEGER);
+            let (_, rest) = read(rest, der::OBJECT_IDENTIFIER);
+            let (imprint, _) = read(rest, der::SEQUENCE);
+            let (_, imprint_hash) = read(imprint, der::SEQUENCE);
+            read(imprint_hash, der::OCTET_STRING).0.to_vec()
+        }
+    }
+
+    // Reads a token, checking the structure around the parts
+    pub(crate) fn open(token: &[u8]) -> Token {
+        let (content_info, _) = read(token, der::SEQUENCE);
+        let (oid, rest) = read(content_info, der::OBJECT_IDENTIFIER);
+        assert_eq!(&der::oid(ID_SIGNED_DATA)[2..], oid);
+        let (signed_data, _) = read(rest, der::context(0, true));
+        let (signed_data, _) = read(signed_data, der::SEQUENCE);
+        let (_, rest) = read(signed_data, der::INTEGER);
+        let (_, rest) = read(rest, der::SET);
+        let (encapsulated, rest) = read(rest, der::SEQUENCE);
+        let (_, content) = read(encapsulated, der::OBJECT_IDENTIFIER);
+        let (content, _) = read(content, der::context(0, true));
+        let (tst_info, _) = read(content, der::OCTET_STRING);
+        let (certificates, rest) = match der::split(rest, der::context(0, true)) {
+            Some((_, certificates, rest)) => (Some(certificates.to_vec()), rest),
+            None => (None, rest),
+        };
+        let (signer_infos, _) = read(rest, der::SET);
+        let (signer_info, _) = read(signer_infos, der::SEQUENCE);
+        let (signer_version, rest) = read(signer_info, der::INTEGER);
+        let rest = match der::split(rest, der::context(0, false)) {
+            Some((_, _, rest)) => rest,
+            None => read(rest, der::SEQUENCE).1,
+        };
+        let (_, rest) = read(rest, der::SEQUENCE);
+        let (attributes, rest) = read(rest, der::context(0, true));
+        let (_, rest) = read(rest, der::SEQUENCE);
+        let (signature, _) = read(rest, der::OCTET_STRING);
+        Token {
+            tst_info: tst_info.to_vec(),
+            certificates,
+            signer_version: signer_version.to_vec(),
+            attributes: der::element(der::SET, attributes),
+            signature: signature.to_vec(),
+        }
+    }
+
+    fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+        haystack.windows(needle.len()).any(|w| w == needle)
+    }
+
+    #[test]
+    fn token_over_hash() {
+        let backend = keys::load_key(Path::new("keys/test/ed25519.pem")).unwrap();
+        let tsa = TimestampAuthority::new(POLICY, None).unwrap();
+        let hash = [7; HASH_LEN];
+        let token = tsa
+            .token(backend.as_ref(), &hash, 1_700_000_000_005, Some(42))
+            .unwrap();
+
+        let token = open(&token);
+        assert_eq!(token.certificates, None);
+        assert_eq!(token.signer_version, [3]);
+        assert!(backend.algorithm().verify(
+            backend.public_key(),
+            &token.attributes,
+            &token.signature
+        ));
+        let message_digest = digest(&SHA512, &token.tst_info);
+        assert!(contains(&token.attributes, message_digest.as_ref()));
+
+        let (tst_info, _) = read(&token.tst_info, der::SEQUENCE);
+        let (_, rest) = read(tst_info, der::INTEGER);
+        let (policy, rest) = read(rest, der::OBJECT_IDENTIFIER);
+        assert_eq!(&der::oid(&[1, 2, 3, 4, 1])[2..], policy);
+        assert_eq!(token.hash(), hash);
+        let (_, rest) = read(rest, der::SEQUENCE);
+        let (_, rest) = read(rest, der::INTEGER);
+        let (time, rest) = read(rest, der::GENERALIZED_TIME);
+        assert_eq!(time, b"20231114221320.005Z");
+        assert_eq!(rest, der::uint(42));
+    }
+
+    #[test]
+    fn token_with_certificate() {
+        let backend = keys::load_key(Path::new("keys/test/ed25519.pem")).unwrap();
+        let certificate =
+            keys::load_certificate(Path::new("keys/test/ed25519_tsa.crt")).unwrap();
+        let tsa = TimestampAuthority::new(POLICY, Some(&certificate)).unwrap();
+        assert!(tsa.certifies(backend.as_ref()));
+        let token = tsa
+            .token(backend.as_ref(), &[7; HASH_LEN], 0, None)
+            .unwrap();
+
+        let token = open(&token);
+        assert_eq!(token.certificates, Some(certificate.clone()));
+        assert_eq!(token.signer_version, [1]);
+        assert!(contains(
+            &token.attributes,
+            digest(&SHA256, &certificate).as_ref()
+        ));
+        assert!(backend.algorithm().verify(
+            backend.public_key(),
+            &token.attributes,
+            &token.signature
+        ));
+
+        // A token signed by another key can't name the certificate
+        let other = keys::load_key(Path::new("keys/test/p256.pk8")).unwrap();
+        assert!(!tsa.certifies(other.as_ref()));
+        let token = tsa.token(other.as_ref(), &[7; HASH_LEN], 0, None).unwrap();
+        assert_eq!(open(&token).certificates, None);
+    }
+
+    #[test]
+    fn ecdsa_token_signature_is_der() {
+        let backend = keys::load_key(Path::new("keys/test/p256.pk8")).unwrap();
+        let tsa = TimestampAuthority::new(POLICY, None).unwrap();
+        let token = tsa
+            .token(backend.as_ref(), &[7; HASH_LEN], 0, None)
+            .unwrap();
+        let signature = open(&token).signature;
+        let (values, _) = read(&signature, der::SEQUENCE);
+        let (_, rest) = read(values, der::INTEGER);
+        let (_, rest) = read(rest, der::INTEGER);
+        assert!(rest.is_empty());
+    }
+
+    #[test]
+    fn reject_bad_configuration() {
+        assert!(TimestampAuthority::new("pickle", None).is_err());
+        assert!(TimestampAuthority::new(POLICY, Some(&[der::SEQUENCE, 0])).is_err());
+    }
+}
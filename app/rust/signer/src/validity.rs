@@ -0,0 +1,107 @@
+//
+// How long signatures stay valid. Every new signature expires: callers may
+// ask for a lifetime up to the configured maximum, and get the default one if
+// they don't. Verification rejects signatures that have expired or claim to
+// have been made in the future, allowing for some difference between the
+// signer's clock and the clock of whoever made the signature.
+//
+
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Validity {
+    /// Lifetime of signatures whose caller doesn't request one
+    pub default_ttl: Duration,
+    /// Longest lifetime a caller may request
+    pub max_ttl: Duration,
+    /// Clock difference tolerated when checking signature times
+    pub clock_skew: Duration,
+}
+
+impl Validity {
+    /// Returns when a signature made at the timestamp expires, in
+    /// milliseconds since the unix epoch, or why the requested lifetime
+    /// can't be granted
+    ///
+    /// # Arguments
+    ///
+    /// * `timestamp` - Milliseconds since the unix epoch
+    /// * `ttl_seconds` - Lifetime requested by the caller, if any
+    pub fn expires_at(&self, timestamp: u64, ttl_seconds: Option<u32>) -> Result<u64, String> {
+        let ttl = match ttl_seconds {
+            None => self.default_ttl,
+            Some(0) => return Err(String::from("must be greater than 0")),
+            Some(seconds) if u64::from(seconds) > self.max_ttl.as_secs() => {
+                return Err(format!(
+                    "must be at most {} seconds",
+                    self.max_ttl.as_secs()
+                ))
+            }
+            Some(seconds) => Duration::from_secs(u64::from(seconds)),
+        };
+        Ok(timestamp.saturating_add(millis(ttl)))
+    }
+
+    /// Checks the times of a signature against the current time, returning
+    /// why the signature is no longer, or not yet, acceptable
+    ///
+    /// # Arguments
+    ///
+    /// * `timestamp` - When the signature was made, in milliseconds since the
+    ///   unix epoch
+    /// * `expires_at` - When the signature expires, for signatures that do
+    /// * `now` - The current time, in milliseconds since the unix epoch
+    pub fn check(&self, timestamp: u64, expires_at: Option<u64>, now: u64) -> Result<(), String> {
+        let skew = millis(self.clock_skew);
+        if timestamp > now.saturating_add(skew) {
+            return Err(format!("timestamp {} is in the future", timestamp));
+        }
+        match expires_at {
+            Some(expires_at) if expires_at.saturating_add(skew) < now => {
+                Err(format!("signature expired at {}", expires_at))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+fn millis(duration: Duration) -> u64 {
+    duration.as_millis() as u64
+}
+
+// Unit tests
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    const VALIDITY: Validity = Validity {
+        default_ttl: Duration::from_secs(60),
+        max_ttl: Duration::from_secs(600),
+        clock_skew: Duration::from_secs(5),
+    };
+
+    #[test]
+    fn ttl_bounded_by_config() {
+        assert_eq!(VALIDITY.expires_at(1_000, None), Ok(61_000));
+        assert_eq!(VALIDITY.expires_at(1_000, Some(600)), Ok(601_000));
+        assert!(VALIDITY.expires_at(1_000, Some(601)).is_err());
+        assert!(VALIDITY.expires_at(1_000, Some(0)).is_err());
+    }
+
+    #[test]
+    fn check_allows_clock_skew() {
+        let now = 100_000;
+        assert!(VALIDITY.check(now, Some(now + 1), now).is_ok());
+        // Slightly ahead, or slightly expired, by less than the skew
+        assert!(VALIDITY.check(now + 5_000, Some(now + 6_000), now).is_ok());
+        assert!(VALIDITY.check(now - 9_000, Some(now - 5_000), now).is_ok());
+        // Beyond the skew
+        assert!(VALIDITY.check(now + 5_001, Some(now + 6_000), now).is_err());
+        assert!(VALIDITY.check(now - 9_000, Some(now - 5_001), now).is_err());
+        // Signatures without an expiry only have their timestamp checked
+        assert!(VALIDITY.check(1, None, now).is_ok());
+        assert!(VALIDITY.check(now + 5_001, None, now).is_err());
+    }
+}
@@ -0,0 +1,1162 @@
+//
+// A Dictionary holds the adjectives, nouns and verbs words are picked from,
+// in one locale. The default dictionary is the one built into the names crate
+// for English, with verbs shipped with words and any list replaceable by a
+// file, and lists shipped with words for other locales. More dictionaries can
+// be loaded from a directory holding one subdirectory per dictionary, named
+// by the dictionary. English word lists sit in the dictionary's directory as
+// `adjectives.txt`, `nouns.txt` and optional `verbs.txt` files; lists for
+// other locales sit in subdirectories named by BCP 47 language tag, such as
+// `fr` or `pt-BR`. A `default` directory adds or replaces locales of the
+// default dictionary other than English.
+//
+// Word list files hold one word per line. Blank lines and lines starting with
+// `#` are skipped; a list must hold at least one word and no word twice. An
+// optional `rules.txt` file beside the lists says how words in the locale go
+// together: `order = after` puts adjectives after the noun, and `genders`
+// lists the genders of nouns, such as `genders = m f`. Each noun is then
+// followed by its gender, and each adjective gives a form for each gender,
+// separated by `|`, or one form for all of them. Verbs are in the third person
+// singular, so they follow a noun, and don't change with its gender.
+//
+// Passphrases are picked from a single list of words. The words image ships
+// the EFF's long word list, 7,776 words, and names it as the passphrase file;
+// without one, the built-in English adjectives and nouns made of lowercase
+// letters are used. The dice numbers starting each line of such lists are
+// dropped. A word that differs from an earlier one only in case is dropped
+// too, as capitalization would make them the same passphrase and each would
+// count towards its entropy.
+//
+// A blocklist file names words, one a line, that are dropped from every list
+// when it's loaded, compared without regard to case. An adjective is dropped
+// if any of its forms is blocked.
+//
+// The dictionaries are reloaded when words receives SIGHUP or a word list
+// file changes. A reload that fails leaves the current dictionaries in place.
+//
+
+use log::info;
+use names::{ADJECTIVES, NOUNS};
+use reload::{self, Shared};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashSet},
+    error::Error as StdError,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+/// Name of the dictionary used when a request doesn't name one
+pub const DEFAULT_DICTIONARY: &str = "default";
+
+/// Locale of the built-in lists and of the lists at the top of a dictionary's
+/// directory
+pub const DEFAULT_LOCALE: &str = "en";
+
+// Names of the files in a dictionary or locale directory
+const ADJECTIVES_FILE: &str = "adjectives.txt";
+const NOUNS_FILE: &str = "nouns.txt";
+const VERBS_FILE: &str = "verbs.txt";
+const RULES_FILE: &str = "rules.txt";
+
+// Verbs shipped for the names crate's English lists
+const ENGLISH_VERBS: &str = include_str!("../data/en/verbs.txt");
+
+// Lists shipped for the default dictionary besides the names crate's, as
+// (locale, adjectives, nouns, verbs, rules)
+const BUILTIN_LOCALES: &[(&str, &str, &str, &str, &str)] = &[
+    (
+        "de",
+        include_str!("../data/de/adjectives.txt"),
+        include_str!("../data/de/nouns.txt"),
+        include_str!("../data/de/verbs.txt"),
+        include_str!("../data/de/rules.txt"),
+    ),
+    (
+        "es",
+        include_str!("../data/es/adjectives.txt"),
+        include_str!("../data/es/nouns.txt"),
+        include_str!("../data/es/verbs.txt"),
+        include_str!("../data/es/rules.txt"),
+    ),
+    (
+        "fr",
+        include_str!("../data/fr/adjectives.txt"),
+        include_str!("../data/fr/nouns.txt"),
+        include_str!("../data/fr/verbs.txt"),
+        include_str!("../data/fr/rules.txt"),
+    ),
+];
+
+/// Why dictionaries couldn't be loaded
+#[derive(Debug)]
+pub enum DictionaryError {
+    /// A word list file or dictionary directory couldn't be read
+    Read { path: PathBuf, source: io::Error },
+    /// A word list or dictionary directory was read but isn't usable
+    Invalid { path: PathBuf, reason: String },
+}
+
+impl fmt::Display for DictionaryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DictionaryError::Read { path, source } => {
+                write!(f, "failed to read {}: {}", path.display(), source)
+            }
+            DictionaryError::Invalid { path, reason } => {
+                write!(f, "invalid dictionary {}: {}", path.display(), reason)
+            }
+        }
+    }
+}
+
+impl StdError for DictionaryError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            DictionaryError::Read { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// Where adjectives go relative to the noun
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Order {
+    Before,
+    After,
+}
+
+/// How words in a locale go together
+#[derive(Debug)]
+pub struct Rules {
+    order: Order,
+    genders: Vec<String>,
+}
+
+impl Default for Rules {
+    fn default() -> Rules {
+        Rules {
+            order: Order::Before,
+            genders: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Adjective {
+    // One form for each gender, or one for all of them
+    forms: Vec<String>,
+}
+
+impl Adjective {
+    pub fn forms(&self) -> &[String] {
+        &self.forms
+    }
+
+    /// The form of the adjective agreeing with a noun of the gender
+    pub fn form(&self, gender: usize) -> &str {
+        self.forms.get(gender).unwrap_or(&self.forms[0])
+    }
+}
+
+#[derive(Debug)]
+pub struct Noun {
+    word: String,
+    // Index of the noun's gender in its locale's rules
+    gender: usize,
+}
+
+impl Noun {
+    pub fn word(&self) -> &str {
+        &self.word
+    }
+
+    /// Index of the noun's gender in its locale's rules
+    pub fn gender(&self) -> usize {
+        self.gender
+    }
+}
+
+#[derive(Debug)]
+pub struct Dictionary {
+    locale: String,
+    rules: Rules,
+    adjectives: Vec<Adjective>,
+    nouns: Vec<Noun>,
+    // May be empty, for lists without verbs
+    verbs: Vec<String>,
+}
+
+impl Dictionary {
+    /// The English dictionary built into the names crate
+    pub fn builtin() -> Dictionary {
+        Dictionary {
+            locale: DEFAULT_LOCALE.to_string(),
+            rules: Rules::default(),
+            adjectives: ADJECTIVES.iter().map(|w| adjective(w)).collect(),
+            nouns: NOUNS.iter().map(|w| noun(w)).collect(),
+            verbs: parse_verbs(ENGLISH_VERBS).unwrap_or_default(),
+        }
+    }
+
+    /// Loads a dictionary from a directory holding its word list files
+    ///
+    /// # Arguments
+    ///
+    /// * `dir` - Directory holding the word lists and any rules
+    /// * `locale` - Language tag of the word lists
+    pub fn load(dir: &Path, locale: &str) -> Result<Dictionary, DictionaryError> {
+        let rules_path = dir.join(RULES_FILE);
+        let rules = match rules_path.exists() {
+            true => parse_rules(&read(&rules_path)?).map_err(|r| invalid(&rules_path, r))?,
+            false => Rules::default(),
+        };
+        let adjectives_path = dir.join(ADJECTIVES_FILE);
+        let adjectives = parse_adjectives(&read(&adjectives_path)?, &rules)
+            .map_err(|r| invalid(&adjectives_path, r))?;
+        let nouns_path = dir.join(NOUNS_FILE);
+        let nouns =
+            parse_nouns(&read(&nouns_path)?, &rules).map_err(|r| invalid(&nouns_path, r))?;
+        let verbs_path = dir.join(VERBS_FILE);
+        let verbs = match verbs_path.exists() {
+            true => parse_verbs(&read(&verbs_path)?).map_err(|r| invalid(&verbs_path, r))?,
+            false => Vec::new(),
+        };
+        Ok(Dictionary {
+            locale: locale.to_string(),
+            rules,
+            adjectives,
+            nouns,
+            verbs,
+        })
+    }
+
+    /// Language tag of the dictionary's word lists
+    pub fn locale(&self) -> &str {
+        &self.locale
+    }
+
+    pub fn adjectives(&self) -> &[Adjective] {
+        &self.adjectives
+    }
+
+    pub fn nouns(&self) -> &[Noun] {
+        &self.nouns
+    }
+
+    pub fn verbs(&self) -> &[String] {
+        &self.verbs
+    }
+
+    /// Puts adjectives and a noun together in the order of the locale, with
+    /// the adjectives agreeing with the noun's gender
+    pub fn phrase(&self, adjectives: &[&Adjective], noun: &Noun) -> Vec<String> {
+        let adjectives = adjectives
+            .iter()
+            .map(|adjective| adjective.form(noun.gender).to_string());
+        let noun = std::iter::once(noun.word.clone());
+        match self.rules.order {
+            Order::Before => adjectives.chain(noun).collect(),
+            Order::After => noun.chain(adjectives).collect(),
+        }
+    }
+}
+
+/// Where dictionaries are loaded from
+#[derive(Clone, Debug, Default)]
+pub struct Sources {
+    /// English adjectives of the default dictionary, replacing the built-in ones
+    pub adjectives_file: Option<PathBuf>,
+    /// English nouns of the default dictionary, replacing the built-in ones
+    pub nouns_file: Option<PathBuf>,
+    /// English verbs of the default dictionary, replacing the built-in ones
+    pub verbs_file: Option<PathBuf>,
+    /// Directory of named dictionaries
+    pub dictionary_dir: Option<PathBuf>,
+    /// Words of passphrases, replacing the built-in ones
+    pub passphrase_file: Option<PathBuf>,
+    /// Words dropped from all the other lists
+    pub blocklist_file: Option<PathBuf>,
+}
+
+impl Sources {
+    // Files and directories whose changes reload the dictionaries
+    fn paths(&self) -> Vec<PathBuf> {
+        self.adjectives_file
+            .iter()
+            .chain(self.nouns_file.iter())
+            .chain(self.verbs_file.iter())
+            .chain(self.dictionary_dir.iter())
+            .chain(self.passphrase_file.iter())
+            .chain(self.blocklist_file.iter())
+            .cloned()
+            .collect()
+    }
+}
+
+// A dictionary's word lists by lowercased language tag
+type Locales = BTreeMap<String, Arc<Dictionary>>;
+
+/// The dictionaries that can be picked from, by name and locale, and the
+/// passphrase word list
+pub struct Dictionaries {
+    dictionaries: BTreeMap<String, Locales>,
+    passphrase_words: Vec<String>,
+}
+
+impl Dictionaries {
+    /// Loads the default dictionary and any named dictionaries
+    pub fn load(sources: &Sources) -> Result<Dictionaries, DictionaryError> {
+        let blocklist = match &sources.blocklist_file {
+            Some(file) => Blocklist::load(file)?,
+            None => Blocklist::default(),
+        };
+        let mut default = Locales::new();
+        default.insert(
+            DEFAULT_LOCALE.to_string(),
+            blocklist.apply(load_english(sources)?)?,
+        );
+        for (locale, adjectives, nouns, verbs, rules) in BUILTIN_LOCALES {
+            default.insert(
+                locale.to_string(),
+                blocklist.apply(builtin(locale, adjectives, nouns, verbs, rules)?)?,
+            );
+        }
+
+        let mut dictionaries = BTreeMap::new();
+        if let Some(dir) = &sources.dictionary_dir {
+            for (name, path) in subdirectories(dir)? {
+                let locales = load_locales(&path, &blocklist)?;
+                if name != DEFAULT_DICTIONARY {
+                    dictionaries.insert(name, locales);
+                    continue;
+                }
+                // The default dictionary's English lists are set by the files
+                if locales.contains_key(DEFAULT_LOCALE) {
+                    return Err(invalid(
+                        &path,
+                        format!(
+                            "default {} lists are set by --adjectives-file, --nouns-file and --verbs-file",
+                            DEFAULT_LOCALE
+                        ),
+                    ));
+                }
+                default.extend(locales);
+            }
+        }
+        dictionaries.insert(DEFAULT_DICTIONARY.to_string(), default);
+
+        let passphrase_words = dedup_ignoring_case(match &sources.passphrase_file {
+            Some(file) => parse_passphrase_words(&read(file)?).map_err(|r| invalid(file, r))?,
+            None => builtin_passphrase_words(),
+        });
+        let passphrase_words = blocklist.apply_words(passphrase_words)?;
+        Ok(Dictionaries {
+            dictionaries,
+            passphrase_words,
+        })
+    }
+
+    // A dictionary's locales, the default dictionary's for an empty name
+    fn locales_of(&self, name: &str) -> Option<&Locales> {
+        let name = match name {
+            "" => DEFAULT_DICTIONARY,
+            name => name,
+        };
+        self.dictionaries.get(name)
+    }
+
+    /// Whether there's a dictionary with the name, or the default one for an
+    /// empty name
+    pub fn contains(&self, name: &str) -> bool {
+        self.locales_of(name).is_some()
+    }
+
+    /// The named dictionary's word lists for the locale that best matches
+    /// the language tag, found by RFC 4647 lookup: `fr-CA` matches `fr-CA`,
+    /// then `fr`
+    pub fn get(&self, name: &str, locale: &str) -> Option<Arc<Dictionary>> {
+        let locales = self.locales_of(name)?;
+        let mut tag = locale.to_ascii_lowercase();
+        loop {
+            if let Some(dictionary) = locales.get(&tag) {
+                return Some(dictionary.clone());
+            }
+            tag.truncate(tag.rfind('-')?);
+            // A single letter subtag only makes sense with the one after it
+            if tag.len() > 1 && tag.as_bytes()[tag.len() - 2] == b'-' {
+                tag.truncate(tag.len() - 2);
+            }
+        }
+    }
+
+    /// The named dictionary's English word lists, or the first of its lists
+    /// if it has no English ones
+    pub fn get_default(&self, name: &str) -> Option<Arc<Dictionary>> {
+        let locales = self.locales_of(name)?;
+        locales
+            .get(DEFAULT_LOCALE)
+            .or_else(|| locales.values().next())
+            .cloned()
+    }
+
+    /// Names of the dictionaries
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.dictionaries.keys().map(String::as_str)
+    }
+
+    /// Language tags of the named dictionary's word lists
+    pub fn locales(&self, name: &str) -> impl Iterator<Item = &str> {
+        self.locales_of(name)
+            .into_iter()
+            .flat_map(|locales| locales.values().map(|dictionary| dictionary.locale()))
+    }
+
+    /// Words passphrases are picked from
+    pub fn passphrase_words(&self) -> &[String] {
+        &self.passphrase_words
+    }
+}
+
+/// Words dropped from the lists as they're loaded
+#[derive(Default)]
+struct Blocklist {
+    path: PathBuf,
+    // Lowercased blocked words
+    words: HashSet<String>,
+}
+
+impl Blocklist {
+    // Loads a blocklist file, which may be empty
+    fn load(path: &Path) -> Result<Blocklist, DictionaryError> {
+        let words = read(path)?
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(str::to_lowercase)
+            .collect();
+        Ok(Blocklist {
+            path: path.to_path_buf(),
+            words,
+        })
+    }
+
+    fn blocks(&self, word: &str) -> bool {
+        !self.words.is_empty() && self.words.contains(&word.to_lowercase())
+    }
+
+    // Drops the blocked words from a dictionary, failing if that empties its
+    // adjectives or nouns
+    fn apply(&self, mut dictionary: Dictionary) -> Result<Arc<Dictionary>, DictionaryError> {
+        dictionary
+            .adjectives
+            .retain(|adjective| !adjective.forms.iter().any(|form| self.blocks(form)));
+        dictionary.nouns.retain(|noun| !self.blocks(&noun.word));
+        dictionary.verbs.retain(|verb| !self.blocks(verb));
+        if dictionary.adjectives.is_empty() || dictionary.nouns.is_empty() {
+            return Err(invalid(
+                &self.path,
+                format!("blocks every adjective or noun in {}", dictionary.locale),
+            ));
+        }
+        Ok(Arc::new(dictionary))
+    }
+
+    // Drops the blocked words from the passphrase words, failing if that
+    // drops them all
+    fn apply_words(&self, mut words: Vec<String>) -> Result<Vec<String>, DictionaryError> {
+        words.retain(|word| !self.blocks(word));
+        if words.is_empty() {
+            return Err(invalid(
+                &self.path,
+                String::from("blocks every passphrase word"),
+            ));
+        }
+        Ok(words)
+    }
+}
+
+/// The current dictionaries, replaced as a whole when they're reloaded
+pub type SharedDictionaries = Shared<Dictionaries>;
+
+/// Logs the names, locales and sizes of the dictionaries and the size of the
+/// passphrase word list
+pub fn log_dictionaries(dictionaries: &Dictionaries) {
+    for (name, locales) in &dictionaries.dictionaries {
+        for dictionary in locales.values() {
+            info!(
+                "Dictionary {} has {} adjectives, {} nouns and {} verbs in {}",
+                name,
+                dictionary.adjectives.len(),
+                dictionary.nouns.len(),
+                dictionary.verbs.len(),
+                dictionary.locale
+            );
+        }
+    }
+    info!(
+        "Picking passphrases from {} words",
+        dictionaries.passphrase_words.len()
+    );
+}
+
+/// Reloads the dictionaries on SIGHUP, or when a word list file changes
+///
+/// # Arguments
+///
+/// * `sources` - Where the dictionaries are loaded from
+/// * `dictionaries` - Dictionaries to replace on reload
+/// * `poll` - Interval between checks for changed files
+pub async fn watch(sources: Sources, dictionaries: Arc<SharedDictionaries>, poll: Duration) {
+    let paths = sources.paths();
+    reload::watch("dictionaries", paths, dictionaries, poll, move || {
+        let dictionaries = Dictionaries::load(&sources)?;
+        log_dictionaries(&dictionaries);
+        Ok::<_, DictionaryError>(dictionaries)
+    })
+    .await
+}
+
+// The default dictionary's English lists, from the names crate or the files
+fn load_english(sources: &Sources) -> Result<Dictionary, DictionaryError> {
+    let mut english = Dictionary::builtin();
+    if let Some(file) = &sources.adjectives_file {
+        english.adjectives = parse_adjectives(&read(file)?, &english.rules)
+            .map_err(|reason| invalid(file, reason))?;
+    }
+    if let Some(file) = &sources.nouns_file {
+        english.nouns =
+            parse_nouns(&read(file)?, &english.rules).map_err(|reason| invalid(file, reason))?;
+    }
+    if let Some(file) = &sources.verbs_file {
+        english.verbs = parse_verbs(&read(file)?).map_err(|reason| invalid(file, reason))?;
+    }
+    Ok(english)
+}
+
+// A dictionary shipped with words
+fn builtin(
+    locale: &str,
+    adjectives: &str,
+    nouns: &str,
+    verbs: &str,
+    rules: &str,
+) -> Result<Dictionary, DictionaryError> {
+    let path = |file: &str| PathBuf::from("data").join(locale).join(file);
+    let rules = parse_rules(rules).map_err(|r| invalid(&path(RULES_FILE), r))?;
+    Ok(Dictionary {
+        locale: locale.to_string(),
+        adjectives: parse_adjectives(adjectives, &rules)
+            .map_err(|r| invalid(&path(ADJECTIVES_FILE), r))?,
+        nouns: parse_nouns(nouns, &rules).map_err(|r| invalid(&path(NOUNS_FILE), r))?,
+        verbs: parse_verbs(verbs).map_err(|r| invalid(&path(VERBS_FILE), r))?,
+        rules,
+    })
+}
+
+// Loads a dictionary's English lists from the top of its directory and its
+// other locales from the subdirectories
+fn load_locales(dir: &Path, blocklist: &Blocklist) -> Result<Locales, DictionaryError> {
+    let mut locales = Locales::new();
+    if dir.join(ADJECTIVES_FILE).exists() || dir.join(NOUNS_FILE).exists() {
+        locales.insert(
+            DEFAULT_LOCALE.to_string(),
+            blocklist.apply(Dictionary::load(dir, DEFAULT_LOCALE)?)?,
+        );
+    }
+    for (locale, path) in subdirectories(dir)? {
+        if !is_language_tag(&locale) {
+            return Err(invalid(&path, format!("{:?} isn't a language tag", locale)));
+        }
+        let key = locale.to_ascii_lowercase();
+        if locales.contains_key(&key) {
+            return Err(invalid(
+                dir,
+                format!("more than one set of {} word lists", locale),
+            ));
+        }
+        locales.insert(key, blocklist.apply(Dictionary::load(&path, &locale)?)?);
+    }
+    if locales.is_empty() {
+        return Err(invalid(dir, String::from("no word lists found")));
+    }
+    Ok(locales)
+}
+
+// Names and paths of the subdirectories of a directory, skipping hidden ones
+fn subdirectories(dir: &Path) -> Result<Vec<(String, PathBuf)>, DictionaryError> {
+    let read_error = |source| DictionaryError::Read {
+        path: dir.to_path_buf(),
+        source,
+    };
+    let mut dirs = Vec::new();
+    for entry in fs::read_dir(dir).map_err(read_error)? {
+        let path = entry.map_err(read_error)?.path();
+        let name = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or_default()
+            .to_string();
+        if name.starts_with('.') || !path.is_dir() {
+            continue;
+        }
+        dirs.push((name, path));
+    }
+    Ok(dirs)
+}
+
+// Whether a name is shaped like a BCP 47 language tag: a language subtag of
+// two or three letters, then alphanumeric subtags of up to eight characters
+fn is_language_tag(name: &str) -> bool {
+    let mut subtags = name.split('-');
+    let language = subtags.next().unwrap_or_default();
+    (2..=3).contains(&language.len())
+        && language.chars().all(|c| c.is_ascii_alphabetic())
+        && subtags
+            .all(|s| (1..=8).contains(&s.len()) && s.chars().all(|c| c.is_ascii_alphanumeric()))
+}
+
+fn read(path: &Path) -> Result<String, DictionaryError> {
+    fs::read_to_string(path).map_err(|source| DictionaryError::Read {
+        path: path.to_path_buf(),
+        source,
+    })
+}
+
+// The entries of a word list, one a line, checking there's at least one and
+// none twice
+fn parse_lines(text: &str) -> Result<Vec<&str>, String> {
+    let mut lines = Vec::new();
+    let mut seen = HashSet::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if !seen.insert(line) {
+            return Err(format!("{:?} on line {} is a duplicate", line, number + 1));
+        }
+        lines.push(line);
+    }
+    if lines.is_empty() {
+        return Err(String::from("no words found"));
+    }
+    Ok(lines)
+}
+
+// Parses adjectives, each one form or a form per gender separated by |
+fn parse_adjectives(text: &str, rules: &Rules) -> Result<Vec<Adjective>, String> {
+    parse_lines(text)?
+        .into_iter()
+        .map(|line| {
+            let forms = line
+                .split('|')
+                .map(|form| form.trim().to_string())
+                .collect::<Vec<String>>();
+            if forms.iter().any(String::is_empty) {
+                return Err(format!("{:?} has an empty form", line));
+            }
+            if forms.len() != 1 && forms.len() != rules.genders.len() {
+                return Err(format!(
+                    "{:?} has {} forms for {} genders",
+                    line,
+                    forms.len(),
+                    rules.genders.len()
+                ));
+            }
+            Ok(Adjective { forms })
+        })
+        .collect()
+}
+
+// Parses nouns, each followed by its gender when the locale has genders
+fn parse_nouns(text: &str, rules: &Rules) -> Result<Vec<Noun>, String> {
+    parse_lines(text)?
+        .into_iter()
+        .map(|line| {
+            if rules.genders.is_empty() {
+                return Ok(noun(line));
+            }
+            let (word, tag) = line
+                .rsplit_once(char::is_whitespace)
+                .ok_or_else(|| format!("{:?} has no gender", line))?;
+            let gender = rules
+                .genders
+                .iter()
+                .position(|g| g == tag)
+                .ok_or_else(|| format!("{:?} has unknown gender {:?}", line, tag))?;
+            Ok(Noun {
+                word: word.trim_end().to_string(),
+                gender,
+            })
+        })
+        .collect()
+}
+
+fn parse_verbs(text: &str) -> Result<Vec<String>, String> {
+    Ok(parse_lines(text)?.into_iter().map(String::from).collect())
+}
+
+// Parses rules, each a `key = value` line
+fn parse_rules(text: &str) -> Result<Rules, String> {
+    let mut rules = Rules::default();
+    for line in text.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| format!("{:?} isn't a key = value rule", line))?;
+        match (key.trim(), value.trim()) {
+            ("order", "before") => rules.order = Order::Before,
+            ("order", "after") => rules.order = Order::After,
+            ("genders", genders) => {
+                rules.genders = genders.split_whitespace().map(String::from).collect()
+            }
+            _ => return Err(format!("unknown rule {:?}", line)),
+        }
+    }
+    Ok(rules)
+}
+
+// The built-in adjectives and nouns made of lowercase letters, without the
+// ones that are both
+fn builtin_passphrase_words() -> Vec<String> {
+    let words = ADJECTIVES
+        .iter()
+        .chain(NOUNS.iter())
+        .filter(|w| w.chars().all(|c| c.is_ascii_lowercase()))
+        .map(|w| w.to_string())
+        .collect::<BTreeSet<String>>();
+    words.into_iter().collect()
+}
+
+// Parses a passphrase word list, dropping any dice numbers before the words
+fn parse_passphrase_words(text: &str) -> Result<Vec<String>, String> {
+    Ok(parse_lines(text)?
+        .into_iter()
+        .map(|line| match line.split_once(char::is_whitespace) {
+            Some((dice, word)) if dice.chars().all(|c| c.is_ascii_digit()) => word.trim(),
+            _ => line,
+        })
+        .map(String::from)
+        .collect())
+}
+
+// Keeps the first of the words that are the same but for case
+fn dedup_ignoring_case(words: Vec<String>) -> Vec<String> {
+    let mut seen = HashSet::new();
+    words
+        .into_iter()
+        .filter(|word| seen.insert(word.to_lowercase()))
+        .collect()
+}
+
+fn adjective(word: &str) -> Adjective {
+    Adjective {
+        forms: vec![word.to_string()],
+    }
+}
+
+fn noun(word: &str) -> Noun {
+    Noun {
+        word: word.to_string(),
+        gender: 0,
+    }
+}
+
+fn invalid(path: &Path, reason: String) -> DictionaryError {
+    DictionaryError::Invalid {
+        path: path.to_path_buf(),
+        reason,
+    }
+}
+
+// Unit tests
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use tempfile::TempDir;
+
+    // Creates a dictionary directory holding word lists at the paths, such
+    // as colors or colors/fr, each with adjectives and nouns
+    fn dictionary_dir(dictionaries: &[(&str, &str, &str)]) -> TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        for (name, adjectives, nouns) in dictionaries {
+            let path = dir.path().join(name);
+            fs::create_dir_all(&path).unwrap();
+            fs::write(path.join(ADJECTIVES_FILE), adjectives).unwrap();
+            fs::write(path.join(NOUNS_FILE), nouns).unwrap();
+        }
+        dir
+    }
+
+    fn load(dir: &TempDir) -> Result<Dictionaries, DictionaryError> {
+        Dictionaries::load(&Sources {
+            dictionary_dir: Some(dir.path().to_path_buf()),
+            ..Default::default()
+        })
+    }
+
+    // The first form of each adjective
+    fn adjectives(dictionary: &Dictionary) -> Vec<&str> {
+        dictionary.adjectives().iter().map(|a| a.form(0)).collect()
+    }
+
+    fn nouns(dictionary: &Dictionary) -> Vec<&str> {
+        dictionary.nouns().iter().map(|n| n.word.as_str()).collect()
+    }
+
+    #[test]
+    fn parse_word_lists() {
+        assert_eq!(
+            parse_lines("# colors\nred\n\n  green \nblue\n").unwrap(),
+            vec!["red", "green", "blue"]
+        );
+        assert!(parse_lines("red\ngreen\nred\n")
+            .unwrap_err()
+            .contains("line 3"));
+        assert!(parse_lines("\n# nothing\n").is_err());
+    }
+
+    #[test]
+    fn parse_gendered_word_lists() {
+        let rules = parse_rules("# Spanish\norder = after\ngenders = m f\n").unwrap();
+        assert_eq!(rules.order, Order::After);
+
+        let adjectives = parse_adjectives("rojo|roja\nverde\n", &rules).unwrap();
+        assert_eq!(adjectives[0].form(1), "roja");
+        assert_eq!(adjectives[1].form(1), "verde");
+        assert!(parse_adjectives("rojo|roja|rojx\n", &rules).is_err());
+        assert!(parse_adjectives("rojo|\n", &rules).is_err());
+        assert!(parse_adjectives("rojo|roja\n", &Rules::default()).is_err());
+
+        let nouns = parse_nouns("casa f\npepino m\n", &rules).unwrap();
+        assert_eq!((nouns[0].word.as_str(), nouns[0].gender), ("casa", 1));
+        assert!(parse_nouns("casa\n", &rules).is_err());
+        assert!(parse_nouns("casa n\n", &rules).is_err());
+
+        assert!(parse_rules("order = sideways\n").is_err());
+        assert!(parse_rules("plural = s\n").is_err());
+    }
+
+    #[test]
+    fn phrase_follows_locale_rules() {
+        let dictionaries = Dictionaries::load(&Sources::default()).unwrap();
+        let phrase = |locale: &str, adjectives: &[&str], noun: &str| {
+            let dictionary = dictionaries.get("", locale).unwrap();
+            let adjectives = adjectives
+                .iter()
+                .map(|a| {
+                    dictionary
+                        .adjectives()
+                        .iter()
+                        .find(|d| d.form(0) == *a)
+                        .unwrap()
+                })
+                .collect::<Vec<&Adjective>>();
+            let noun = dictionary.nouns().iter().find(|n| n.word == noun).unwrap();
+            dictionary.phrase(&adjectives, noun)
+        };
+        assert_eq!(
+            phrase("es", &["rojo", "verde"], "casa"),
+            ["casa", "roja", "verde"]
+        );
+        assert_eq!(phrase("fr", &["blanc"], "pomme"), ["pomme", "blanche"]);
+        assert_eq!(
+            phrase("de", &["roter", "kleiner"], "Haus"),
+            ["rotes", "kleines", "Haus"]
+        );
+    }
+
+    #[test]
+    fn load_passphrase_words() {
+        let dictionaries = Dictionaries::load(&Sources::default()).unwrap();
+        let words = dictionaries.passphrase_words();
+        assert!(words.len() > 2000);
+        assert!(words.windows(2).all(|w| w[0] < w[1]));
+        assert!(words.iter().all(|w| !w.contains('-')));
+
+        assert_eq!(
+            parse_passphrase_words("11111\tabacus\n11112\tabdomen\nzebra\n").unwrap(),
+            vec!["abacus", "abdomen", "zebra"]
+        );
+        assert_eq!(
+            dedup_ignoring_case(
+                parse_passphrase_words("11111 abacus\n11112 Abacus\n11113 ABACUS\nzebra\n")
+                    .unwrap()
+            ),
+            vec!["abacus", "zebra"]
+        );
+
+        // A file's words are deduplicated as they're loaded
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("passphrase.txt");
+        fs::write(&file, "Dill\ndill\npickle\nDILL\n").unwrap();
+        let sources = Sources {
+            passphrase_file: Some(file),
+            ..Default::default()
+        };
+        assert_eq!(
+            Dictionaries::load(&sources).unwrap().passphrase_words(),
+            ["Dill", "pickle"]
+        );
+    }
+
+    #[test]
+    fn load_verbs() {
+        let dir = dictionary_dir(&[("colors", "red\n", "paint\n"), ("food", "sour\n", "dill\n")]);
+        fs::write(
+            dir.path().join("food").join(VERBS_FILE),
+            "pickles\nbrines\n",
+        )
+        .unwrap();
+        let blocklist = dir.path().join("blocklist");
+        fs::write(&blocklist, "brines\njumps\n").unwrap();
+        let dictionaries = Dictionaries::load(&Sources {
+            dictionary_dir: Some(dir.path().to_path_buf()),
+            blocklist_file: Some(blocklist),
+            ..Default::default()
+        })
+        .unwrap();
+        for locale in ["en", "de", "es", "fr"].iter() {
+            assert!(!dictionaries.get("", locale).unwrap().verbs().is_empty());
+        }
+        assert!(!dictionaries
+            .get("", "en")
+            .unwrap()
+            .verbs()
+            .contains(&String::from("jumps")));
+        assert!(dictionaries
+            .get_default("colors")
+            .unwrap()
+            .verbs()
+            .is_empty());
+        assert_eq!(
+            dictionaries.get_default("food").unwrap().verbs(),
+            ["pickles"]
+        );
+    }
+
+    #[test]
+    fn drop_blocked_words() {
+        let dir = dictionary_dir(&[("colors", "red\nGreen\n", "paint\nink\n")]);
+        let blocklist = dir.path().join("blocklist");
+        fs::write(&blocklist, "# not these\ngreen\nINK\nwall\ncolorful\n").unwrap();
+        let dictionaries = Dictionaries::load(&Sources {
+            dictionary_dir: Some(dir.path().to_path_buf()),
+            blocklist_file: Some(blocklist.clone()),
+            ..Default::default()
+        })
+        .unwrap();
+        let colors = dictionaries.get_default("colors").unwrap();
+        assert_eq!(adjectives(&colors), ["red"]);
+        assert_eq!(nouns(&colors), ["paint"]);
+        let english = dictionaries.get_default("").unwrap();
+        let blocked = ["green", "ink", "wall", "colorful"];
+        assert_eq!(
+            english.nouns().len(),
+            NOUNS.iter().filter(|n| !blocked.contains(n)).count()
+        );
+        assert!(!nouns(&english).contains(&"wall"));
+        assert!(!dictionaries
+            .passphrase_words()
+            .contains(&String::from("colorful")));
+
+        let spanish = dictionary_dir(&[("food/es", "agrio|agria\n", "pepino m\n")]);
+        fs::write(&blocklist, "agria\n").unwrap();
+        assert!(matches!(
+            Dictionaries::load(&Sources {
+                dictionary_dir: Some(spanish.path().to_path_buf()),
+                blocklist_file: Some(blocklist),
+                ..Default::default()
+            }),
+            Err(DictionaryError::Invalid { .. })
+        ));
+    }
+
+    #[test]
+    fn load_default_dictionary() {
+        let dictionaries = Dictionaries::load(&Sources::default()).unwrap();
+        let default = dictionaries.get_default("").unwrap();
+        assert_eq!(default.locale(), DEFAULT_LOCALE);
+        assert_eq!(default.adjectives().len(), ADJECTIVES.len());
+        assert_eq!(default.nouns().len(), NOUNS.len());
+        assert_eq!(
+            dictionaries.locales("").collect::<Vec<&str>>(),
+            vec!["de", "en", "es", "fr"]
+        );
+        assert!(!dictionaries.contains("colors"));
+
+        let dir = tempfile::tempdir().unwrap();
+        let nouns_file = dir.path().join("nouns");
+        fs::write(&nouns_file, "pickle\nrelish\n").unwrap();
+        let dictionaries = Dictionaries::load(&Sources {
+            nouns_file: Some(nouns_file),
+            ..Default::default()
+        })
+        .unwrap();
+        let default = dictionaries.get_default(DEFAULT_DICTIONARY).unwrap();
+        assert_eq!(default.adjectives().len(), ADJECTIVES.len());
+        assert_eq!(nouns(&default), ["pickle", "relish"]);
+    }
+
+    #[test]
+    fn load_dictionary_directory() {
+        let dir = dictionary_dir(&[
+            ("colors", "red\ngreen\n", "paint\n"),
+            ("colors/fr", "rouge\nvert\n", "peinture\n"),
+            ("food", "sour\n", "pickle\n"),
+            ("default/it", "rosso\n", "cetriolo\n"),
+        ]);
+        let dictionaries = load(&dir).unwrap();
+        assert_eq!(
+            dictionaries.names().collect::<Vec<&str>>(),
+            vec!["colors", DEFAULT_DICTIONARY, "food"]
+        );
+        assert_eq!(
+            adjectives(&dictionaries.get_default("colors").unwrap()),
+            ["red", "green"]
+        );
+        assert_eq!(
+            adjectives(&dictionaries.get("colors", "fr").unwrap()),
+            ["rouge", "vert"]
+        );
+        assert_eq!(nouns(&dictionaries.get("food", "en").unwrap()), ["pickle"]);
+        assert!(dictionaries.get("food", "fr").is_none());
+        assert_eq!(nouns(&dictionaries.get("", "it").unwrap()), ["cetriolo"]);
+
+        let dir = dictionary_dir(&[("colors/pt-BR", "vermelho\n", "tinta\n")]);
+        let dictionaries = load(&dir).unwrap();
+        assert_eq!(
+            dictionaries.get_default("colors").unwrap().locale(),
+            "pt-BR"
+        );
+    }
+
+    #[test]
+    fn look_up_locales() {
+        let dir = dictionary_dir(&[
+            ("colors/fr", "rouge\n", "peinture\n"),
+            ("colors/zh-Hant", "紅\n", "漆\n"),
+        ]);
+        let dictionaries = load(&dir).unwrap();
+        let locale = |tag| {
+            dictionaries
+                .get("colors", tag)
+                .map(|d| d.locale().to_string())
+        };
+        assert_eq!(locale("fr").as_deref(), Some("fr"));
+        assert_eq!(locale("FR-ca").as_deref(), Some("fr"));
+        assert_eq!(locale("zh-Hant-TW").as_deref(), Some("zh-Hant"));
+        assert_eq!(locale("zh-Hant-x-private").as_deref(), Some("zh-Hant"));
+        assert_eq!(locale("zh"), None);
+        assert_eq!(locale("en"), None);
+    }
+
+    #[test]
+    fn reject_bad_dictionaries() {
+        let empty = dictionary_dir(&[("colors", "\n", "paint\n")]);
+        assert!(matches!(load(&empty), Err(DictionaryError::Invalid { .. })));
+
+        let duplicate = dictionary_dir(&[("colors", "red\n", "paint\npaint\n")]);
+        assert!(matches!(
+            load(&duplicate),
+            Err(DictionaryError::Invalid { .. })
+        ));
+
+        let default = dictionary_dir(&[(DEFAULT_DICTIONARY, "red\n", "paint\n")]);
+        assert!(matches!(
+            load(&default),
+            Err(DictionaryError::Invalid { .. })
+        ));
+
+        let not_a_tag = dictionary_dir(&[("colors/french", "rouge\n", "peinture\n")]);
+        assert!(matches!(
+            load(&not_a_tag),
+            Err(DictionaryError::Invalid { .. })
+        ));
+
+        let twice = dictionary_dir(&[
+            ("colors/fr", "rouge\n", "peinture\n"),
+            ("colors/FR", "vert\n", "peinture\n"),
+        ]);
+        assert!(matches!(load(&twice), Err(DictionaryError::Invalid { .. })));
+
+        let no_lists = tempfile::tempdir().unwrap();
+        fs::create_dir(no_lists.path().join("colors")).unwrap();
+        assert!(matches!(
+            load(&no_lists),
+            Err(DictionaryError::Invalid { .. })
+        ));
+
+        let missing = dictionary_dir(&[("colors", "red\n", "paint\n")]);
+        fs::remove_file(missing.path().join("colors").join(NOUNS_FILE)).unwrap();
+        assert!(matches!(load(&missing), Err(DictionaryError::Read { .. })));
+
+        let bad_rules = dictionary_dir(&[("colors/es", "rojo|roja\n", "casa f\n")]);
+        fs::write(
+            bad_rules.path().join("colors/es").join(RULES_FILE),
+            "genders = m\n",
+        )
+        .unwrap();
+        assert!(matches!(
+            load(&bad_rules),
+            Err(DictionaryError::Invalid { .. })
+        ));
+    }
+
+    #[test]
+    fn reload_keeps_dictionaries_on_failure() {
+        let dir = dictionary_dir(&[("colors", "red\n", "paint\n")]);
+        let sources = Sources {
+            dictionary_dir: Some(dir.path().to_path_buf()),
+            ..Default::default()
+        };
+        let shared = SharedDictionaries::new(Dictionaries::load(&sources).unwrap());
+
+        let path = dir.path().join("colors").join(ADJECTIVES_FILE);
+        fs::write(&path, "blue\n").unwrap();
+        shared.reload(|| Dictionaries::load(&sources)).unwrap();
+        assert_eq!(
+            adjectives(&shared.current().get_default("colors").unwrap()),
+            ["blue"]
+        );
+
+        fs::write(&path, "blue\nblue\n").unwrap();
+        assert!(shared.reload(|| Dictionaries::load(&sources)).is_err());
+        assert_eq!(
+            adjectives(&shared.current().get_default("colors").unwrap()),
+            ["blue"]
+        );
+    }
+
+    #[tokio::test]
+    async fn watch_reloads_changed_dictionaries() {
+        let dir = dictionary_dir(&[("colors", "red\n", "paint\n")]);
+        let sources = Sources {
+            dictionary_dir: Some(dir.path().to_path_buf()),
+            ..Default::default()
+        };
+        let shared = Arc::new(SharedDictionaries::new(
+            Dictionaries::load(&sources).unwrap(),
+        ));
+        tokio::spawn(watch(sources, shared.clone(), Duration::from_millis(10)));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let path = dir.path().join("colors").join("fr");
+        fs::create_dir(&path).unwrap();
+        fs::write(path.join(ADJECTIVES_FILE), "rouge\n").unwrap();
+        fs::write(path.join(NOUNS_FILE), "peinture\n").unwrap();
+        for _ in 0..100 {
+            if shared.current().get("colors", "fr").is_some() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(shared.current().get("colors", "fr").is_some());
+    }
+}
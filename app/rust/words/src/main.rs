@@ -2,30 +2,62 @@
 // Words is an example simple grpc service. It uses tonic for grpc support.
 //
 
+mod dictionary;
+
 use b3::{ExMetadataMap, InMetadataMap};
-use dill::dill::{
-    pick_words_server::{PickWords, PickWordsServer},
-    sign_words_client::SignWordsClient,
-    SignRequest, WordsRequest, WordsResponse,
+use dictionary::{Adjective, Dictionaries, Dictionary, Noun, SharedDictionaries, Sources};
+use dill::{
+    details::{self, bad_request, error_info, retry_after},
+    dill::{
+        pick_words_server::{PickWords, PickWordsServer},
+        sign_words_client::SignWordsClient,
+        Capitalization, SignRequest, WordsRequest, WordsResponse,
+    },
 };
 use futures::FutureExt;
 use log::{error, info, warn};
-use names::Generator;
 use opentelemetry::{
     global,
     global::shutdown_tracer_provider,
     trace::{noop::NoopTracerProvider, Span, TraceContextExt, Tracer},
     Context,
 };
+use rand::{rngs::OsRng, seq::SliceRandom, Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
 use rocket::serde::Deserialize;
-use std::time::Duration;
+use std::{
+    collections::{BTreeMap, HashSet},
+    path::PathBuf,
+    process,
+    sync::Arc,
+    time::Duration,
+};
 use structopt::StructOpt;
 use tokio::{signal, sync::oneshot};
 use tonic::{
     transport::{Channel, Server},
-    Request, Response, Status,
+    Code, Request, Response, Status,
 };
 
+// Domain of the ErrorInfo reasons returned by the service
+const ERROR_DOMAIN: &str = "dill.PickWords";
+
+// Random seeds are below this, the largest integer a JSON number holds
+// exactly, so clients parsing pickle's JSON get back the seed they were given
+const SEED_LIMIT: u64 = 1 << 53;
+
+// Separator between the words of a passphrase when the request gives none
+const DEFAULT_SEPARATOR: &str = "-";
+
+// Parts a pattern is made of, joined by -
+const PATTERN_PARTS: &str = "adj, noun, verb or num";
+
+// Numbers in patterns are below this
+const MAX_NUMBER: u32 = 10000;
+
+// How long callers should wait before retrying when signing is unavailable
+const SIGNER_RETRY_DELAY: Duration = Duration::from_secs(1);
+
 #[derive(StructOpt, Deserialize)]
 struct Args {
     // port for the grpc service to listen on
@@ -55,36 +87,739 @@ struct Args {
         default_value = "http://collector.linkerd-jaeger:14268/api/traces"
     )]
     trace_collector_endpoint: String,
+
+    // file of adjectives for the default dictionary, one a line
+    #[structopt(long = "adjectives-file", parse(from_os_str))]
+    adjectives_file: Option<PathBuf>,
+
+    // file of nouns for the default dictionary, one a line
+    #[structopt(long = "nouns-file", parse(from_os_str))]
+    nouns_file: Option<PathBuf>,
+
+    // file of verbs for the default dictionary, one a line
+    #[structopt(long = "verbs-file", parse(from_os_str))]
+    verbs_file: Option<PathBuf>,
+
+    // directory of named dictionaries, each a directory holding adjectives.txt,
+    // nouns.txt and optionally verbs.txt
+    #[structopt(
+        long = "dictionary-dir",
+        env = "WORDS_DICTIONARY_DIR",
+        parse(from_os_str)
+    )]
+    dictionary_dir: Option<PathBuf>,
+
+    // file of words for passphrases, one a line, optionally after dice numbers
+    #[structopt(
+        long = "passphrase-file",
+        env = "WORDS_PASSPHRASE_FILE",
+        parse(from_os_str)
+    )]
+    passphrase_file: Option<PathBuf>,
+
+    // file of words never to return, one a line
+    #[structopt(
+        long = "blocklist-file",
+        env = "WORDS_BLOCKLIST_FILE",
+        parse(from_os_str)
+    )]
+    blocklist_file: Option<PathBuf>,
+
+    // seconds between checks for changed word list files
+    #[structopt(
+        long = "dictionary-poll-interval",
+        default_value = "10",
+        parse(try_from_str = reload::parse_poll_interval)
+    )]
+    dictionary_poll_interval: u64,
 }
 
 // grpc service
 pub struct MyPickWords {
     sign_words_channel: Channel,
+    dictionaries: Arc<SharedDictionaries>,
+}
+
+/// Limits a request puts on the words picked
+#[derive(Default)]
+struct Constraints {
+    unique: bool,
+    min_length: Option<u32>,
+    max_length: Option<u32>,
+    alliterate: bool,
+    rhyme: bool,
+}
+
+/// What alliterative or rhyming words share: their first letter, their
+/// ending, or both
+type Sound = (Option<char>, Option<String>);
+
+impl Constraints {
+    fn from_request(request: &WordsRequest) -> Constraints {
+        Constraints {
+            unique: request.unique,
+            min_length: request.min_length,
+            max_length: request.max_length,
+            alliterate: request.alliterate,
+            rhyme: request.rhyme,
+        }
+    }
+
+    /// The sound a word shares with the others picked, given by the letter it
+    /// starts with when alliterating and its ending when rhyming; None if it
+    /// has no such letter or ending
+    fn sound(&self, word: &str) -> Option<Sound> {
+        let initial = match self.alliterate {
+            true => Some(initial(word)?),
+            false => None,
+        };
+        let ending = match self.rhyme {
+            true => Some(ending(word)?),
+            false => None,
+        };
+        Some((initial, ending))
+    }
+
+    /// Whether a word has an allowed number of characters
+    fn fits(&self, word: &str) -> bool {
+        let length = word.chars().count() as u32;
+        self.min_length.unwrap_or(0) <= length && length <= self.max_length.unwrap_or(u32::MAX)
+    }
+
+    /// INVALID_ARGUMENT for a request whose constraints leave fewer words
+    /// than it asks for
+    fn not_enough_words(&self, available: usize) -> Status {
+        let mut violations = Vec::new();
+        if self.min_length.is_some() {
+            violations.push(("min_length", "leaves too few words to pick from"));
+        }
+        if self.max_length.is_some() {
+            violations.push(("max_length", "leaves too few words to pick from"));
+        }
+        if self.unique {
+            violations.push(("count", "is more than the words there are to pick from"));
+        }
+        if self.alliterate {
+            violations.push(("alliterate", "leaves too few words to pick from"));
+        }
+        if self.rhyme {
+            violations.push(("rhyme", "leaves too few words to pick from"));
+        }
+        details::with_details(
+            Code::InvalidArgument,
+            format!("only {} words fit the constraints", available),
+            vec![
+                error_info(
+                    "NOT_ENOUGH_WORDS",
+                    ERROR_DOMAIN,
+                    &[("available", &available.to_string())],
+                ),
+                bad_request(&violations),
+            ],
+        )
+    }
+}
+
+/// Picks count items from the candidates, none twice when unique
+fn pick<'a, T, R: Rng>(
+    candidates: &[&'a T],
+    count: usize,
+    unique: bool,
+    rng: &mut R,
+) -> Vec<&'a T> {
+    let mut picked: Vec<&T> = Vec::with_capacity(count);
+    while picked.len() < count {
+        let candidate = *candidates.choose(rng).unwrap();
+        if !unique || !picked.iter().any(|p| std::ptr::eq(*p, candidate)) {
+            picked.push(candidate);
+        }
+    }
+    picked
+}
+
+/// A kind of word in a pattern
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Part {
+    Adjective,
+    Noun,
+    Verb,
+    Number,
+}
+
+/// The kinds of words a request asks for, in order
+#[derive(Debug, PartialEq)]
+struct Pattern {
+    parts: Vec<Part>,
+    // Whether the words go in the order of the parts, rather than the
+    // adjectives and noun going in the order of the dictionary's locale
+    literal: bool,
+}
+
+impl Pattern {
+    /// count - 1 adjectives and a noun
+    fn from_count(count: u32) -> Pattern {
+        let mut parts = vec![Part::Adjective; count.saturating_sub(1) as usize];
+        if count > 0 {
+            parts.push(Part::Noun);
+        }
+        Pattern {
+            parts,
+            literal: false,
+        }
+    }
+
+    /// Parses parts joined by -, such as adj-adj-noun-verb, returning the
+    /// first part that isn't one on error
+    fn parse(pattern: &str) -> Result<Pattern, String> {
+        let parts = pattern
+            .split('-')
+            .map(|part| match part {
+                "adj" => Ok(Part::Adjective),
+                "noun" => Ok(Part::Noun),
+                "verb" => Ok(Part::Verb),
+                "num" => Ok(Part::Number),
+                part => Err(part.to_string()),
+            })
+            .collect::<Result<Vec<Part>, String>>()?;
+        Ok(Pattern {
+            parts,
+            literal: true,
+        })
+    }
+
+    /// The request's pattern, or count - 1 adjectives and a noun if it has
+    /// none. Returns INVALID_ARGUMENT if the pattern can't be parsed, is too
+    /// long or disagrees with the count.
+    #[allow(clippy::result_large_err)]
+    fn from_request(request: &WordsRequest) -> Result<Pattern, Status> {
+        if request.pattern.is_empty() {
+            return Ok(Pattern::from_count(request.count));
+        }
+        let pattern = Pattern::parse(&request.pattern).map_err(|part| {
+            details::with_details(
+                Code::InvalidArgument,
+                format!("{:?} in pattern {:?} isn't a part", part, request.pattern),
+                vec![
+                    error_info("INVALID_PATTERN", ERROR_DOMAIN, &[("part", &part)]),
+                    bad_request(&[(
+                        "pattern",
+                        &format!("must be parts joined by -, each {}", PATTERN_PARTS),
+                    )]),
+                ],
+            )
+        })?;
+        let parts = pattern.parts.len() as u32;
+        if request.count != 0 && request.count != parts {
+            return Err(details::with_details(
+                Code::InvalidArgument,
+                format!("count {} but pattern has {} parts", request.count, parts),
+                vec![
+                    error_info("PATTERN_CONFLICT", ERROR_DOMAIN, &[]),
+                    bad_request(&[(
+                        "count",
+                        &format!("must be 0 or the pattern's {} parts", parts),
+                    )]),
+                ],
+            ));
+        }
+        Ok(pattern)
+    }
+
+    fn has(&self, part: Part) -> bool {
+        self.parts.contains(&part)
+    }
+
+    // How many words of the kind the pattern needs to pick from
+    fn needs(&self, part: Part, constraints: &Constraints) -> usize {
+        match constraints.unique {
+            true => self.parts.iter().filter(|p| **p == part).count(),
+            false => self.has(part) as usize,
+        }
+    }
 }
 
-/// Returns a list of adjectives followed by a noun
+/// A word picked for a part of a pattern
+enum Picked<'a> {
+    Adjective(&'a Adjective),
+    Noun(&'a Noun),
+    Verb(&'a str),
+    Number(u32),
+}
+
+/// The first letter of a word, lowercased
+fn initial(word: &str) -> Option<char> {
+    word.chars().next()?.to_lowercase().next()
+}
+
+/// Whether a letter is a vowel in any of the dictionaries' languages
+fn is_vowel(letter: char) -> bool {
+    "aeiouyàáâäèéêëìíîïòóôöùúûü".contains(letter)
+}
+
+/// The end of a word from its last vowel, lowercased, which words rhyme by.
+/// A word ending in a vowel keeps the vowels before it too, so pickle rhymes
+/// with tickle rather than with every word ending in e.
+fn ending(word: &str) -> Option<String> {
+    let letters = word.to_lowercase().chars().collect::<Vec<char>>();
+    // Where each run of vowels starts
+    let vowels = (0..letters.len())
+        .filter(|&i| is_vowel(letters[i]) && (i == 0 || !is_vowel(letters[i - 1])))
+        .collect::<Vec<usize>>();
+    let start = match (vowels.as_slice(), letters.last()) {
+        ([.., before, _], Some(last)) if is_vowel(*last) => *before,
+        ([.., last], _) => *last,
+        ([], _) => return None,
+    };
+    Some(letters[start..].iter().collect())
+}
+
+/// The sound all of an adjective's forms share, if they share one
+fn adjective_sound(adjective: &Adjective, constraints: &Constraints) -> Option<Sound> {
+    let first = constraints.sound(&adjective.forms()[0]);
+    match adjective
+        .forms()
+        .iter()
+        .all(|form| constraints.sound(form) == first)
+    {
+        true => first,
+        false => None,
+    }
+}
+
+/// Picks one of the candidates that isn't used
+fn pick_one<'a, T: ?Sized, R: Rng>(
+    candidates: &[&'a T],
+    used: impl Fn(&T) -> bool,
+    rng: &mut R,
+) -> Option<&'a T> {
+    let unused = candidates
+        .iter()
+        .copied()
+        .filter(|candidate| !used(candidate))
+        .collect::<Vec<&T>>();
+    unused.choose(rng).copied()
+}
+
+/// Picks the sound alliterative or rhyming words share, from the sounds
+/// shared by enough of the words of each kind the pattern needs. Fails with
+/// INVALID_ARGUMENT if there's no such sound.
+#[allow(clippy::result_large_err)]
+fn pick_sound<R: Rng>(
+    pattern: &Pattern,
+    dictionary: &Dictionary,
+    constraints: &Constraints,
+    rng: &mut R,
+) -> Result<Option<Sound>, Status> {
+    if pattern.parts.iter().all(|part| *part == Part::Number) {
+        return Ok(None);
+    }
+    // Words fitting the constraints by sound, as adjectives, nouns and verbs
+    let mut counts = BTreeMap::<Sound, [usize; 3]>::new();
+    let fitting_adjectives = dictionary
+        .adjectives()
+        .iter()
+        .filter(|adjective| adjective.forms().iter().all(|form| constraints.fits(form)));
+    for sound in fitting_adjectives.filter_map(|adjective| adjective_sound(adjective, constraints))
+    {
+        counts.entry(sound).or_default()[0] += 1;
+    }
+    let fitting_nouns = dictionary
+        .nouns()
+        .iter()
+        .map(Noun::word)
+        .filter(|word| constraints.fits(word));
+    for sound in fitting_nouns.filter_map(|noun| constraints.sound(noun)) {
+        counts.entry(sound).or_default()[1] += 1;
+    }
+    let fitting_verbs = dictionary
+        .verbs()
+        .iter()
+        .filter(|verb| constraints.fits(verb));
+    for sound in fitting_verbs.filter_map(|verb| constraints.sound(verb)) {
+        counts.entry(sound).or_default()[2] += 1;
+    }
+    let needs = [
+        pattern.needs(Part::Adjective, constraints),
+        pattern.needs(Part::Noun, constraints),
+        pattern.needs(Part::Verb, constraints),
+    ];
+    let sounds = counts
+        .into_iter()
+        .filter(|(_, count)| count.iter().zip(needs.iter()).all(|(c, n)| c >= n))
+        .map(|(sound, _)| sound)
+        .collect::<Vec<Sound>>();
+    if let Some(sound) = sounds.choose(rng) {
+        return Ok(Some(sound.clone()));
+    }
+    let message = match constraints.rhyme {
+        false => "no letter starts enough words to alliterate",
+        true if constraints.alliterate => "no letter starts enough rhyming words to alliterate",
+        true => "no ending is shared by enough words to rhyme",
+    };
+    let mut violations = Vec::new();
+    if constraints.alliterate {
+        violations.push((
+            "alliterate",
+            "no letter starts enough words for the pattern",
+        ));
+    }
+    if constraints.rhyme {
+        violations.push((
+            "rhyme",
+            "no ending is shared by enough words for the pattern",
+        ));
+    }
+    Err(details::with_details(
+        Code::InvalidArgument,
+        message,
+        vec![
+            error_info("NOT_ENOUGH_WORDS", ERROR_DOMAIN, &[("available", "0")]),
+            bad_request(&violations),
+        ],
+    ))
+}
+
+/// Returns words of the kinds in the pattern, the same for the same pattern,
+/// seed, dictionary and constraints. Without a pattern given, the adjectives
+/// and noun go in the order of the dictionary's locale; with one, the words go
+/// in the pattern's order and each adjective agrees with the next noun, or
+/// the one before it if none follows. Fails with INVALID_ARGUMENT if the
+/// dictionary has no verbs for a pattern with one, or too few words fit the
+/// constraints.
 ///
 /// # Arguments
 ///
-/// * `count` - Number of words to return
-fn generate_words(count: u32) -> Vec<String> {
-    let mut words = Vec::new();
-    let mut gen = Generator::default();
-    match count {
-        0 => (),
-        1 => words.push(gen.next().unwrap().split('-').collect::<Vec<&str>>()[1].to_string()), // noun
-        _ => {
-            for _ in 2..count {
-                words.push(gen.next().unwrap().split('-').collect::<Vec<&str>>()[0].to_string())
-                // adjectives
+/// * `pattern` - Kinds of words to return
+/// * `seed` - Seed for the generator
+/// * `dictionary` - Dictionary to pick the words from
+/// * `constraints` - Limits on the words picked
+#[allow(clippy::result_large_err)]
+fn generate_words(
+    pattern: &Pattern,
+    seed: u64,
+    dictionary: &Dictionary,
+    constraints: &Constraints,
+) -> Result<Vec<String>, Status> {
+    if pattern.has(Part::Verb) && dictionary.verbs().is_empty() {
+        return Err(details::with_details(
+            Code::InvalidArgument,
+            format!("no verbs in {} word lists", dictionary.locale()),
+            vec![
+                error_info("NO_VERBS", ERROR_DOMAIN, &[("locale", dictionary.locale())]),
+                bad_request(&[("pattern", "can't have verbs for the dictionary and locale")]),
+            ],
+        ));
+    }
+
+    // ChaCha8 output is fixed for a seed across rand versions and platforms,
+    // unlike StdRng's, so seeds stay valid
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    let sound = match constraints.alliterate || constraints.rhyme {
+        true => pick_sound(pattern, dictionary, constraints, &mut rng)?,
+        false => None,
+    };
+    let fits = |word: &str| {
+        constraints.fits(word) && (sound.is_none() || constraints.sound(word) == sound)
+    };
+    let adjectives = dictionary
+        .adjectives()
+        .iter()
+        .filter(|adjective| adjective.forms().iter().all(|form| fits(form)))
+        .collect::<Vec<&Adjective>>();
+    let nouns = dictionary
+        .nouns()
+        .iter()
+        .filter(|noun| fits(noun.word()))
+        .collect::<Vec<&Noun>>();
+    let verbs = dictionary
+        .verbs()
+        .iter()
+        .map(String::as_str)
+        .filter(|verb| fits(verb))
+        .collect::<Vec<&str>>();
+
+    // Words picked so far, when each may only be picked once
+    let mut used = HashSet::<&str>::new();
+    let mut picked = Vec::with_capacity(pattern.parts.len());
+    for part in &pattern.parts {
+        let word = match part {
+            Part::Adjective => pick_one(
+                &adjectives,
+                |adjective| {
+                    adjective
+                        .forms()
+                        .iter()
+                        .any(|form| used.contains(form.as_str()))
+                },
+                &mut rng,
+            )
+            .map(Picked::Adjective)
+            .ok_or(adjectives.len()),
+            Part::Noun => pick_one(&nouns, |noun| used.contains(noun.word()), &mut rng)
+                .map(Picked::Noun)
+                .ok_or(nouns.len()),
+            Part::Verb => pick_one(&verbs, |verb| used.contains(verb), &mut rng)
+                .map(Picked::Verb)
+                .ok_or(verbs.len()),
+            Part::Number => Ok(Picked::Number(rng.gen_range(0..MAX_NUMBER))),
+        };
+        let word = word.map_err(|available| constraints.not_enough_words(available))?;
+        if constraints.unique {
+            match word {
+                Picked::Adjective(adjective) => {
+                    used.extend(adjective.forms().iter().map(String::as_str))
+                }
+                Picked::Noun(noun) => {
+                    used.insert(noun.word());
+                }
+                Picked::Verb(verb) => {
+                    used.insert(verb);
+                }
+                Picked::Number(_) => {}
             }
-            for w in gen.next().unwrap().split('-') {
-                // adjective and noun
-                words.push(w.to_string());
+        }
+        picked.push(word);
+    }
+    Ok(arrange(pattern, dictionary, &picked))
+}
+
+/// Puts the picked words together as the pattern says
+fn arrange(pattern: &Pattern, dictionary: &Dictionary, picked: &[Picked]) -> Vec<String> {
+    let noun_at = |i: usize| match picked.get(i) {
+        Some(Picked::Noun(noun)) => Some(*noun),
+        _ => None,
+    };
+    if !pattern.literal {
+        let adjectives = picked
+            .iter()
+            .filter_map(|word| match word {
+                Picked::Adjective(adjective) => Some(*adjective),
+                _ => None,
+            })
+            .collect::<Vec<&Adjective>>();
+        return match picked
+            .iter()
+            .rposition(|word| matches!(word, Picked::Noun(_)))
+        {
+            Some(i) => dictionary.phrase(&adjectives, noun_at(i).unwrap()),
+            None => Vec::new(),
+        };
+    }
+    picked
+        .iter()
+        .enumerate()
+        .map(|(i, word)| match word {
+            Picked::Adjective(adjective) => {
+                let noun = (i + 1..picked.len())
+                    .find_map(noun_at)
+                    .or_else(|| (0..i).rev().find_map(noun_at));
+                adjective.form(noun.map_or(0, Noun::gender)).to_string()
+            }
+            Picked::Noun(noun) => noun.word().to_string(),
+            Picked::Verb(verb) => verb.to_string(),
+            Picked::Number(number) => number.to_string(),
+        })
+        .collect()
+}
+
+/// Returns words picked from the list by the operating system's secure
+/// generator, and the bits of entropy in the choice. Fails with
+/// INVALID_ARGUMENT if too few words fit the constraints.
+///
+/// # Arguments
+///
+/// * `count` - Number of words to return
+/// * `words` - Words to pick from
+/// * `constraints` - Limits on the words picked
+#[allow(clippy::result_large_err)]
+fn generate_passphrase(
+    count: u32,
+    words: &[String],
+    constraints: &Constraints,
+) -> Result<(Vec<String>, f64), Status> {
+    let count = count as usize;
+    let candidates = words
+        .iter()
+        .filter(|word| constraints.fits(word))
+        .collect::<Vec<&String>>();
+    if count > 0 && candidates.is_empty() || constraints.unique && candidates.len() < count {
+        return Err(constraints.not_enough_words(candidates.len()));
+    }
+    let picked = pick(&candidates, count, constraints.unique, &mut OsRng)
+        .into_iter()
+        .cloned()
+        .collect();
+    let n = candidates.len() as f64;
+    let entropy_bits = match constraints.unique {
+        // Each word is picked from those not picked yet
+        true => (0..count).map(|i| (n - i as f64).log2()).sum(),
+        false => count as f64 * n.log2(),
+    };
+    Ok((picked, entropy_bits))
+}
+
+/// Returns a passphrase word with the capitalization
+fn capitalize(word: &str, capitalization: Capitalization) -> String {
+    match capitalization {
+        Capitalization::Lower => word.to_lowercase(),
+        Capitalization::Upper => word.to_uppercase(),
+        Capitalization::Title => {
+            let mut chars = word.chars();
+            match chars.next() {
+                Some(first) => first.to_uppercase().chain(chars).collect(),
+                None => String::new(),
             }
         }
     }
-    words
+}
+
+/// Checks a WordsRequest, returning INVALID_ARGUMENT with the offending
+/// fields if it can't be served
+#[allow(clippy::result_large_err)]
+fn validate(request: &WordsRequest) -> Result<(), Status> {
+    if let (Some(min), Some(max)) = (request.min_length, request.max_length) {
+        if min > max {
+            return Err(details::with_details(
+                Code::InvalidArgument,
+                format!("min_length {} is more than max_length {}", min, max),
+                vec![
+                    error_info("INVALID_LENGTHS", ERROR_DOMAIN, &[]),
+                    bad_request(&[("min_length", "must be at most max_length")]),
+                ],
+            ));
+        }
+    }
+    if Capitalization::from_i32(request.capitalization).is_none() {
+        return Err(details::with_details(
+            Code::InvalidArgument,
+            format!("unknown capitalization {}", request.capitalization),
+            vec![
+                error_info("UNKNOWN_CAPITALIZATION", ERROR_DOMAIN, &[]),
+                bad_request(&[("capitalization", "must be LOWER, UPPER or TITLE")]),
+            ],
+        ));
+    }
+    if request.passphrase {
+        // A known seed would give the passphrase away, and signing would
+        // record its hash in the audit log
+        let mut violations = Vec::new();
+        if request.seed.is_some() {
+            violations.push("seed");
+        }
+        if request.signed {
+            violations.push("signed");
+        }
+        if !request.dictionary.is_empty() {
+            violations.push("dictionary");
+        }
+        if !request.locale.is_empty() {
+            violations.push("locale");
+        }
+        if !request.pattern.is_empty() {
+            violations.push("pattern");
+        }
+        if request.alliterate {
+            violations.push("alliterate");
+        }
+        if request.rhyme {
+            violations.push("rhyme");
+        }
+        if !violations.is_empty() {
+            return Err(details::with_details(
+                Code::InvalidArgument,
+                format!("{} can't be given for a passphrase", violations.join(", ")),
+                vec![
+                    error_info("PASSPHRASE_CONFLICT", ERROR_DOMAIN, &[]),
+                    bad_request(
+                        &violations
+                            .iter()
+                            .map(|field| (*field, "can't be given for a passphrase"))
+                            .collect::<Vec<(&str, &str)>>(),
+                    ),
+                ],
+            ));
+        }
+    }
+    Pattern::from_request(request)?;
+    Ok(())
+}
+
+/// Returns the word lists of the dictionary a WordsRequest names in the
+/// locale it asks for, or the first of its accepted locales the dictionary
+/// has, falling back on the dictionary's default locale. Returns
+/// INVALID_ARGUMENT listing the choices there are if it names a dictionary
+/// or locale there's none of.
+#[allow(clippy::result_large_err)]
+fn find_dictionary(
+    dictionaries: &Dictionaries,
+    request: &WordsRequest,
+) -> Result<Arc<Dictionary>, Status> {
+    let name = &request.dictionary;
+    if !dictionaries.contains(name) {
+        let names = dictionaries.names().collect::<Vec<&str>>().join(", ");
+        return Err(details::with_details(
+            Code::InvalidArgument,
+            format!("no dictionary named {:?}", name),
+            vec![
+                error_info("UNKNOWN_DICTIONARY", ERROR_DOMAIN, &[("dictionary", name)]),
+                bad_request(&[("dictionary", &format!("must be one of {}", names))]),
+            ],
+        ));
+    }
+    let dictionary = match request.locale.as_str() {
+        "" => request
+            .accept_locales
+            .iter()
+            .find_map(|locale| dictionaries.get(name, locale))
+            .or_else(|| dictionaries.get_default(name)),
+        locale => dictionaries.get(name, locale),
+    };
+    dictionary.ok_or_else(|| {
+        let locales = dictionaries.locales(name).collect::<Vec<&str>>().join(", ");
+        details::with_details(
+            Code::InvalidArgument,
+            format!(
+                "no {:?} word lists in dictionary {:?}",
+                request.locale, name
+            ),
+            vec![
+                error_info(
+                    "UNKNOWN_LOCALE",
+                    ERROR_DOMAIN,
+                    &[("dictionary", name), ("locale", &request.locale)],
+                ),
+                bad_request(&[("locale", &format!("must be one of {}", locales))]),
+            ],
+        )
+    })
+}
+
+/// Maps a failed call to the signing service to the status returned to the
+/// caller. An unreachable signer is reported as UNAVAILABLE with a retry
+/// delay; otherwise the signer's code and details are passed through.
+fn signing_error(e: &Status) -> Status {
+    // A status with details was returned by the signer itself
+    let unreachable = (e.code() == Code::Unavailable && e.details().is_empty())
+        || details::is_transport_error(e);
+    if unreachable {
+        return details::with_details(
+            Code::Unavailable,
+            "signing service unavailable",
+            vec![
+                error_info("SIGNER_UNAVAILABLE", ERROR_DOMAIN, &[]),
+                retry_after(SIGNER_RETRY_DELAY),
+            ],
+        );
+    }
+    let mut signer_details = details::error_details(e);
+    if signer_details.is_empty() {
+        signer_details.push(error_info("SIGNING_FAILED", ERROR_DOMAIN, &[]));
+    }
+    details::with_details(
+        e.code(),
+        format!("signing service: {}", e.message()),
+        signer_details,
+    )
 }
 
 #[tonic::async_trait]
@@ -97,17 +832,56 @@ impl PickWords for MyPickWords {
             propagator.extract(&ExMetadataMap(request.metadata()))
         });
         let words_request = request.into_inner();
-        let count = words_request.count.into();
-        let sign = words_request.signed.into();
+        validate(&words_request)?;
+        let constraints = Constraints::from_request(&words_request);
+        if words_request.passphrase {
+            let mut w_span =
+                global::tracer("words").start_with_context("generating passphrase", cx);
+            let dictionaries = self.dictionaries.current();
+            let generated = generate_passphrase(
+                words_request.count,
+                dictionaries.passphrase_words(),
+                &constraints,
+            );
+            w_span.end();
+            let (words, entropy_bits) = generated?;
+
+            let capitalization = Capitalization::from_i32(words_request.capitalization)
+                .unwrap_or(Capitalization::Lower);
+            let words = words
+                .iter()
+                .map(|word| capitalize(word, capitalization))
+                .collect::<Vec<String>>();
+            let separator = words_request
+                .separator
+                .as_deref()
+                .unwrap_or(DEFAULT_SEPARATOR);
+            return Ok(Response::new(WordsResponse {
+                passphrase: Some(words.join(separator)),
+                words,
+                entropy_bits: Some(entropy_bits),
+                ..Default::default()
+            }));
+        }
+        let dictionary = find_dictionary(&self.dictionaries.current(), &words_request)?;
+        let pattern = Pattern::from_request(&words_request)?;
+        let sign = words_request.signed;
+
+        let seed = words_request
+            .seed
+            .unwrap_or_else(|| rand::thread_rng().gen_range(0..SEED_LIMIT));
 
         let mut w_span = global::tracer("words").start_with_context("generating words", cx.clone());
-        let words = generate_words(count);
+        let generated = generate_words(&pattern, seed, &dictionary, &constraints);
         w_span.end();
+        let words = generated?;
 
         match sign {
             false => {
                 let reply = WordsResponse {
-                    words: words,
+                    words,
+                    seed: Some(seed),
+                    locale: Some(dictionary.locale().to_string()),
                     ..Default::default()
                 };
                 return Ok(Response::new(reply));
@@ -117,7 +891,12 @@ impl PickWords for MyPickWords {
                     global::tracer("words").start_with_context("requesting signature", cx);
 
                 let v = &words;
-                let mut req = tonic::Request::new(SignRequest { words: v.to_vec() });
+                let mut req = tonic::Request::new(SignRequest {
+                    words: v.to_vec(),
+                    format: words_request.format,
+                    ttl_seconds: words_request.ttl_seconds,
+                    timestamp_token: words_request.timestamp_token,
+                });
 
                 let grpc_cx =
                     &Context::new().with_remote_span_context(s_span.span_context().clone());
@@ -131,13 +910,16 @@ impl PickWords for MyPickWords {
                 match response {
                     Ok(response) => {
                         s_span.end();
-                        return Ok(response);
+                        let mut reply = response.into_inner();
+                        reply.seed = Some(seed);
+                        reply.locale = Some(dictionary.locale().to_string());
+                        return Ok(Response::new(reply));
                     }
                     Err(e) => {
                         error!("Failed to call SignWords service: {}", e);
                         s_span.record_exception(&e);
                         s_span.end();
-                        return Err(Status::unknown(format!("error invoking signing service")));
+                        return Err(signing_error(&e));
                     }
                 };
             }
@@ -168,6 +950,30 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         }
     };
 
+    // Setup dictionaries
+    let sources = Sources {
+        adjectives_file: args.adjectives_file,
+        nouns_file: args.nouns_file,
+        verbs_file: args.verbs_file,
+        dictionary_dir: args.dictionary_dir,
+        passphrase_file: args.passphrase_file,
+        blocklist_file: args.blocklist_file,
+    };
+    let dictionaries = match Dictionaries::load(&sources) {
+        Ok(dictionaries) => dictionaries,
+        Err(e) => {
+            error!("Failed to load dictionaries: {}", e);
+            process::exit(1);
+        }
+    };
+    dictionary::log_dictionaries(&dictionaries);
+    let dictionaries = Arc::new(SharedDictionaries::new(dictionaries));
+    tokio::spawn(dictionary::watch(
+        sources,
+        dictionaries.clone(),
+        Duration::from_secs(args.dictionary_poll_interval),
+    ));
+
     let channel = Channel::from_shared(args.sign_svc_addr.clone())
         .unwrap()
         .timeout(Duration::from_millis(500))
@@ -175,6 +981,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .await?;
     let pw = MyPickWords {
         sign_words_channel: channel,
+        dictionaries,
     };
     let addr = format!("0.0.0.0:{}", args.port).parse()?;
 
@@ -207,35 +1014,236 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 mod tests {
 
     use super::*;
+    use names::{ADJECTIVES, NOUNS};
+
+    #[test]
+    fn validate_passphrase() {
+        let ok = WordsRequest {
+            passphrase: true,
+            accept_locales: vec![String::from("fr")],
+            ..Default::default()
+        };
+        assert!(validate(&ok).is_ok());
+
+        let seeded = WordsRequest {
+            passphrase: true,
+            seed: Some(42),
+            signed: true,
+            ..Default::default()
+        };
+        let e = validate(&seeded).unwrap_err();
+        assert_eq!(e.code(), Code::InvalidArgument);
+        assert!(details::error_details(&e).contains(&bad_request(&[
+            ("seed", "can't be given for a passphrase"),
+            ("signed", "can't be given for a passphrase")
+        ])));
+
+        let lengths = WordsRequest {
+            min_length: Some(8),
+            max_length: Some(4),
+            ..Default::default()
+        };
+        let e = validate(&lengths).unwrap_err();
+        assert_eq!(e.code(), Code::InvalidArgument);
+        assert!(details::error_details(&e).contains(&bad_request(&[(
+            "min_length",
+            "must be at most max_length"
+        )])));
+
+        let capitalization = WordsRequest {
+            capitalization: 7,
+            ..Default::default()
+        };
+        assert_eq!(
+            validate(&capitalization).unwrap_err().code(),
+            Code::InvalidArgument
+        );
+    }
+
+    #[test]
+    fn generate_passphrase_entropy() {
+        let words = (0..1024).map(|i| i.to_string()).collect::<Vec<String>>();
+        let (passphrase, entropy_bits) =
+            generate_passphrase(6, &words, &Constraints::default()).unwrap();
+        assert_eq!(passphrase.len(), 6);
+        assert!(passphrase.iter().all(|w| words.contains(w)));
+        assert!((entropy_bits - 60.0).abs() < 1e-9);
+
+        let (passphrase, entropy_bits) =
+            generate_passphrase(0, &words, &Constraints::default()).unwrap();
+        assert!(passphrase.is_empty());
+        assert_eq!(entropy_bits, 0.0);
+    }
+
+    #[test]
+    fn generate_passphrase_with_constraints() {
+        let words = (0..1024).map(|i| i.to_string()).collect::<Vec<String>>();
+        let constraints = Constraints {
+            unique: true,
+            min_length: Some(3),
+            max_length: Some(3),
+            ..Default::default()
+        };
+        let (passphrase, entropy_bits) = generate_passphrase(900, &words, &constraints).unwrap();
+        assert!(passphrase.iter().all(|w| w.len() == 3));
+        let mut distinct = passphrase.clone();
+        distinct.sort();
+        distinct.dedup();
+        assert_eq!(distinct.len(), 900);
+        let expected: f64 = (0..900).map(|i| ((900 - i) as f64).log2()).sum();
+        assert!((entropy_bits - expected).abs() < 1e-9);
+
+        let e = generate_passphrase(901, &words, &constraints).unwrap_err();
+        assert_eq!(e.code(), Code::InvalidArgument);
+        assert!(details::error_details(&e).contains(&error_info(
+            "NOT_ENOUGH_WORDS",
+            ERROR_DOMAIN,
+            &[("available", "900")]
+        )));
+    }
+
+    #[test]
+    fn capitalize_passphrase_words() {
+        assert_eq!(capitalize("Éclair", Capitalization::Lower), "éclair");
+        assert_eq!(capitalize("éclair", Capitalization::Upper), "ÉCLAIR");
+        assert_eq!(capitalize("éclair", Capitalization::Title), "Éclair");
+        assert_eq!(capitalize("", Capitalization::Title), "");
+    }
+
+    #[test]
+    fn find_named_dictionary() {
+        let dictionaries = Dictionaries::load(&Sources::default()).unwrap();
+        assert!(find_dictionary(&dictionaries, &WordsRequest::default()).is_ok());
+
+        let unknown = WordsRequest {
+            dictionary: String::from("klingon"),
+            ..Default::default()
+        };
+        let e = find_dictionary(&dictionaries, &unknown).unwrap_err();
+        assert_eq!(e.code(), Code::InvalidArgument);
+        assert!(details::error_details(&e)
+            .contains(&bad_request(&[("dictionary", "must be one of default")])));
+    }
+
+    #[test]
+    #[allow(clippy::result_large_err)]
+    fn find_dictionary_locale() {
+        let dictionaries = Dictionaries::load(&Sources::default()).unwrap();
+        let locale = |locale: &str, accept_locales: &[&str]| {
+            let request = WordsRequest {
+                locale: locale.to_string(),
+                accept_locales: accept_locales.iter().map(|l| l.to_string()).collect(),
+                ..Default::default()
+            };
+            find_dictionary(&dictionaries, &request).map(|d| d.locale().to_string())
+        };
+        assert_eq!(locale("", &[]).unwrap(), "en");
+        assert_eq!(locale("fr-CA", &[]).unwrap(), "fr");
+        assert_eq!(locale("", &["ja", "de-AT", "fr"]).unwrap(), "de");
+        assert_eq!(locale("", &["ja"]).unwrap(), "en");
+        assert_eq!(locale("es", &["de"]).unwrap(), "es");
+
+        let e = locale("ja", &[]).unwrap_err();
+        assert_eq!(e.code(), Code::InvalidArgument);
+        assert!(details::error_details(&e)
+            .contains(&bad_request(&[("locale", "must be one of de, en, es, fr")])));
+    }
+
+    #[test]
+    fn signing_error_when_unreachable() {
+        let e = signing_error(&Status::unknown("transport error"));
+        assert_eq!(e.code(), Code::Unavailable);
+        let d = details::error_details(&e);
+        assert!(d.contains(&error_info("SIGNER_UNAVAILABLE", ERROR_DOMAIN, &[])));
+        assert!(d.contains(&retry_after(SIGNER_RETRY_DELAY)));
+    }
+
+    #[test]
+    fn signing_error_passes_through_details() {
+        let signer = details::with_details(
+            Code::InvalidArgument,
+            "empty word",
+            vec![bad_request(&[("words[0]", "must not be empty")])],
+        );
+        let e = signing_error(&signer);
+        assert_eq!(e.code(), Code::InvalidArgument);
+        assert_eq!(e.message(), "signing service: empty word");
+        assert_eq!(
+            details::error_details(&e),
+            vec![bad_request(&[("words[0]", "must not be empty")])]
+        );
+
+        let signer = details::with_details(
+            Code::Unavailable,
+            "signing backend unavailable",
+            vec![retry_after(Duration::from_secs(5))],
+        );
+        let e = signing_error(&signer);
+        assert_eq!(e.code(), Code::Unavailable);
+        assert_eq!(
+            details::error_details(&e),
+            vec![retry_after(Duration::from_secs(5))]
+        );
+
+        let e = signing_error(&Status::internal("oops"));
+        assert_eq!(
+            details::error_details(&e),
+            vec![error_info("SIGNING_FAILED", ERROR_DOMAIN, &[])]
+        );
+    }
 
     #[test]
     fn generate_words_with_one() {
-        let a = generate_words(1);
+        let a = generate_words(
+            &Pattern::from_count(1),
+            7,
+            &Dictionary::builtin(),
+            &Constraints::default(),
+        )
+        .unwrap();
         assert_eq!(a.len(), 1);
-        assert!(a[0].len() > 0);
+        assert!(NOUNS.contains(&a[0].as_str()));
     }
 
     #[test]
     fn generate_words_with_two() {
-        let a = generate_words(2);
+        let a = generate_words(
+            &Pattern::from_count(2),
+            7,
+            &Dictionary::builtin(),
+            &Constraints::default(),
+        )
+        .unwrap();
         assert_eq!(a.len(), 2);
-        assert!(a[0].len() > 0);
-        assert!(a[1].len() > 0);
+        assert!(ADJECTIVES.contains(&a[0].as_str()));
+        assert!(NOUNS.contains(&a[1].as_str()));
     }
 
     #[test]
     fn generate_words_with_zero() {
-        let a = generate_words(0);
+        let a = generate_words(
+            &Pattern::from_count(0),
+            7,
+            &Dictionary::builtin(),
+            &Constraints::default(),
+        )
+        .unwrap();
         assert_eq!(a.len(), 0);
     }
 
     #[test]
     fn generate_words_with_three() {
-        let a = generate_words(3);
+        let a = generate_words(
+            &Pattern::from_count(3),
+            7,
+            &Dictionary::builtin(),
+            &Constraints::default(),
+        )
+        .unwrap();
         assert_eq!(a.len(), 3);
         assert_eq!(
             a.into_iter()
-                .filter(|w| w.len() > 0)
+                .filter(|w| !w.is_empty())
                 .collect::<Vec<String>>()
                 .len(),
             3
@@ -244,11 +1252,17 @@ mod tests {
 
     #[test]
     fn generate_words_with_even() {
-        let a = generate_words(42);
+        let a = generate_words(
+            &Pattern::from_count(42),
+            7,
+            &Dictionary::builtin(),
+            &Constraints::default(),
+        )
+        .unwrap();
         assert_eq!(a.len(), 42);
         assert_eq!(
             a.into_iter()
-                .filter(|w| w.len() > 0)
+                .filter(|w| !w.is_empty())
                 .collect::<Vec<String>>()
                 .len(),
             42
@@ -257,14 +1271,433 @@ mod tests {
 
     #[test]
     fn generate_words_with_odd() {
-        let a = generate_words(93);
-        assert_eq!(a.len(), 93);
+        // Some adjectives contain hyphens, which must not split them
+        for seed in 0..20 {
+            let a = generate_words(
+                &Pattern::from_count(93),
+                seed,
+                &Dictionary::builtin(),
+                &Constraints::default(),
+            )
+            .unwrap();
+            assert_eq!(a.len(), 93);
+            assert_eq!(
+                a.iter()
+                    .filter(|w| !w.is_empty())
+                    .collect::<Vec<&String>>()
+                    .len(),
+                93
+            );
+            assert!(a[..92].iter().all(|w| ADJECTIVES.contains(&w.as_str())));
+        }
+    }
+
+    #[test]
+    fn generate_words_is_seeded() {
         assert_eq!(
-            a.into_iter()
-                .filter(|w| w.len() > 0)
-                .collect::<Vec<String>>()
-                .len(),
-            93
+            generate_words(
+                &Pattern::from_count(5),
+                42,
+                &Dictionary::builtin(),
+                &Constraints::default()
+            )
+            .unwrap(),
+            generate_words(
+                &Pattern::from_count(5),
+                42,
+                &Dictionary::builtin(),
+                &Constraints::default()
+            )
+            .unwrap()
+        );
+        assert_ne!(
+            generate_words(
+                &Pattern::from_count(5),
+                42,
+                &Dictionary::builtin(),
+                &Constraints::default()
+            )
+            .unwrap(),
+            generate_words(
+                &Pattern::from_count(5),
+                43,
+                &Dictionary::builtin(),
+                &Constraints::default()
+            )
+            .unwrap()
+        );
+        // Fixed for a seed, so seeds handed out stay valid across releases
+        assert_eq!(
+            generate_words(
+                &Pattern::from_count(3),
+                42,
+                &Dictionary::builtin(),
+                &Constraints::default()
+            )
+            .unwrap(),
+            ["quarrelsome", "colorful", "wall"]
+        );
+    }
+
+    #[test]
+    fn generate_words_from_dictionary() {
+        let dir = tempfile::tempdir().unwrap();
+        let (adjectives, nouns) = (dir.path().join("adjectives"), dir.path().join("nouns"));
+        std::fs::write(&adjectives, "sour\n").unwrap();
+        std::fs::write(&nouns, "pickle\n").unwrap();
+        let dictionaries = Dictionaries::load(&Sources {
+            adjectives_file: Some(adjectives),
+            nouns_file: Some(nouns),
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(
+            generate_words(
+                &Pattern::from_count(3),
+                42,
+                &dictionaries.get("", "en").unwrap(),
+                &Constraints::default()
+            )
+            .unwrap(),
+            ["sour", "sour", "pickle"]
+        );
+
+        let dictionaries = Dictionaries::load(&Sources::default()).unwrap();
+        let spanish = generate_words(
+            &Pattern::from_count(3),
+            42,
+            &dictionaries.get("", "es").unwrap(),
+            &Constraints::default(),
+        )
+        .unwrap();
+        assert_eq!(spanish.len(), 3);
+        assert_eq!(
+            generate_words(
+                &Pattern::from_count(3),
+                42,
+                &dictionaries.get("", "es").unwrap(),
+                &Constraints::default()
+            )
+            .unwrap(),
+            spanish
+        );
+    }
+
+    #[test]
+    fn generate_words_with_constraints() {
+        let constraints = Constraints {
+            unique: true,
+            min_length: Some(4),
+            max_length: Some(6),
+            ..Default::default()
+        };
+        for seed in 0..20 {
+            let a = generate_words(
+                &Pattern::from_count(40),
+                seed,
+                &Dictionary::builtin(),
+                &constraints,
+            )
+            .unwrap();
+            assert_eq!(a.len(), 40);
+            assert!(a.iter().all(|w| (4..=6).contains(&w.chars().count())));
+            let mut distinct = a.clone();
+            distinct.sort();
+            distinct.dedup();
+            assert_eq!(distinct.len(), 40);
+        }
+
+        // No constraints leaves seeded words as they were
+        assert_eq!(
+            generate_words(
+                &Pattern::from_count(3),
+                42,
+                &Dictionary::builtin(),
+                &Constraints::default()
+            )
+            .unwrap(),
+            ["quarrelsome", "colorful", "wall"]
+        );
+
+        let dir = tempfile::tempdir().unwrap();
+        let (adjectives, nouns) = (dir.path().join("adjectives"), dir.path().join("nouns"));
+        std::fs::write(&adjectives, "sour\nsweet\n").unwrap();
+        std::fs::write(&nouns, "pickle\n").unwrap();
+        let dictionaries = Dictionaries::load(&Sources {
+            adjectives_file: Some(adjectives),
+            nouns_file: Some(nouns),
+            ..Default::default()
+        })
+        .unwrap();
+        let dictionary = dictionaries.get("", "en").unwrap();
+        let unique = Constraints {
+            unique: true,
+            ..Default::default()
+        };
+        let mut a = generate_words(&Pattern::from_count(3), 42, &dictionary, &unique).unwrap();
+        a[..2].sort();
+        assert_eq!(a, ["sour", "sweet", "pickle"]);
+
+        let e = generate_words(&Pattern::from_count(4), 42, &dictionary, &unique).unwrap_err();
+        assert_eq!(e.code(), Code::InvalidArgument);
+        assert!(details::error_details(&e).contains(&bad_request(&[(
+            "count",
+            "is more than the words there are to pick from"
+        )])));
+
+        let short = Constraints {
+            max_length: Some(4),
+            ..Default::default()
+        };
+        let e = generate_words(&Pattern::from_count(2), 42, &dictionary, &short).unwrap_err();
+        assert_eq!(e.code(), Code::InvalidArgument);
+    }
+
+    #[test]
+    fn parse_pattern() {
+        assert_eq!(
+            Pattern::parse("adj-adj-noun-verb-num").unwrap().parts,
+            [
+                Part::Adjective,
+                Part::Adjective,
+                Part::Noun,
+                Part::Verb,
+                Part::Number
+            ]
+        );
+        assert_eq!(Pattern::parse("adj--noun").unwrap_err(), "");
+        assert_eq!(Pattern::parse("adj-Noun").unwrap_err(), "Noun");
+        assert_eq!(Pattern::from_count(0).parts, []);
+        assert_eq!(Pattern::from_count(2).parts, [Part::Adjective, Part::Noun]);
+    }
+
+    #[test]
+    fn validate_pattern() {
+        let request = |pattern: &str, count: u32| WordsRequest {
+            pattern: pattern.to_string(),
+            count,
+            ..Default::default()
+        };
+        assert!(validate(&request("noun-verb", 0)).is_ok());
+        assert!(validate(&request("noun-verb", 2)).is_ok());
+
+        let e = validate(&request("adj-pickle", 0)).unwrap_err();
+        assert_eq!(e.code(), Code::InvalidArgument);
+        assert!(details::error_details(&e).contains(&error_info(
+            "INVALID_PATTERN",
+            ERROR_DOMAIN,
+            &[("part", "pickle")]
+        )));
+
+        let e = validate(&request("noun-verb", 3)).unwrap_err();
+        assert!(details::error_details(&e).contains(&bad_request(&[(
+            "count",
+            "must be 0 or the pattern's 2 parts"
+        )])));
+
+        let passphrase = WordsRequest {
+            passphrase: true,
+            alliterate: true,
+            rhyme: true,
+            ..request("adj-noun", 0)
+        };
+        let e = validate(&passphrase).unwrap_err();
+        assert!(details::error_details(&e).contains(&bad_request(&[
+            ("pattern", "can't be given for a passphrase"),
+            ("alliterate", "can't be given for a passphrase"),
+            ("rhyme", "can't be given for a passphrase")
+        ])));
+    }
+
+    #[test]
+    fn generate_words_from_pattern() {
+        let dictionaries = Dictionaries::load(&Sources::default()).unwrap();
+        let english = dictionaries.get("", "en").unwrap();
+        let pattern = Pattern::parse("adj-adj-noun-verb-num").unwrap();
+        for seed in 0..20 {
+            let a = generate_words(&pattern, seed, &english, &Constraints::default()).unwrap();
+            assert_eq!(a.len(), 5);
+            assert!(a[..2].iter().all(|w| ADJECTIVES.contains(&w.as_str())));
+            assert!(NOUNS.contains(&a[2].as_str()));
+            assert!(english.verbs().contains(&a[3]));
+            assert!(a[4].parse::<u32>().unwrap() < MAX_NUMBER);
+        }
+
+        // Adjectives agree with the next noun, or the one before, in the
+        // pattern's order rather than the locale's
+        let french = dictionaries.get("", "fr").unwrap();
+        let pattern = Pattern::parse("adj-noun-verb-adj").unwrap();
+        for seed in 0..20 {
+            let a = generate_words(&pattern, seed, &french, &Constraints::default()).unwrap();
+            let noun = french.nouns().iter().find(|n| n.word() == a[1]).unwrap();
+            for adjective in [&a[0], &a[3]].iter() {
+                assert!(french
+                    .adjectives()
+                    .iter()
+                    .any(|adj| adj.form(noun.gender()) == adjective.as_str()));
+            }
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let food = dir.path().join("food");
+        std::fs::create_dir(&food).unwrap();
+        std::fs::write(food.join("adjectives.txt"), "sour\n").unwrap();
+        std::fs::write(food.join("nouns.txt"), "pickle\n").unwrap();
+        let dictionaries = Dictionaries::load(&Sources {
+            dictionary_dir: Some(dir.path().to_path_buf()),
+            ..Default::default()
+        })
+        .unwrap();
+        let food = dictionaries.get_default("food").unwrap();
+        let e = generate_words(
+            &Pattern::parse("noun-verb").unwrap(),
+            42,
+            &food,
+            &Constraints::default(),
+        )
+        .unwrap_err();
+        assert_eq!(e.code(), Code::InvalidArgument);
+        assert!(details::error_details(&e).contains(&error_info(
+            "NO_VERBS",
+            ERROR_DOMAIN,
+            &[("locale", "en")]
+        )));
+    }
+
+    #[test]
+    fn generate_alliterative_words() {
+        let dictionaries = Dictionaries::load(&Sources::default()).unwrap();
+        let alliterate = Constraints {
+            alliterate: true,
+            ..Default::default()
+        };
+        let pattern = Pattern::parse("adj-noun-verb-num").unwrap();
+        for locale in ["en", "de", "es", "fr"].iter() {
+            let dictionary = dictionaries.get("", locale).unwrap();
+            for seed in 0..20 {
+                let a = generate_words(&pattern, seed, &dictionary, &alliterate).unwrap();
+                let first = initial(&a[0]);
+                assert!(a[..3].iter().all(|w| initial(w) == first), "{:?}", a);
+            }
+        }
+        let a = generate_words(
+            &Pattern::from_count(4),
+            42,
+            &dictionaries.get("", "en").unwrap(),
+            &alliterate,
+        )
+        .unwrap();
+        assert!(a.iter().all(|w| initial(w) == initial(&a[0])));
+
+        // No letter starts 50 distinct French verbs
+        let e = generate_words(
+            &Pattern::parse(&vec!["verb"; 50].join("-")).unwrap(),
+            42,
+            &dictionaries.get("", "fr").unwrap(),
+            &Constraints {
+                unique: true,
+                ..alliterate
+            },
+        )
+        .unwrap_err();
+        assert!(details::error_details(&e).contains(&bad_request(&[(
+            "alliterate",
+            "no letter starts enough words for the pattern"
+        )])));
+    }
+
+    #[test]
+    fn rhyme_by_ending() {
+        assert_eq!(ending("wall").as_deref(), Some("all"));
+        assert_eq!(ending("Colorful").as_deref(), Some("ul"));
+        assert_eq!(ending("pickle").as_deref(), Some("ickle"));
+        assert_eq!(ending("tickle"), ending("pickle"));
+        assert_eq!(ending("blue").as_deref(), Some("ue"));
+        assert_eq!(ending("grün").as_deref(), Some("ün"));
+        assert_eq!(ending("brr"), None);
+    }
+
+    #[test]
+    fn generate_rhyming_words() {
+        let dictionaries = Dictionaries::load(&Sources::default()).unwrap();
+        let rhyme = Constraints {
+            rhyme: true,
+            ..Default::default()
+        };
+        let english = dictionaries.get("", "en").unwrap();
+        let pattern = Pattern::parse("adj-noun-num").unwrap();
+        for seed in 0..20 {
+            let a = generate_words(&pattern, seed, &english, &rhyme).unwrap();
+            assert_eq!(ending(&a[0]), ending(&a[1]), "{:?}", a);
+        }
+        let a = generate_words(&Pattern::from_count(4), 42, &english, &rhyme).unwrap();
+        assert!(a.iter().all(|w| ending(w) == ending(&a[0])), "{:?}", a);
+        for locale in ["de", "es", "fr"].iter() {
+            let dictionary = dictionaries.get("", locale).unwrap();
+            let unique = Constraints {
+                unique: true,
+                ..rhyme
+            };
+            let a = generate_words(
+                &Pattern::parse("noun-noun").unwrap(),
+                42,
+                &dictionary,
+                &unique,
+            )
+            .unwrap();
+            assert_ne!(a[0], a[1]);
+            assert_eq!(ending(&a[0]), ending(&a[1]), "{:?}", a);
+        }
+
+        // No ending is shared by 30 distinct English verbs
+        let e = generate_words(
+            &Pattern::parse(&vec!["verb"; 30].join("-")).unwrap(),
+            42,
+            &english,
+            &Constraints {
+                unique: true,
+                ..rhyme
+            },
+        )
+        .unwrap_err();
+        assert_eq!(e.message(), "no ending is shared by enough words to rhyme");
+        assert!(details::error_details(&e).contains(&bad_request(&[(
+            "rhyme",
+            "no ending is shared by enough words for the pattern"
+        )])));
+
+        // Alliterating too, the words share both their first letter and ending
+        let both = Constraints {
+            alliterate: true,
+            ..rhyme
+        };
+        let a = generate_words(&Pattern::parse("noun-noun").unwrap(), 42, &english, &both).unwrap();
+        assert_eq!(initial(&a[0]), initial(&a[1]));
+        assert_eq!(ending(&a[0]), ending(&a[1]));
+
+        let e = generate_words(
+            &Pattern::parse(&vec!["verb"; 50].join("-")).unwrap(),
+            42,
+            &dictionaries.get("", "fr").unwrap(),
+            &Constraints {
+                unique: true,
+                ..both
+            },
+        )
+        .unwrap_err();
+        assert_eq!(
+            e.message(),
+            "no letter starts enough rhyming words to alliterate"
         );
+        assert!(details::error_details(&e).contains(&bad_request(&[
+            (
+                "alliterate",
+                "no letter starts enough words for the pattern"
+            ),
+            (
+                "rhyme",
+                "no ending is shared by enough words for the pattern"
+            )
+        ])));
     }
 }